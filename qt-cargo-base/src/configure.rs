@@ -1,10 +1,21 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 use itertools::Itertools;
 
-use crate::{features, util};
+use crate::{
+    error::QtBuildError,
+    features,
+    store::ContentStore,
+    target::{Endianness, TargetDataLayout},
+    timing::HeaderScanProfile,
+    util,
+    version::QtVersion,
+};
 
 // Qt configure implementation
 //
@@ -30,7 +41,7 @@ use crate::{features, util};
 // which will then write the Qt configuration files to disk. The confiuration is
 // independent of Qt source and and build location.
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default)]
 #[allow(dead_code)]
 pub struct QtConfiguration {
     qplatformdefs_path: Option<PathBuf>,
@@ -42,6 +53,207 @@ pub struct QtConfiguration {
     qtcore_features: Vec<(String, bool)>,
     qtcore_private_features: Vec<(String, bool)>,
     qtcore_defines: Vec<(String, String)>,
+
+    // QtTest features, set by set_testlib(true)
+    testlib_features: Vec<(String, bool)>,
+    testlib_private_features: Vec<(String, bool)>,
+
+    // Compile options, see compile_defines()
+    no_foreach: bool,
+
+    // Backends which share the "icu" feature, see update_icu_feature()
+    locale_backend: Option<LocaleBackend>,
+    timezone_backend: Option<TimezoneBackend>,
+
+    config_prelude: Option<ConfigPrelude>,
+    version_string: Option<String>,
+    library_prefix: Option<String>,
+}
+
+/// Content placed at the top of the generated qconfig.h, see
+/// QtConfiguration::set_config_prelude
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigPrelude {
+    /// Content which is written as is
+    Content(String),
+    /// A header which is included with #include "<path>"
+    Include(PathBuf),
+}
+
+/// Time zone backend for QTimeZone and QDateTime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimezoneBackend {
+    /// The system time zone database (zoneinfo), without ICU
+    System,
+    /// ICU
+    Icu,
+    /// No time zone support; QTimeZone is not available, and QDateTime
+    /// supports local time, UTC and fixed offsets from UTC only.
+    Disabled,
+}
+
+/// Backend for locale dependent text operations: collation (QCollator) and locale aware
+/// case conversion. QLocale number and date formatting uses the CLDR data built into
+/// QtCore with either backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LocaleBackend {
+    /// The built-in CLDR data, with collation using the C library (strcoll)
+    #[default]
+    Builtin,
+    /// ICU; requires the ICU libraries, see QtConfiguration::link_libraries
+    Icu,
+}
+
+/// Destination for qDebug(), qWarning() and categorized logging (QLoggingCategory) output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoggingBackend {
+    /// Standard error
+    Stderr,
+    /// The systemd journal; requires libsystemd
+    Journald,
+    /// syslog
+    Syslog,
+}
+
+/// Selects the classes which get "QFoo"-type class forwarding headers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClassHeaderFilter {
+    /// All classes found, including internal classes which are not exported
+    #[default]
+    AllClasses,
+    /// Classes declared with an export macro ("class Q_CORE_EXPORT QFoo") only,
+    /// which limits the class headers to the public API.
+    ExportedClasses,
+    /// All classes found, and the Q-prefixed types declared with the Qt type declaration
+    /// macros in TYPE_DECLARATION_MACROS, e.g. "QFooFlags" for
+    /// Q_DECLARE_FLAGS(QFooFlags, QFooFlag).
+    AllClassesAndTypeMacros,
+}
+
+/// Macros which declare a type named by their first argument, for
+/// ClassHeaderFilter::AllClassesAndTypeMacros
+pub const TYPE_DECLARATION_MACROS: &[&str] = &["Q_DECLARE_FLAGS"];
+
+/// The form of the "#include" statement in forwarding headers
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum IncludeStyle {
+    /// #include "../path/to/header.h", relative to the forwarding header
+    #[default]
+    Quoted,
+    /// #include <path/to/header.h>, relative to the given include path. The include path
+    /// must contain the target header, and must be added to the include paths of builds
+    /// which use the forwarding header.
+    AngleBrackets(PathBuf),
+    /// #include "<virtual_root>/path/to/header.h", with the header path relative to
+    /// source_root. The forwarding headers do not depend on the location of the source and
+    /// the output, and can be relocated (e.g. for remote builds) as long as virtual_root
+    /// resolves to the source root there, for example with an include path or a path
+    /// mapping. source_root must contain the target headers.
+    VirtualRoot {
+        source_root: PathBuf,
+        virtual_root: PathBuf,
+    },
+    /// #include "../path/to/header.h" as for Quoted, as long as the relative path has at
+    /// most max_parent_dirs leading ".." components. Headers further away (for example
+    /// with the source and output in unrelated locations), and headers without a relative
+    /// path (on another drive on Windows), use the fallback. This keeps the generated tree
+    /// relocatable together with a nearby source tree.
+    QuotedWithFallback {
+        max_parent_dirs: usize,
+        fallback: DistantHeaderFallback,
+    },
+}
+
+/// Placeholder for the Qt source path in relocatable forwarding headers, see
+/// IncludeStyle::qt_src_placeholder
+pub const QT_SRC_PLACEHOLDER: &str = "$QT_SRC";
+
+impl IncludeStyle {
+    /// Returns the VirtualRoot style with QT_SRC_PLACEHOLDER as the virtual root:
+    /// #include "$QT_SRC/qtbase/src/corelib/...". Use for a generated tree which is
+    /// distributed and used with the Qt source at a different location. Consumers replace
+    /// the placeholder with their Qt source path before use, with
+    /// substitute_qt_src_placeholder or with e.g. sed -i 's|\$QT_SRC|/path/to/qt|'.
+    /// Alternatively the placeholder can be left in place and resolved by the compiler,
+    /// with an include path which contains a "$QT_SRC" symlink to the Qt source.
+    pub fn qt_src_placeholder<P: AsRef<Path>>(qt_source_path: P) -> IncludeStyle {
+        IncludeStyle::VirtualRoot {
+            source_root: qt_source_path.as_ref().to_path_buf(),
+            virtual_root: PathBuf::from(QT_SRC_PLACEHOLDER),
+        }
+    }
+}
+
+/// Replaces QT_SRC_PLACEHOLDER with qt_source_path in the "#include" statements of the
+/// generated headers under generated_path, see IncludeStyle::qt_src_placeholder. Returns
+/// the number of headers changed. Symlinks (see store::ContentStore) are not followed:
+/// copy the tree out of the store before substituting.
+pub fn substitute_qt_src_placeholder<P, Q>(
+    generated_path: P,
+    qt_source_path: Q,
+) -> io::Result<usize>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let placeholder_include = format!("#include \"{}/", QT_SRC_PLACEHOLDER);
+    let qt_source_include = format!("#include \"{}/", qt_source_path.as_ref().display());
+    let mut changed = 0;
+    for entry in walkdir::WalkDir::new(generated_path) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let content = match fs::read_to_string(entry.path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+            Err(e) => return Err(e),
+        };
+        if content.contains(&placeholder_include) {
+            fs::write(
+                entry.path(),
+                content.replace(&placeholder_include, &qt_source_include),
+            )?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Include statement for headers which are too far from the forwarding header, see
+/// IncludeStyle::QuotedWithFallback
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DistantHeaderFallback {
+    /// Return QtBuildError::DistantHeader, with the forwarding header and target header paths
+    #[default]
+    Error,
+    /// #include "/absolute/path/to/header.h", with a "cargo:warning" from build scripts
+    /// since the generated tree is not portable
+    Absolute,
+    /// As IncludeStyle::VirtualRoot
+    VirtualRoot {
+        source_root: PathBuf,
+        virtual_root: PathBuf,
+    },
+}
+
+/// Qt license edition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QtLicense {
+    /// LGPL/GPL
+    OpenSource,
+    /// Qt Commercial license
+    Commercial,
+}
+
+impl QtLicense {
+    /// Returns the value for the QT_EDITION define
+    pub fn edition_define(&self) -> &'static str {
+        match self {
+            QtLicense::OpenSource => "QT_EDITION_OPENSOURCE",
+            QtLicense::Commercial => "QT_EDITION_COMMERCIAL",
+        }
+    }
 }
 
 impl QtConfiguration {
@@ -49,11 +261,670 @@ impl QtConfiguration {
     pub fn new() -> QtConfiguration {
         Default::default()
     }
+
+    /// Enables or disables the Qt property system ("properties" feature). Qt 6
+    /// does not gate QProperty and the bindable properties implementation on a
+    /// separate feature; they are available as long as the property system is.
+    pub fn set_bindable_properties(&mut self, enabled: bool) {
+        self.set_feature("properties", enabled);
+    }
+
+    /// Selects the time zone backend, by setting the "timezone" and "icu" features.
+    /// The "icu" feature is shared with the locale backend, and is enabled if either
+    /// backend is ICU.
+    pub fn set_timezone_backend(&mut self, backend: TimezoneBackend) {
+        self.set_feature("timezone", backend != TimezoneBackend::Disabled);
+        self.timezone_backend = Some(backend);
+        self.update_icu_feature();
+    }
+
+    /// Selects the locale backend, by setting the "icu" feature. The sources for the
+    /// backend are selected with sources::filter_sources and sources::locale_sources.
+    /// The "icu" feature is shared with the time zone backend, and is enabled if either
+    /// backend is ICU.
+    pub fn set_locale_backend(&mut self, backend: LocaleBackend) {
+        self.locale_backend = Some(backend);
+        self.update_icu_feature();
+    }
+
+    /// Returns the locale backend selected with set_locale_backend, or if not set the
+    /// backend selected by the "icu" feature
+    pub fn locale_backend(&self) -> LocaleBackend {
+        self.locale_backend
+            .unwrap_or_else(|| match self.feature("icu") {
+                Some(true) => LocaleBackend::Icu,
+                _ => LocaleBackend::Builtin,
+            })
+    }
+
+    // Sets the "icu" feature from the locale and time zone backends
+    fn update_icu_feature(&mut self) {
+        let icu = self.locale_backend == Some(LocaleBackend::Icu)
+            || self.timezone_backend == Some(TimezoneBackend::Icu);
+        self.set_feature("icu", icu);
+    }
+
+    /// Sets the Qt version, which is written as the QT_VERSION_* defines in qconfig.h and
+    /// to the QtCore/qtcoreversion.h version header. See version::detect_qt_version.
+    /// The version string keeps the value given with set_qt_version_string, if any.
+    pub fn set_qt_version(&mut self, version: QtVersion) {
+        let version_string = self
+            .version_string
+            .clone()
+            .unwrap_or_else(|| version.to_string());
+        for (key, value) in [
+            ("QT_VERSION_STR", format!("\"{}\"", version_string)),
+            ("QT_VERSION_MAJOR", version.major.to_string()),
+            ("QT_VERSION_MINOR", version.minor.to_string()),
+            ("QT_VERSION_PATCH", version.patch.to_string()),
+        ] {
+            set_define_value(&mut self.global_defines, key, &value);
+        }
+    }
+
+    /// Sets the version string (QT_VERSION_STR and QTCORE_VERSION_STR) independently of
+    /// the numeric version, for example "6.5.0-mycompany" for a patched Qt. The numeric
+    /// version defines (QT_VERSION_MAJOR etc.) are not changed.
+    pub fn set_qt_version_string(&mut self, version_string: &str) {
+        self.version_string = Some(version_string.to_string());
+        set_define_value(
+            &mut self.global_defines,
+            "QT_VERSION_STR",
+            &format!("\"{}\"", version_string),
+        );
+    }
+
+    /// Returns the Qt version, if set
+    pub fn qt_version(&self) -> Option<QtVersion> {
+        let component = |name: &str| {
+            self.global_defines
+                .iter()
+                .find(|(key, _)| key == name)
+                .and_then(|(_, value)| value.parse().ok())
+        };
+        Some(QtVersion::new(
+            component("QT_VERSION_MAJOR")?,
+            component("QT_VERSION_MINOR")?,
+            component("QT_VERSION_PATCH")?,
+        ))
+    }
+
+    /// Returns the Qt version string (the unquoted QT_VERSION_STR), if set
+    pub fn qt_version_string(&self) -> Option<String> {
+        self.global_defines
+            .iter()
+            .find(|(key, _)| key == "QT_VERSION_STR")
+            .map(|(_, value)| value.trim_matches('"').to_string())
+    }
+
+    /// Hides Qt APIs deprecated before the given version by setting the
+    /// QT_DISABLE_DEPRECATED_BEFORE define, e.g. to 0x060000 for 6.0.0.
+    pub fn set_disable_deprecated_before(&mut self, version: QtVersion) {
+        set_define_value(
+            &mut self.global_defines,
+            "QT_DISABLE_DEPRECATED_BEFORE",
+            &version.to_hex_string(),
+        );
+    }
+
+    /// Sets the license edition, which is recorded in qconfig.h as the QT_EDITION define.
+    /// The open source and commercial editions are built from the same sources, with
+    /// the same features. See detect_license.
+    pub fn set_license(&mut self, license: QtLicense) {
+        set_define_value(
+            &mut self.global_defines,
+            "QT_EDITION",
+            license.edition_define(),
+        );
+    }
+
+    /// Sets the QT_POINTER_SIZE and Q_BYTE_ORDER defines, which must match the target.
+    /// See TargetDataLayout::from_cargo_env.
+    pub fn set_target_data_layout(&mut self, layout: TargetDataLayout) {
+        set_define_value(
+            &mut self.global_defines,
+            "QT_POINTER_SIZE",
+            &layout.pointer_size.to_string(),
+        );
+        let byte_order = match layout.endianness {
+            Endianness::Little => "Q_LITTLE_ENDIAN",
+            Endianness::Big => "Q_BIG_ENDIAN",
+        };
+        set_define_value(&mut self.global_defines, "Q_BYTE_ORDER", byte_order);
+    }
+
+    /// Sets a global define in qconfig.h, replacing any existing value. Global defines
+    /// apply to the Qt build and to code using the Qt headers. Use an empty value for
+    /// a define without value.
+    ///
+    /// This can be used to match the environment's heap. Qt 6 has no allocator hook; it
+    /// allocates using operator new and malloc/realloc/free, which can be replaced at link
+    /// time. Defines which affect allocation failure handling:
+    ///
+    ///  - QT_NO_EXCEPTIONS: Q_CHECK_PTR and qBadAlloc() abort instead of throwing
+    ///    std::bad_alloc. Use when compiling with -fno-exceptions.
+    ///  - QT_NO_DEBUG: disables Q_CHECK_PTR, as for other debug checks.
+    pub fn set_global_define(&mut self, key: &str, value: &str) {
+        set_define_value(&mut self.global_defines, key, value);
+    }
+
+    /// Removes a global define from qconfig.h
+    pub fn remove_global_define(&mut self, key: &str) {
+        self.global_defines.retain(|(define, _)| define != key);
+    }
+
+    /// Returns the license edition, if set
+    pub fn license(&self) -> Option<QtLicense> {
+        self.global_defines
+            .iter()
+            .find(|(key, _)| key == "QT_EDITION")
+            .and_then(|(_, value)| match value.as_str() {
+                "QT_EDITION_OPENSOURCE" => Some(QtLicense::OpenSource),
+                "QT_EDITION_COMMERCIAL" => Some(QtLicense::Commercial),
+                _ => None,
+            })
+    }
+
+    /// Enables or disables the CBOR stream reader and writer (QCborStreamReader and
+    /// QCborStreamWriter), which are implemented using the bundled tinycbor. JSON
+    /// support does not depend on these: QJsonDocument and friends are built on
+    /// QCborValue, which is always available, but without the stream classes
+    /// QCborValue can't be serialized to or parsed from CBOR.
+    pub fn set_cbor_streams(&mut self, enabled: bool) {
+        self.set_feature("cborstreamreader", enabled);
+        self.set_feature("cborstreamwriter", enabled);
+    }
+
+    /// Sets the logging backend. Categorized logging (QLoggingCategory, including filtering
+    /// with QT_LOGGING_RULES) is always available, see sources::QTCORE_LOGGING_SOURCES; the
+    /// backend selects where the log output goes.
+    pub fn set_logging_backend(&mut self, backend: LoggingBackend) {
+        self.set_feature("journald", backend == LoggingBackend::Journald);
+        self.set_feature("syslog", backend == LoggingBackend::Syslog);
+        self.set_feature("slog2", false);
+    }
+
+    /// Enables or disables the glib event dispatcher. With glib disabled QtCore uses the
+    /// generic unix event dispatcher (QEventDispatcherUNIX), and does not depend on glib.
+    /// With glib enabled the glib-2.0 headers must be added to the include paths, for
+    /// example using pkg-config. The default configuration sets the "glib" feature but
+    /// also QT_NO_GLIB, which keeps Qt from using glib; this removes or restores
+    /// QT_NO_GLIB as well.
+    pub fn set_glib(&mut self, enabled: bool) {
+        self.set_feature("glib", enabled);
+        match enabled {
+            true => self.remove_define("QT_NO_GLIB"),
+            false => self.set_define("QT_NO_GLIB", "1"),
+        }
+    }
+
+    /// Enables or disables the network related QtCore features. QtCore itself does no host
+    /// lookups or network interface queries (QHostInfo and QNetworkInterface are part of
+    /// QtNetwork); the related features are "topleveldomain" (QUrl::topLevelDomain and
+    /// the public suffix list) and "network", which tells code built against the headers
+    /// whether QtNetwork is available. See also sources::QTCORE_FEATURE_SOURCES.
+    pub fn set_network_features(&mut self, enabled: bool) {
+        self.set_feature("network", enabled);
+        self.set_feature("topleveldomain", enabled);
+    }
+
+    /// Enables or disables the inter-process communication classes QSharedMemory
+    /// ("sharedmemory") and QSystemSemaphore ("systemsemaphore"). On Linux these use
+    /// POSIX shared memory and semaphores, which require librt with older C libraries.
+    pub fn set_ipc(&mut self, enabled: bool) {
+        self.set_feature("sharedmemory", enabled);
+        self.set_feature("systemsemaphore", enabled);
+    }
+
+    /// Enables or disables QProcess ("process") and QProcessEnvironment
+    /// ("processenvironment"). Disable these for sandboxed builds which must not start
+    /// other processes. On unix QProcess uses the bundled forkfd, see requires_forkfd().
+    pub fn set_process(&mut self, enabled: bool) {
+        self.set_feature("process", enabled);
+        self.set_feature("processenvironment", enabled);
+    }
+
+    /// Sets QT_NAMESPACE in qconfig.h, which puts all Qt symbols in the given C++
+    /// namespace. An empty namespace removes the define. Code using Qt must be compiled
+    /// with the same config headers. To link with another Qt in the same binary, prefix the
+    /// library names as well, see set_library_prefix.
+    pub fn set_namespace(&mut self, namespace: &str) {
+        match namespace.is_empty() {
+            true => self.remove_global_define("QT_NAMESPACE"),
+            false => self.set_global_define("QT_NAMESPACE", namespace),
+        }
+    }
+
+    /// Returns the Qt namespace (QT_NAMESPACE), if set
+    pub fn namespace(&self) -> Option<String> {
+        self.global_defines
+            .iter()
+            .find(|(key, _)| key == "QT_NAMESPACE")
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Sets a prefix for the library names, for example "vendored_" for
+    /// "libvendored_Qt6Core.a", so that the archives and "cargo:rustc-link-lib" names do
+    /// not clash with a system Qt. Use library_name() when compiling and linking.
+    pub fn set_library_prefix(&mut self, prefix: &str) {
+        self.library_prefix = Some(prefix.to_string()).filter(|prefix| !prefix.is_empty());
+    }
+
+    /// Returns the library name to use for the Qt library name (e.g. "Qt6Core"), with the
+    /// library prefix if set. Pass this to library::compile_library, which emits the
+    /// link directives under the same name.
+    pub fn library_name(&self, name: &str) -> String {
+        format!(
+            "{}{}",
+            self.library_prefix.as_deref().unwrap_or_default(),
+            name
+        )
+    }
+
+    /// Enables or disables the animation framework ("animation"): QAbstractAnimation,
+    /// QVariantAnimation, QPropertyAnimation and the animation groups. Server and tool
+    /// builds rarely need it. The animation framework requires "properties" and
+    /// "easingcurve"; QEasingCurve itself is kept, and can be disabled separately.
+    pub fn set_animation(&mut self, enabled: bool) {
+        self.set_feature("animation", enabled);
+    }
+
+    /// Configures the build for QtTest based tests: enables the "testlib" module feature,
+    /// the "private_tests" feature and QT_BUILD_INTERNAL (which exports the Q_AUTOTEST_EXPORT
+    /// internals that QtCore's own tests use), and writes the QtTest config headers
+    /// (QtTest/qttestlib-config.h) with the configuration. See sources::TESTLIB_SOURCES and
+    /// write_testlib_forwarding_headers for compiling QtTest. Disabling removes the QtTest
+    /// config headers and restores "private_tests" and QT_BUILD_INTERNAL to their values
+    /// in the default configuration (see features::global_private_features and
+    /// features::global_defines), which enables both.
+    pub fn set_testlib(&mut self, enabled: bool) {
+        self.set_feature("testlib", enabled);
+        let to_owned = |features: Vec<(&str, bool)>| {
+            features
+                .iter()
+                .map(|(feature, enabled)| (feature.to_string(), *enabled))
+                .collect()
+        };
+        if enabled {
+            self.set_feature("private_tests", true);
+            self.set_global_define("QT_BUILD_INTERNAL", "");
+            self.testlib_features = to_owned(features::qt_testlib_features());
+            self.testlib_private_features = to_owned(features::qt_testlib_private_features());
+        } else {
+            let private_tests = features::global_private_features()
+                .iter()
+                .any(|(feature, enabled)| *feature == "private_tests" && *enabled);
+            self.set_feature("private_tests", private_tests);
+            match features::global_defines()
+                .iter()
+                .find(|(define, _)| *define == "QT_BUILD_INTERNAL")
+            {
+                Some((_, value)) => self.set_global_define("QT_BUILD_INTERNAL", value),
+                None => self.remove_global_define("QT_BUILD_INTERNAL"),
+            }
+            self.testlib_features.clear();
+            self.testlib_private_features.clear();
+        }
+    }
+
+    /// Enables or disables D-Bus support, the features in features::DBUS_FEATURES.
+    /// Disabling turns off both "dbus" and "dbus_linked", so that link_libraries does not
+    /// return "dbus-1". Enabling turns on "dbus" only, with libdbus-1 loaded at run time;
+    /// use set_private_feature("dbus_linked", true) to link it instead.
+    pub fn set_dbus(&mut self, enabled: bool) {
+        self.set_feature("dbus", enabled);
+        if !enabled {
+            self.set_private_feature("dbus_linked", false);
+        }
+    }
+
+    /// Enables or disables QXmlStreamReader ("xmlstreamreader") and QXmlStreamWriter
+    /// ("xmlstreamwriter"), and the shared "xmlstream" feature. The stream classes are part
+    /// of QtCore and don't depend on the QtXml module ("xml", QDomDocument), which can be
+    /// disabled on its own for a build which only parses or writes XML streams.
+    pub fn set_xml_stream(&mut self, reader: bool, writer: bool) {
+        self.set_feature("xmlstream", reader || writer);
+        self.set_feature("xmlstreamreader", reader);
+        self.set_feature("xmlstreamwriter", writer);
+    }
+
+    /// Enables or disables threading ("thread"): QThread with a separate thread of
+    /// execution, the locking primitives and QThreadPool. QThread itself is available in
+    /// either case. "future" and "concurrent" require "thread" and must be disabled with
+    /// it, see set_concurrent() and validate_feature_dependencies().
+    pub fn set_thread(&mut self, enabled: bool) {
+        self.set_feature("thread", enabled);
+    }
+
+    /// Enables or disables QFuture, QPromise and QFutureWatcher ("future") and the
+    /// QtConcurrent map/filter/run machinery ("concurrent"), independently of threading.
+    /// Disable these for a build which uses QThread and QThreadPool only.
+    pub fn set_concurrent(&mut self, enabled: bool) {
+        self.set_feature("future", enabled);
+        self.set_feature("concurrent", enabled);
+    }
+
+    /// Checks that the required features of each enabled feature are enabled, see
+    /// features::feature_dependencies. Returns a description of each invalid
+    /// combination as the error.
+    pub fn validate_feature_dependencies(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        for (feature, required_features) in features::feature_dependencies() {
+            if self.feature(feature) != Some(true) {
+                continue;
+            }
+            for required_feature in required_features {
+                if self.feature(required_feature) == Some(false) {
+                    errors.push(format!(
+                        "feature \"{}\" requires \"{}\", which is disabled",
+                        feature, required_feature
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Sets content which is placed at the top of the generated qconfig.h, before the
+    /// generated defines. This can be used to add "#pragma once", or to include a header
+    /// which provides defaults for (or overrides the handling of) the generated defines.
+    pub fn set_config_prelude(&mut self, prelude: ConfigPrelude) {
+        self.config_prelude = Some(prelude);
+    }
+
+    /// Returns a key which identifies the configuration: a hash of the generated config
+    /// headers and the compile defines. Configurations with the same key produce the same
+    /// build output from the same Qt source.
+    pub fn cache_key(&self) -> u64 {
+        let headers: BTreeMap<PathBuf, String> =
+            generate_config_headers(self).into_iter().collect();
+        let mut content = String::new();
+        for (header_path, header_content) in headers {
+            content.push_str(&format!("{}\n{}\n", header_path.display(), header_content));
+        }
+        for (key, value) in self.compile_defines() {
+            content.push_str(&format!("-D{}={}\n", key, value.unwrap_or_default()));
+        }
+        util::content_hash(content.as_bytes())
+    }
+
+    /// Enables or disables the foreach and Q_FOREACH macros for code compiled with the
+    /// configuration. Disabling defines QT_NO_FOREACH, as the Qt bootstrap build does;
+    /// the macros are enabled by default. This is a compile define and not part of the
+    /// config headers, see compile_defines().
+    pub fn set_foreach(&mut self, enabled: bool) {
+        self.no_foreach = !enabled;
+    }
+
+    /// Returns true if the foreach macros are enabled, see set_foreach()
+    pub fn foreach(&self) -> bool {
+        !self.no_foreach
+    }
+
+    /// Returns the defines which must be passed to the compiler (in addition to the
+    /// config headers) for code compiled with the configuration, as (key, value) pairs
+    /// for cc::Build::define.
+    pub fn compile_defines(&self) -> Vec<(&'static str, Option<&'static str>)> {
+        let mut defines = Vec::new();
+        if self.no_foreach {
+            defines.push(("QT_NO_FOREACH", None));
+        }
+        defines
+    }
+
+    /// Enables or disables loading of shared libraries and plugins ("library"): QLibrary
+    /// and QPluginLoader. Dynamic loading requires libdl on linux, see link_libraries;
+    /// disable this for a fully static build which does not load plugins. QFactoryLoader
+    /// remains, and finds statically linked plugins only.
+    pub fn set_plugins(&mut self, enabled: bool) {
+        self.set_feature("library", enabled);
+    }
+
+    /// Enables or disables QRegularExpression ("regularexpression"), and with it the
+    /// PCRE2 dependency. With the feature enabled QtCore uses the bundled PCRE2 (see
+    /// requires_pcre2), or the system PCRE2 if "system_pcre2" is enabled (see
+    /// link_libraries).
+    pub fn set_regular_expression(&mut self, enabled: bool) {
+        self.set_feature("regularexpression", enabled);
+    }
+
+    /// Returns the system libraries the configuration requires, as names for
+    /// "cargo:rustc-link-lib" (e.g. "systemd" for libsystemd).
+    pub fn link_libraries(&self) -> Vec<&'static str> {
+        let mut libraries = Vec::new();
+        if self.feature("glib") == Some(true) && self.define("QT_NO_GLIB").is_none() {
+            libraries.push("glib-2.0");
+        }
+        if self.feature("journald") == Some(true) {
+            libraries.push("systemd");
+        }
+        if self.feature("dbus") == Some(true) && self.feature("dbus_linked") == Some(true) {
+            libraries.push("dbus-1");
+        }
+        if self.feature("icu") == Some(true) {
+            libraries.extend(["icui18n", "icuuc", "icudata"]);
+        }
+        if self.feature("regularexpression") == Some(true)
+            && self.feature("system_pcre2") == Some(true)
+        {
+            libraries.push("pcre2-16");
+        }
+        let is_linux = self
+            .mkspec()
+            .is_some_and(|mkspec| mkspec.starts_with("linux"));
+        if is_linux
+            && (self.feature("sharedmemory") == Some(true)
+                || self.feature("systemsemaphore") == Some(true))
+        {
+            libraries.push("rt");
+        }
+        let is_android = self
+            .mkspec()
+            .is_some_and(|mkspec| mkspec.starts_with("android"));
+        if (is_linux || is_android)
+            && self.feature("library") == Some(true)
+            && self.feature("dlopen") == Some(true)
+        {
+            libraries.push("dl");
+        }
+        if is_android {
+            libraries.extend(ANDROID_SYSTEM_LIBRARIES);
+        }
+        if self
+            .mkspec()
+            .is_some_and(|mkspec| mkspec.starts_with("win32"))
+        {
+            libraries.extend(WINDOWS_SYSTEM_LIBRARIES);
+        }
+        if self
+            .mkspec()
+            .is_some_and(|mkspec| mkspec.starts_with("macx"))
+        {
+            libraries.extend(MACOS_SYSTEM_LIBRARIES);
+        }
+        libraries
+    }
+
+    /// Returns true if the configuration requires the bundled forkfd library; add
+    /// sources::FORKFD_INCLUDE_PATH to the include paths if so.
+    pub fn requires_forkfd(&self) -> bool {
+        self.feature("process") == Some(true)
+    }
+
+    /// Returns true if the configuration requires the bundled PCRE2 library; compile it with
+    /// configure_pcre2 and PCRE2_DEFAULT_CODE_UNIT_WIDTH if so.
+    pub fn requires_pcre2(&self) -> bool {
+        self.feature("regularexpression") == Some(true)
+            && self.feature("system_pcre2") != Some(true)
+    }
+
+    /// Returns true if the configuration requires the bundled tinycbor library.
+    pub fn requires_tinycbor(&self) -> bool {
+        self.feature("cborstreamreader") == Some(true)
+            || self.feature("cborstreamwriter") == Some(true)
+    }
+
+    /// Enables or disables the named feature, for example "thread" or "QT_FEATURE_thread".
+    /// The feature is updated wherever it is present in the global and QtCore features;
+    /// unknown features are added as QtCore features. Note that dependent features are not
+    /// updated, see sources::QTCORE_FEATURE_SOURCES.
+    pub fn set_feature(&mut self, name: &str, enabled: bool) {
+        let name = feature_name(name);
+        let mut found = false;
+        for features in self.feature_lists_mut() {
+            if let Some(feature) = features.iter_mut().find(|(feature, _)| feature == name) {
+                feature.1 = enabled;
+                found = true;
+            }
+        }
+        if !found {
+            self.qtcore_features.push((name.to_string(), enabled));
+        }
+    }
+
+    /// Enables or disables the named private feature, like set_feature. The feature is
+    /// updated wherever it is present in the global and QtCore private features; unknown
+    /// features are added as QtCore private features (qtcore-config_p.h).
+    pub fn set_private_feature(&mut self, name: &str, enabled: bool) {
+        let name = feature_name(name);
+        let mut found = false;
+        for features in [
+            &mut self.global_private_features,
+            &mut self.qtcore_private_features,
+        ] {
+            if let Some(feature) = features.iter_mut().find(|(feature, _)| feature == name) {
+                feature.1 = enabled;
+                found = true;
+            }
+        }
+        if !found {
+            set_feature_value(&mut self.qtcore_private_features, name, enabled);
+        }
+    }
+
+    /// Sets a define, for example ("QT_NO_DEBUG", ""). An existing global or QtCore define
+    /// is overwritten; new defines are added to qtcore-config.h. See set_global_define
+    /// for adding defines to qconfig.h.
+    pub fn set_define(&mut self, key: &str, value: &str) {
+        let mut found = false;
+        for defines in [&mut self.global_defines, &mut self.qtcore_defines] {
+            if let Some(define) = defines.iter_mut().find(|(define, _)| define == key) {
+                define.1 = value.to_string();
+                found = true;
+            }
+        }
+        if !found {
+            set_define_value(&mut self.qtcore_defines, key, value);
+        }
+    }
+
+    /// Removes a global or QtCore define, see set_define
+    pub fn remove_define(&mut self, key: &str) {
+        self.global_defines.retain(|(define, _)| define != key);
+        self.qtcore_defines.retain(|(define, _)| define != key);
+    }
+
+    /// Returns the value of the named define, or None if the define is not set
+    pub fn define(&self, key: &str) -> Option<&str> {
+        self.global_defines
+            .iter()
+            .chain(self.qtcore_defines.iter())
+            .find(|(define, _)| define == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all configured features, global and QtCore, sorted by name
+    pub fn features(&self) -> BTreeMap<String, bool> {
+        [
+            &self.global_features,
+            &self.global_private_features,
+            &self.qtcore_features,
+            &self.qtcore_private_features,
+        ]
+        .iter()
+        .flat_map(|features| features.iter().cloned())
+        .collect()
+    }
+
+    /// Returns all configured defines, global and QtCore, sorted by name
+    pub fn defines(&self) -> BTreeMap<String, String> {
+        self.global_defines
+            .iter()
+            .chain(self.qtcore_defines.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Sets the mkspec (e.g. "linux-g++"), which selects the qplatformdefs.h the
+    /// configuration forwards to. See mkspec::Mkspec for the mkspec defines.
+    pub fn set_mkspec(&mut self, mkspec: &str) {
+        self.qplatformdefs_path = Some(qplatformdefs_path(mkspec));
+    }
+
+    /// Returns the mkspec name (e.g. "linux-clang"), if a qplatformdefs.h path is set
+    pub fn mkspec(&self) -> Option<String> {
+        self.qplatformdefs_path
+            .as_ref()
+            .and_then(|path| path.parent())
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    /// Returns the value of the named feature, for example "thread" or "QT_FEATURE_thread",
+    /// or None if the feature is not configured.
+    pub fn feature(&self, name: &str) -> Option<bool> {
+        let name = feature_name(name);
+        [
+            &self.global_features,
+            &self.global_private_features,
+            &self.qtcore_features,
+            &self.qtcore_private_features,
+        ]
+        .iter()
+        .flat_map(|features| features.iter())
+        .find(|(feature, _)| feature == name)
+        .map(|(_, enabled)| *enabled)
+    }
+
+    fn feature_lists_mut(&mut self) -> [&mut Vec<(String, bool)>; 4] {
+        [
+            &mut self.global_features,
+            &mut self.global_private_features,
+            &mut self.qtcore_features,
+            &mut self.qtcore_private_features,
+        ]
+    }
+}
+
+// Returns the feature name without the QT_FEATURE_ prefix, so that features can be
+// given either as "thread" or as "QT_FEATURE_thread".
+fn feature_name(name: &str) -> &str {
+    name.strip_prefix("QT_FEATURE_").unwrap_or(name)
+}
+
+// Sets the value of the define, adding the define if not already present.
+fn set_define_value(defines: &mut Vec<(String, String)>, key: &str, value: &str) {
+    match defines.iter_mut().find(|(define, _)| define == key) {
+        Some(define) => define.1 = value.to_string(),
+        None => defines.push((key.to_string(), value.to_string())),
+    }
+}
+
+// Sets the value of the named feature, adding the feature if not already present.
+fn set_feature_value(features: &mut Vec<(String, bool)>, name: &str, enabled: bool) {
+    match features.iter_mut().find(|(feature, _)| feature == name) {
+        Some(feature) => feature.1 = enabled,
+        None => features.push((name.to_string(), enabled)),
+    }
 }
 
 #[allow(dead_code)]
 pub fn set_default_configuration(qt_configuration: &mut QtConfiguration) {
-    qt_configuration.qplatformdefs_path = Some("qtbase/mkspecs/linux-clang/qplatformdefs.h".into()); // Hardcode linux-clang
+    qt_configuration.qplatformdefs_path = Some(qplatformdefs_path("linux-clang")); // Hardcode linux-clang
 
     qt_configuration.global_features = features::global_features()
         .iter()
@@ -82,75 +953,530 @@ pub fn set_default_configuration(qt_configuration: &mut QtConfiguration) {
         .collect();
 }
 
-#[allow(dead_code)]
+// The Windows system libraries QtCore links against
+const WINDOWS_SYSTEM_LIBRARIES: &[&str] = &[
+    "advapi32", "authz", "kernel32", "mpr", "netapi32", "ole32", "shell32", "user32", "userenv",
+    "uuid", "version", "winmm", "ws2_32",
+];
+
+/// Sets the Windows MSVC configuration: the default configuration with the win32-msvc
+/// mkspec, and the features and defines which differ on Windows, see
+/// features::windows_msvc_feature_overrides.
+pub fn set_windows_msvc_configuration(qt_configuration: &mut QtConfiguration) {
+    set_default_configuration(qt_configuration);
+    qt_configuration.set_mkspec("win32-msvc");
+    for (feature, enabled) in features::windows_msvc_feature_overrides() {
+        qt_configuration.set_feature(feature, enabled);
+    }
+    // GCC visibility and -fPIC, see the "reduce_exports" and "reduce_relocations" features
+    qt_configuration.remove_global_define("QT_VISIBILITY_AVAILABLE");
+    qt_configuration.remove_global_define("QT_REDUCE_RELOCATIONS");
+}
+
+// Frameworks QtCore links against on macOS, see QtConfiguration::link_libraries.
+// The "framework=" prefix is the "cargo:rustc-link-lib" kind.
+const MACOS_SYSTEM_LIBRARIES: &[&str] = &["framework=CoreFoundation", "framework=Foundation"];
+
+/// Sets the macOS configuration: the default configuration with the macx-clang mkspec,
+/// and the features which differ on Darwin, see features::macos_feature_overrides.
+pub fn set_macos_configuration(qt_configuration: &mut QtConfiguration) {
+    set_default_configuration(qt_configuration);
+    qt_configuration.set_mkspec("macx-clang");
+    for (feature, enabled) in features::macos_feature_overrides() {
+        qt_configuration.set_feature(feature, enabled);
+    }
+    // -fPIC with ELF, see the "reduce_relocations" feature
+    qt_configuration.remove_global_define("QT_REDUCE_RELOCATIONS");
+}
+
+// Libraries QtCore links against on Android, see QtConfiguration::link_libraries.
+// liblog provides __android_log_print for the qDebug() output.
+const ANDROID_SYSTEM_LIBRARIES: &[&str] = &["log"];
+
+/// Sets the Android configuration: the default configuration with the android-clang
+/// mkspec, and the features which differ with bionic, see
+/// features::android_feature_overrides.
+pub fn set_android_configuration(qt_configuration: &mut QtConfiguration) {
+    set_default_configuration(qt_configuration);
+    qt_configuration.set_mkspec("android-clang");
+    for (feature, enabled) in features::android_feature_overrides() {
+        qt_configuration.set_feature(feature, enabled);
+    }
+    // -Bsymbolic-functions, see the "reduce_relocations" feature
+    qt_configuration.remove_global_define("QT_REDUCE_RELOCATIONS");
+}
+
+/// Writes the Qt configuration headers (see generate_config_headers) to destination_path,
+/// and a qplatformdefs.h forwarding header if qt_source_path is given.
 pub fn write_configuration<P, Q>(
     qt_configuration: &QtConfiguration,
     destination_path: P,
     qt_source_path: Option<Q>,
-) where
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    write_configuration_with_style(
+        qt_configuration,
+        destination_path,
+        qt_source_path,
+        &IncludeStyle::Quoted,
+    )
+}
+
+/// Writes the configuration like write_configuration, with the "#include" statement of the
+/// qplatformdefs.h forwarding header in the given style. See IncludeStyle.
+pub fn write_configuration_with_style<P, Q>(
+    qt_configuration: &QtConfiguration,
+    destination_path: P,
+    qt_source_path: Option<Q>,
+    style: &IncludeStyle,
+) -> Result<(), QtBuildError>
+where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
     let qtcore_path = destination_path.as_ref().join("QtCore");
     let qtcore_private_path = qtcore_path.join("private");
-    std::fs::create_dir_all(&qtcore_private_path).expect("Unable to create Qt config directory");
-    std::fs::create_dir_all(&qtcore_private_path).expect("Unable to create Qt config directory");
+    std::fs::create_dir_all(&qtcore_private_path)
+        .map_err(QtBuildError::config_write(&qtcore_private_path))?;
 
     // Write qplatformdefs_p.h forwarding header, if we have Qt source to point it to.
     if let Some(path) = qt_source_path {
-        write_forwarding_header(
+        let qplatformdefs_path = qt_configuration
+            .qplatformdefs_path
+            .as_ref()
+            .ok_or_else(|| QtBuildError::InvalidArgument {
+                message: "the Qt configuration has no mkspec, see QtConfiguration::set_mkspec"
+                    .to_string(),
+            })?;
+        write_named_forwarding_header(
             &qtcore_path,
-            path.as_ref()
-                .join(qt_configuration.qplatformdefs_path.as_ref().unwrap()),
-        );
+            path.as_ref().join(qplatformdefs_path),
+            style,
+            &mut |include| include.to_string(),
+        )?;
     };
 
-    // Write Qt global public config and features to QtCore/qconfig.h.
-    write_config_header(
-        qtcore_path.join("qconfig.h"),
-        &qt_configuration.global_defines,
-        &qt_configuration.global_features,
-    );
-
-    // Write Qt global private config features to QtCore/private/qconfig_p.h
-    write_config_header(
-        qtcore_private_path.join("qconfig_p.h"),
-        &Vec::new(),
-        &qt_configuration.global_private_features,
-    );
-
-    write_config_header(
-        qtcore_path.join("qtcore-config.h"),
-        &qt_configuration.qtcore_defines,
-        &qt_configuration.qtcore_features,
-    );
-
-    write_config_header(
-        qtcore_private_path.join("qtcore-config_p.h"),
-        &Vec::new(),
-        &qt_configuration.qtcore_private_features,
-    );
+    for (header_path, content) in generate_config_headers(qt_configuration) {
+        let header_path = destination_path.as_ref().join(header_path);
+        if let Some(parent) = header_path.parent() {
+            std::fs::create_dir_all(parent).map_err(QtBuildError::config_write(parent))?;
+        }
+        fs::write(&header_path, content).map_err(QtBuildError::config_write(&header_path))?;
+    }
+    Ok(())
 }
 
-/// Creates a string containing #defines by concatenating (key, values) from the iteratable
-pub fn make_define_string<'a, F>(defines: F) -> String
+/// Writes the configuration like write_configuration, with the features in overrides
+/// enabled or disabled for this write only, as (feature, enabled) pairs. qt_configuration
+/// is not changed. Overrides are applied with QtConfiguration::set_feature, and so do not
+/// update dependent features.
+pub fn write_configuration_with_overrides<P, Q>(
+    qt_configuration: &QtConfiguration,
+    overrides: &[(String, bool)],
+    destination_path: P,
+    qt_source_path: Option<Q>,
+) -> Result<(), QtBuildError>
 where
-    F: IntoIterator<Item = &'a (String, String)>,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
 {
-    defines
+    let mut overridden_configuration = qt_configuration.clone();
+    for (feature, enabled) in overrides {
+        overridden_configuration.set_feature(feature, *enabled);
+    }
+    write_configuration(&overridden_configuration, destination_path, qt_source_path)
+}
+
+/// Define which marks the qconfig.h written by generate_config_headers, see
+/// util::check_generated_qconfig
+pub const QCONFIG_MARKER: &str = "QT_CARGO_GENERATED_QCONFIG";
+
+/// Returns the configuration headers for qt_configuration, as (path relative to the
+/// destination path, content) pairs, without writing anything. write_configuration
+/// writes these, in addition to the qplatformdefs.h forwarding header (which depends
+/// on the Qt source location).
+pub fn generate_config_headers(qt_configuration: &QtConfiguration) -> HashMap<PathBuf, String> {
+    let qtcore_path = Path::new("QtCore");
+    let qtcore_private_path = qtcore_path.join("private");
+    HashMap::from([
+        // Qt global public config and features
+        (
+            qtcore_path.join("qconfig.h"),
+            make_config_prelude(qt_configuration.config_prelude.as_ref())
+                + &format!("#define {} 1\n", QCONFIG_MARKER)
+                + &make_config_header(
+                    &qt_configuration.global_defines,
+                    &qt_configuration.global_features,
+                ),
+        ),
+        // Qt global private config features
+        (
+            qtcore_private_path.join("qconfig_p.h"),
+            make_config_header(&[], &qt_configuration.global_private_features),
+        ),
+        (
+            qtcore_path.join("qtcore-config.h"),
+            make_config_header(
+                &qt_configuration.qtcore_defines,
+                &qt_configuration.qtcore_features,
+            ),
+        ),
+        (
+            qtcore_private_path.join("qtcore-config_p.h"),
+            make_config_header(&[], &qt_configuration.qtcore_private_features),
+        ),
+    ])
+    .into_iter()
+    .chain(
+        [
+            (
+                Path::new("QtTest").join("qttestlib-config.h"),
+                make_config_header(&[], &qt_configuration.testlib_features),
+            ),
+            (
+                Path::new("QtTest/private").join("qttestlib-config_p.h"),
+                make_config_header(&[], &qt_configuration.testlib_private_features),
+            ),
+        ]
         .into_iter()
-        .map(|(key, value)| format!("#define {} {}\n", key, value))
-        .collect()
+        .filter(|_| !qt_configuration.testlib_features.is_empty()),
+    )
+    .chain(qt_configuration.qt_version().map(|version| {
+        let version_string = qt_configuration
+            .qt_version_string()
+            .unwrap_or_else(|| version.to_string());
+        (
+            qtcore_path.join("qtcoreversion.h"),
+            make_version_header_with_string(version, &version_string),
+        )
+    }))
+    .collect()
 }
 
-/// Create a string containing QT_FEATURE_foo defines from the given (define, enable) iterable
-pub fn make_feature_defines(features: &[(String, bool)]) -> String {
-    features
-        .iter()
-        .map(|(name, enable)| {
-            let value = match enable {
-                true => "1",
-                false => "-1",
+/// A config header generated by generate_config_headers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigHeaderKind {
+    /// QtCore/qconfig.h, the global public defines and features
+    QConfig,
+    /// QtCore/private/qconfig_p.h, the global private features
+    QConfigPrivate,
+    /// QtCore/qtcore-config.h, the QtCore public defines and features
+    QtCoreConfig,
+    /// QtCore/private/qtcore-config_p.h, the QtCore private features
+    QtCoreConfigPrivate,
+    /// QtCore/qtcoreversion.h, generated if the Qt version is set
+    QtCoreVersion,
+}
+
+impl ConfigHeaderKind {
+    /// Returns the header path, relative to the config headers directory
+    pub fn relative_path(&self) -> PathBuf {
+        PathBuf::from(match self {
+            ConfigHeaderKind::QConfig => "QtCore/qconfig.h",
+            ConfigHeaderKind::QConfigPrivate => "QtCore/private/qconfig_p.h",
+            ConfigHeaderKind::QtCoreConfig => "QtCore/qtcore-config.h",
+            ConfigHeaderKind::QtCoreConfigPrivate => "QtCore/private/qtcore-config_p.h",
+            ConfigHeaderKind::QtCoreVersion => "QtCore/qtcoreversion.h",
+        })
+    }
+}
+
+/// Writes the content of the given config header to out, for example std::io::stdout()
+/// for inspecting the generated configuration. Returns an error of kind NotFound for
+/// QtCoreVersion if the configuration has no Qt version.
+pub fn print_config_header<W: io::Write>(
+    qt_configuration: &QtConfiguration,
+    which: ConfigHeaderKind,
+    out: &mut W,
+) -> io::Result<()> {
+    let headers = generate_config_headers(qt_configuration);
+    let content = headers.get(&which.relative_path()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{:?} is not generated for the configuration", which),
+        )
+    })?;
+    out.write_all(content.as_bytes())
+}
+
+/// A difference between the configuration and the config headers on disk, see
+/// verify_config_headers. Header paths are relative to the config headers directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Discrepancy {
+    MissingHeader {
+        header: PathBuf,
+    },
+    MissingDefine {
+        header: PathBuf,
+        key: String,
+        expected: String,
+    },
+    WrongValue {
+        header: PathBuf,
+        key: String,
+        expected: String,
+        actual: String,
+    },
+    UnexpectedDefine {
+        header: PathBuf,
+        key: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::MissingHeader { header } => write!(f, "{:?}: missing", header),
+            Discrepancy::MissingDefine {
+                header,
+                key,
+                expected,
+            } => write!(f, "{:?}: missing #define {} {}", header, key, expected),
+            Discrepancy::WrongValue {
+                header,
+                key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{:?}: {} is {:?}, expected {:?}",
+                header, key, actual, expected
+            ),
+            Discrepancy::UnexpectedDefine {
+                header,
+                key,
+                actual,
+            } => write!(f, "{:?}: unexpected #define {} {}", header, key, actual),
+        }
+    }
+}
+
+// Returns the "#define KEY VALUE" lines of a header as key -> value
+fn parse_defines(content: &str) -> BTreeMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#define "))
+        .map(
+            |define| match define.trim().split_once(char::is_whitespace) {
+                Some((key, value)) => (key.to_string(), value.trim().to_string()),
+                None => (define.trim().to_string(), String::new()),
+            },
+        )
+        .collect()
+}
+
+/// Verifies that the config headers in config_headers_path (as written by
+/// write_configuration) contain the features and defines of qt_configuration, and
+/// nothing else. Returns the differences found, for example for a stale or modified
+/// header.
+pub fn verify_config_headers(
+    qt_configuration: &QtConfiguration,
+    config_headers_path: &Path,
+) -> Result<(), Vec<Discrepancy>> {
+    let mut discrepancies = Vec::new();
+    let expected_headers: BTreeMap<PathBuf, String> = generate_config_headers(qt_configuration)
+        .into_iter()
+        .collect();
+    for (header, expected_content) in expected_headers {
+        let content = match fs::read_to_string(config_headers_path.join(&header)) {
+            Ok(content) => content,
+            Err(_) => {
+                discrepancies.push(Discrepancy::MissingHeader { header });
+                continue;
+            }
+        };
+        let mut actual = parse_defines(&content);
+        for (key, expected) in parse_defines(&expected_content) {
+            match actual.remove(&key) {
+                None => discrepancies.push(Discrepancy::MissingDefine {
+                    header: header.clone(),
+                    key,
+                    expected,
+                }),
+                Some(actual) if actual != expected => discrepancies.push(Discrepancy::WrongValue {
+                    header: header.clone(),
+                    key,
+                    expected,
+                    actual,
+                }),
+                Some(_) => {}
+            }
+        }
+        for (key, actual) in actual {
+            discrepancies.push(Discrepancy::UnexpectedDefine {
+                header: header.clone(),
+                key,
+                actual,
+            });
+        }
+    }
+    match discrepancies.is_empty() {
+        true => Ok(()),
+        false => Err(discrepancies),
+    }
+}
+
+fn make_config_prelude(prelude: Option<&ConfigPrelude>) -> String {
+    match prelude {
+        None => String::new(),
+        Some(ConfigPrelude::Content(content)) if content.ends_with('\n') => content.clone(),
+        Some(ConfigPrelude::Content(content)) => format!("{}\n", content),
+        Some(ConfigPrelude::Include(path)) => format!("#include \"{}\"\n", path.display()),
+    }
+}
+
+/// Creates the content of the QtCore/qtcoreversion.h version header, as generated
+/// by the Qt build
+pub fn make_version_header(version: QtVersion) -> String {
+    make_version_header_with_string(version, &version.to_string())
+}
+
+fn make_version_header_with_string(version: QtVersion, version_string: &str) -> String {
+    format!(
+        "#ifndef QT_QTCORE_VERSION_H\n\
+         #define QT_QTCORE_VERSION_H\n\
+         \n\
+         #define QTCORE_VERSION_STR \"{}\"\n\
+         \n\
+         #define QTCORE_VERSION {}\n\
+         \n\
+         #endif // QT_QTCORE_VERSION_H\n",
+        version_string,
+        version.to_hex_string()
+    )
+}
+
+/// Detects the license edition of the Qt source. Commercial source packages contain
+/// a license agreement file ("LICENSE.QT-LICENSE-AGREEMENT" or ".QT-LICENSE-AGREEMENT")
+/// in qtbase; anything else is treated as the open source edition.
+pub fn detect_license<P: AsRef<Path>>(qt_source_path: P) -> QtLicense {
+    let is_license_agreement = |file_name: &str| {
+        file_name.starts_with("LICENSE.QT-LICENSE-AGREEMENT")
+            || file_name.starts_with(".QT-LICENSE-AGREEMENT")
+    };
+    let has_license_agreement = fs::read_dir(qt_source_path.as_ref().join("qtbase"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .any(|entry| is_license_agreement(&entry.file_name().to_string_lossy()))
+        })
+        .unwrap_or(false);
+    match has_license_agreement {
+        true => QtLicense::Commercial,
+        false => QtLicense::OpenSource,
+    }
+}
+
+/// Writes an umbrella header per subsystem to destination_path, for example "io" which
+/// includes <QtCore/qfile.h>, <QtCore/qdir.h> etc. Subsystems are the first-level source
+/// directories in source_path (e.g. qtbase/src/corelib), and each umbrella includes the
+/// public headers (not "_p.h") found in its directory, using the given module name as the
+/// include prefix. Returns the paths of the written umbrella headers.
+pub fn write_subsystem_umbrella_headers<P, Q>(
+    source_path: P,
+    destination_path: Q,
+    module: &str,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let source_path = source_path.as_ref();
+    let destination_path = destination_path.as_ref();
+    if !source_path.is_dir() {
+        return Err(QtBuildError::MissingSource {
+            path: source_path.to_path_buf(),
+        });
+    }
+    std::fs::create_dir_all(destination_path)
+        .map_err(QtBuildError::config_write(destination_path))?;
+
+    let mut subsystems: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for header_path in
+        util::glob_files_excluding(source_path, OsStr::new("h"), util::DEFAULT_EXCLUDED_DIRS)
+    {
+        let file_name = header_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let subsystem = header_path
+            .strip_prefix(source_path)
+            .ok()
+            .filter(|relative_path| relative_path.components().count() > 1)
+            .and_then(|relative_path| relative_path.components().next())
+            .map(|component| component.as_os_str().to_string_lossy().to_string());
+        if let (Some(subsystem), false) = (subsystem, file_name.contains("_p.h")) {
+            subsystems.entry(subsystem).or_default().push(file_name);
+        }
+    }
+
+    subsystems
+        .into_iter()
+        .map(|(subsystem, mut headers)| {
+            headers.sort();
+            let content: String = headers
+                .iter()
+                .map(|header| format!("#include <{}/{}>\n", module, header))
+                .collect();
+            let umbrella_path = destination_path.join(subsystem);
+            fs::write(&umbrella_path, content)
+                .map_err(QtBuildError::config_write(&umbrella_path))?;
+            Ok(umbrella_path)
+        })
+        .collect()
+}
+
+/// Returns the path to qplatformdefs.h for the given mkspec (e.g. "linux-clang"),
+/// relative to the Qt source path.
+pub fn qplatformdefs_path(mkspec: &str) -> PathBuf {
+    Path::new("qtbase/mkspecs")
+        .join(mkspec)
+        .join("qplatformdefs.h")
+}
+
+/// Writes a qplatformdefs.h forwarding header to destination_path, which includes
+/// qplatformdefs.h for the given mkspec in the Qt source.
+pub fn write_qplatformdefs_forwarding_header<P, Q>(
+    destination_path: P,
+    qt_source_path: Q,
+    mkspec: &str,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    std::fs::create_dir_all(&destination_path)
+        .map_err(QtBuildError::config_write(destination_path.as_ref()))?;
+    write_forwarding_header(
+        destination_path,
+        qt_source_path.as_ref().join(qplatformdefs_path(mkspec)),
+    )
+}
+
+/// Creates a string containing #defines by concatenating (key, values) from the iteratable
+pub fn make_define_string<'a, F>(defines: F) -> String
+where
+    F: IntoIterator<Item = &'a (String, String)>,
+{
+    defines
+        .into_iter()
+        .map(|(key, value)| format!("#define {} {}\n", key, value))
+        .collect()
+}
+
+/// Create a string containing QT_FEATURE_foo defines from the given (define, enable) iterable
+pub fn make_feature_defines(features: &[(String, bool)]) -> String {
+    features
+        .iter()
+        .map(|(name, enable)| {
+            let value = match enable {
+                true => "1",
+                false => "-1",
             };
             format!("#define QT_FEATURE_{} {}\n", name, value)
         })
@@ -159,33 +1485,182 @@ pub fn make_feature_defines(features: &[(String, bool)]) -> String {
 
 /// Writes a forwarding header to forwarding_header_path which incliudes the header at
 /// target_header_path. target_header_path may be a relative path, and will be resolved against
-/// std:::env::current_dir if so.
-pub fn write_forwarding_header_2<P, V>(forwarding_header_path: P, target_header_path: V)
+/// std:::env::current_dir if so. See write_forwarding_header_with_style for "#include <...>"
+/// forwarding headers.
+pub fn write_forwarding_header_2<P, V>(
+    forwarding_header_path: P,
+    target_header_path: V,
+) -> Result<(), QtBuildError>
 where
     P: AsRef<Path>,
     V: AsRef<Path>,
 {
-    let forwarding_header_path = forwarding_header_path.as_ref();
-    let target_header_path = target_header_path.as_ref();
+    write_forwarding_header_with_transform(
+        forwarding_header_path,
+        target_header_path,
+        |include_statement| include_statement.to_string(),
+    )
+}
 
-    let target_header_path = match target_header_path.is_relative() {
-        true => target_header_path.to_path_buf(),
-        false => std::env::current_dir()
-            .unwrap()
-            .as_path()
-            .join(target_header_path),
-    }
-    .canonicalize()
-    .unwrap();
+/// Writes a forwarding header like write_forwarding_header_2, with content given by
+/// transform. transform is called with the generated "#include" statement, and can
+/// wrap or annotate it, for example with a license banner or "#pragma once".
+pub fn write_forwarding_header_with_transform<P, V, F>(
+    forwarding_header_path: P,
+    target_header_path: V,
+    mut transform: F,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    V: AsRef<Path>,
+    F: FnMut(&str) -> String,
+{
+    write_forwarding_header_impl(
+        forwarding_header_path.as_ref(),
+        target_header_path.as_ref(),
+        &IncludeStyle::Quoted,
+        &mut transform,
+    )
+}
 
-    let target_header_path = pathdiff::diff_paths(
-        target_header_path,
-        &forwarding_header_path.parent().unwrap(),
+/// Writes a forwarding header like write_forwarding_header_2, with the "#include" statement
+/// in the given style. See IncludeStyle.
+pub fn write_forwarding_header_with_style<P, V>(
+    forwarding_header_path: P,
+    target_header_path: V,
+    style: &IncludeStyle,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    V: AsRef<Path>,
+{
+    write_forwarding_header_impl(
+        forwarding_header_path.as_ref(),
+        target_header_path.as_ref(),
+        style,
+        &mut |include| include.to_string(),
     )
-    .expect("Unable to create fwd path");
-    // println!("{:?}", target_header_path);
-    let include_statement = format!("#include \"{}\"\n", target_header_path.to_str().unwrap());
-    fs::write(forwarding_header_path, include_statement).expect("Unable to write file");
+}
+
+fn write_forwarding_header_impl(
+    forwarding_header_path: &Path,
+    target_header_path: &Path,
+    style: &IncludeStyle,
+    transform: &mut dyn FnMut(&str) -> String,
+) -> Result<(), QtBuildError> {
+    let include_statement =
+        make_include_statement(forwarding_header_path, target_header_path, style)?;
+    fs::write(forwarding_header_path, transform(&include_statement))
+        .map_err(QtBuildError::config_write(forwarding_header_path))
+}
+
+fn make_include_statement(
+    forwarding_header_path: &Path,
+    target_header_path: &Path,
+    style: &IncludeStyle,
+) -> Result<String, QtBuildError> {
+    let target_header_path = util::safe_canonicalize(target_header_path)
+        .map_err(QtBuildError::missing_source(target_header_path))?;
+    let relative_target_header_path = || -> Result<PathBuf, QtBuildError> {
+        let forwarding_header_dir = forwarding_header_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        let forwarding_header_dir = util::safe_canonicalize(forwarding_header_dir)
+            .map_err(QtBuildError::config_write(forwarding_header_dir))?;
+        pathdiff::diff_paths(&target_header_path, &forwarding_header_dir).ok_or_else(|| {
+            QtBuildError::UnrelatedHeaderPath {
+                header: target_header_path.clone(),
+                base: forwarding_header_dir,
+            }
+        })
+    };
+    Ok(match style {
+        IncludeStyle::Quoted => {
+            let relative_path = relative_target_header_path()?;
+            if relative_path.is_absolute() {
+                util::build_script_warning(&format!(
+                    "Forwarding header {:?} uses the absolute path {:?}",
+                    forwarding_header_path, relative_path
+                ));
+            }
+            format!("#include \"{}\"\n", path_str(&relative_path)?)
+        }
+        IncludeStyle::QuotedWithFallback {
+            max_parent_dirs,
+            fallback,
+        } => {
+            let relative_path = relative_target_header_path()?;
+            let parent_dirs = relative_path
+                .components()
+                .take_while(|component| *component == std::path::Component::ParentDir)
+                .count();
+            if !relative_path.is_absolute() && parent_dirs <= *max_parent_dirs {
+                return Ok(format!("#include \"{}\"\n", path_str(&relative_path)?));
+            }
+            match fallback {
+                DistantHeaderFallback::Error => {
+                    return Err(QtBuildError::DistantHeader {
+                        forwarding_header: forwarding_header_path.to_path_buf(),
+                        target_header: target_header_path,
+                        max_parent_dirs: *max_parent_dirs,
+                    })
+                }
+                DistantHeaderFallback::Absolute => {
+                    util::build_script_warning(&format!(
+                        "Forwarding header {:?} uses the absolute path {:?}",
+                        forwarding_header_path, target_header_path
+                    ));
+                    format!("#include \"{}\"\n", path_str(&target_header_path)?)
+                }
+                DistantHeaderFallback::VirtualRoot {
+                    source_root,
+                    virtual_root,
+                } => make_include_statement(
+                    forwarding_header_path,
+                    &target_header_path,
+                    &IncludeStyle::VirtualRoot {
+                        source_root: source_root.clone(),
+                        virtual_root: virtual_root.clone(),
+                    },
+                )?,
+            }
+        }
+        IncludeStyle::AngleBrackets(include_path) => {
+            let include_path = util::safe_canonicalize(include_path)
+                .map_err(QtBuildError::missing_source(include_path))?;
+            let relative_path = target_header_path
+                .strip_prefix(&include_path)
+                .map_err(|_| QtBuildError::UnrelatedHeaderPath {
+                    header: target_header_path.clone(),
+                    base: include_path.clone(),
+                })?;
+            format!("#include <{}>\n", path_str(relative_path)?)
+        }
+        IncludeStyle::VirtualRoot {
+            source_root,
+            virtual_root,
+        } => {
+            let source_root = util::safe_canonicalize(source_root)
+                .map_err(QtBuildError::missing_source(source_root))?;
+            let relative_path = target_header_path.strip_prefix(&source_root).map_err(|_| {
+                QtBuildError::UnrelatedHeaderPath {
+                    header: target_header_path.clone(),
+                    base: source_root.clone(),
+                }
+            })?;
+            format!(
+                "#include \"{}\"\n",
+                path_str(&virtual_root.join(relative_path))?
+            )
+        }
+    })
+}
+
+// Returns the path as a string for an "#include" statement, which must be valid UTF-8
+fn path_str(path: &Path) -> Result<&str, QtBuildError> {
+    path.to_str().ok_or_else(|| QtBuildError::NonUtf8Path {
+        path: path.to_path_buf(),
+    })
 }
 
 /// Writes a forwarding header to destination_path. The forwarding header
@@ -193,7 +1668,55 @@ where
 /// contain an "#include" statement which includes the target header.
 /// target_header_path may be a relative path, and will be resolved against
 /// std:::env::current_dir if so.
-pub fn write_forwarding_header<P, V>(destination_path: P, target_header_path: V)
+pub fn write_forwarding_header<P, V>(
+    destination_path: P,
+    target_header_path: V,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    V: AsRef<Path>,
+{
+    write_named_forwarding_header(
+        destination_path,
+        target_header_path,
+        &IncludeStyle::Quoted,
+        &mut |include| include.to_string(),
+    )
+}
+
+/// Writes a forwarding header like write_forwarding_header, through the content-addressed
+/// store: the content is stored once, and the forwarding header refers to the stored copy.
+/// See store::ContentStore.
+pub fn write_forwarding_header_to_store<P, V>(
+    store: &ContentStore,
+    destination_path: P,
+    target_header_path: V,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    V: AsRef<Path>,
+{
+    let target_header_path = target_header_path.as_ref();
+    if let Some(file_name) = target_header_path.file_name() {
+        let forwarding_header_path = destination_path.as_ref().join(file_name);
+        let include_statement = make_include_statement(
+            &forwarding_header_path,
+            target_header_path,
+            &IncludeStyle::Quoted,
+        )?;
+        store
+            .write(&forwarding_header_path, include_statement.as_bytes())
+            .map_err(QtBuildError::config_write(&forwarding_header_path))?;
+    }
+    Ok(())
+}
+
+fn write_named_forwarding_header<P, V>(
+    destination_path: P,
+    target_header_path: V,
+    style: &IncludeStyle,
+    transform: &mut dyn FnMut(&str) -> String,
+) -> Result<(), QtBuildError>
 where
     P: AsRef<Path>,
     V: AsRef<Path>,
@@ -202,35 +1725,81 @@ where
     let target_header_path = target_header_path.as_ref();
     if let Some(file_name) = target_header_path.file_name() {
         let forwarding_header_path = destination_path.join(file_name);
-        write_forwarding_header_2(forwarding_header_path, target_header_path)
+        write_forwarding_header_impl(
+            &forwarding_header_path,
+            target_header_path,
+            style,
+            transform,
+        )?;
     }
+    Ok(())
 }
 
 /// Writes forwarding headers which contain e.g. #include "../diff/path/to/real/header.h"
 /// The headers are written to the given path. Each header points back to the corrsponding
 /// header path in the headers iterable.
 #[allow(dead_code)]
-pub fn write_forwarding_headers<'a, P, V>(path: P, headers: V)
+pub fn write_forwarding_headers<'a, P, V>(path: P, headers: V) -> Result<(), QtBuildError>
 where
     P: AsRef<Path>,
     V: IntoIterator<Item = &'a PathBuf>,
 {
-    std::fs::create_dir_all(&path).expect("Unable to create directory");
+    std::fs::create_dir_all(&path).map_err(QtBuildError::config_write(path.as_ref()))?;
     for header_realative_path in headers.into_iter() {
-        write_forwarding_header(&path, header_realative_path);
+        write_forwarding_header(&path, header_realative_path)?;
     }
+    Ok(())
 }
 
 /// Looks for Qt classes in the header at target_header_path, then writes "QFoo"-
 /// type headers to destination_path.
-pub fn write_class_forwarding_header<P, V>(destination_path: P, target_header_path: V)
+pub fn write_class_forwarding_header<P, V>(
+    destination_path: P,
+    target_header_path: V,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    V: AsRef<Path>,
+{
+    write_class_forwarding_header_with_transform(
+        destination_path,
+        target_header_path,
+        ClassHeaderFilter::AllClasses,
+        &IncludeStyle::Quoted,
+        &mut |include| include.to_string(),
+    )
+}
+
+fn write_class_forwarding_header_with_transform<P, V>(
+    destination_path: P,
+    target_header_path: V,
+    class_filter: ClassHeaderFilter,
+    style: &IncludeStyle,
+    transform: &mut dyn FnMut(&str) -> String,
+) -> Result<(), QtBuildError>
 where
     P: AsRef<Path>,
     V: AsRef<Path>,
 {
     let target_header_path = target_header_path.as_ref();
-    // println!("Scan {:?} for classes", &target_header_path);
+    let bytes = std::fs::read(target_header_path)
+        .map_err(QtBuildError::missing_source(target_header_path))?;
+    let source = std::str::from_utf8(&bytes).map_err(|_| QtBuildError::InvalidUtf8Header {
+        path: target_header_path.to_path_buf(),
+    })?;
+    for class in find_qt_classes(source, class_filter) {
+        write_forwarding_header_impl(
+            &destination_path.as_ref().join(class),
+            target_header_path,
+            style,
+            transform,
+        )?;
+    }
+    Ok(())
+}
 
+// Returns the Qt classes declared in the header source, e.g. "QFoo".
+fn find_qt_classes(source: &str, class_filter: ClassHeaderFilter) -> Vec<&str> {
     // Behold, the Qt class name detector
     let is_qt_class = |token: &str| {
         token.starts_with("Q")
@@ -241,21 +1810,20 @@ where
             && !token.contains("<")
             && !token.contains(">")
     };
+    let is_export_macro = |token: &str| token.starts_with("Q_") && token.ends_with("_EXPORT");
+    let exported_only = class_filter == ClassHeaderFilter::ExportedClasses;
 
-    let bytes = std::fs::read(&target_header_path)
-        .expect(&format!("Unable to read file {:?}", &target_header_path));
-    let tokens = std::str::from_utf8(&bytes)
-        .expect("Non-UTF8 source code!")
-        .split_whitespace();
-    let qt_classes = tokens
+    let mut classes: Vec<&str> = source
+        .split_whitespace()
         .tuple_windows::<(_, _, _)>()
         .filter_map(|(elem, next, next_next)| {
             // Look for "class QFoo" and "class <some token> QFoo" and emit
-            // "QFoo". <some token> is typically a Q_CORE_EXPORT or similar.
+            // "QFoo". <some token> is typically a Q_CORE_EXPORT or similar,
+            // and is required to be an export macro if exported_only is set.
             if elem == "class" {
-                if is_qt_class(next) {
+                if is_qt_class(next) && !exported_only {
                     Some(next)
-                } else if is_qt_class(next_next) {
+                } else if is_qt_class(next_next) && (is_export_macro(next) || !exported_only) {
                     Some(next_next)
                 } else {
                     None
@@ -263,23 +1831,326 @@ where
             } else {
                 None
             }
+        })
+        .collect();
+    if class_filter == ClassHeaderFilter::AllClassesAndTypeMacros {
+        classes.extend(
+            find_type_macro_names(source)
+                .into_iter()
+                .filter(|name| is_qt_class(name)),
+        );
+    }
+    classes
+}
+
+// Returns the type names declared with the macros in TYPE_DECLARATION_MACROS, e.g.
+// "QFooFlags" for "Q_DECLARE_FLAGS(QFooFlags, QFooFlag)".
+fn find_type_macro_names(source: &str) -> Vec<&str> {
+    TYPE_DECLARATION_MACROS
+        .iter()
+        .flat_map(|macro_name| {
+            source
+                .match_indices(macro_name)
+                .filter_map(move |(index, _)| {
+                    let arguments = source[index + macro_name.len()..].trim_start();
+                    let arguments = arguments.strip_prefix('(')?;
+                    let end = arguments.find([',', ')'])?;
+                    Some(arguments[..end].trim())
+                })
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Returns the Qt classes declared in the public headers (not "_p.h") in source_path, as
+/// found by the class detector used for the class forwarding headers. The classes are
+/// sorted, without duplicates.
+pub fn find_qt_classes_in_headers<P: AsRef<Path>>(
+    source_path: P,
+    class_filter: ClassHeaderFilter,
+) -> Result<Vec<String>, QtBuildError> {
+    if !source_path.as_ref().is_dir() {
+        return Err(QtBuildError::MissingSource {
+            path: source_path.as_ref().to_path_buf(),
         });
-    for class in qt_classes {
-        // println!("class {:?}", class);
-        write_forwarding_header_2(destination_path.as_ref().join(class), target_header_path);
     }
+    let mut classes = Vec::new();
+    for header_path in
+        util::glob_files_excluding(source_path, OsStr::new("h"), util::DEFAULT_EXCLUDED_DIRS)
+    {
+        if header_path.to_string_lossy().contains("_p.h") {
+            continue;
+        }
+        let bytes = fs::read(&header_path).map_err(QtBuildError::missing_source(&header_path))?;
+        let source = std::str::from_utf8(&bytes).map_err(|_| QtBuildError::InvalidUtf8Header {
+            path: header_path.clone(),
+        })?;
+        classes.extend(
+            find_qt_classes(source, class_filter)
+                .into_iter()
+                .map(|class| class.to_string()),
+        );
+    }
+    classes.sort();
+    classes.dedup();
+    Ok(classes)
+}
+
+/// Writes a Rust source file to path which declares the given classes as
+/// "pub const QT_CLASSES: &[&str]", for use with include!() in crates which generate
+/// bindings. See find_qt_classes_in_headers.
+pub fn write_qt_classes_module<P: AsRef<Path>>(
+    path: P,
+    classes: &[String],
+) -> Result<(), QtBuildError> {
+    let mut content = String::from(
+        "// Generated by qt-cargo-base: the Qt classes found in the Qt headers.\n\
+         pub const QT_CLASSES: &[&str] = &[\n",
+    );
+    for class in classes {
+        content.push_str(&format!("    {:?},\n", class));
+    }
+    content.push_str("];\n");
+    if let Some(parent) = path.as_ref().parent() {
+        std::fs::create_dir_all(parent).map_err(QtBuildError::config_write(parent))?;
+    }
+    fs::write(&path, content).map_err(QtBuildError::config_write(path.as_ref()))
+}
+
+/// Records the content hash of each header scanned for classes, and the class forwarding
+/// headers written for it. Used by write_class_forwarding_headers_cached and
+/// ForwardingHeaderOptions::cache to skip headers
+/// whose content is unchanged, independent of file modification times (which are updated
+/// by e.g. "git checkout").
+#[derive(Debug, Default)]
+pub struct ClassHeaderCache {
+    cache_path: PathBuf,
+    entries: BTreeMap<PathBuf, (u64, Vec<String>)>,
+}
+
+impl ClassHeaderCache {
+    /// Loads the cache from cache_path. Returns an empty cache if the file does not exist
+    /// or can't be parsed.
+    pub fn load<P: AsRef<Path>>(cache_path: P) -> ClassHeaderCache {
+        let cache_path = cache_path.as_ref().to_path_buf();
+        let content = fs::read_to_string(&cache_path).unwrap_or_default();
+        // Line format: <hash> <tab> <header path> <tab> <comma-separated classes>, with
+        // tabs, newlines and backslashes in the header path escaped
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let header_path = PathBuf::from(unescape_cache_field(fields.next()?)?);
+                let classes = fields
+                    .next()?
+                    .split(',')
+                    .filter(|class| !class.is_empty())
+                    .map(|class| class.to_string())
+                    .collect();
+                Some((header_path, (hash, classes)))
+            })
+            .collect();
+        ClassHeaderCache {
+            cache_path,
+            entries,
+        }
+    }
+
+    /// Writes the cache to the path it was loaded from
+    pub fn save(&self) -> Result<(), QtBuildError> {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(header_path, (hash, classes))| {
+                format!(
+                    "{:016x}\t{}\t{}\n",
+                    hash,
+                    escape_cache_field(&header_path.to_string_lossy()),
+                    classes.join(",")
+                )
+            })
+            .collect();
+        if let Some(parent) = self.cache_path.parent() {
+            std::fs::create_dir_all(parent).map_err(QtBuildError::config_write(parent))?;
+        }
+        fs::write(&self.cache_path, content).map_err(QtBuildError::config_write(&self.cache_path))
+    }
+}
+
+// Escapes the tabs, newlines and backslashes in a ClassHeaderCache field
+fn escape_cache_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Reverses escape_cache_field. Returns None for an invalid escape sequence.
+fn unescape_cache_field(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            't' => unescaped.push('\t'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+/// Writes class forwarding headers like write_class_forwarding_headers, skipping headers
+/// whose content hash matches the cache entry (as long as the class forwarding headers
+/// from the previous run still exist). headers is the complete set of headers for path:
+/// the class forwarding headers for classes which are no longer declared in any of them
+/// are deleted. Updates the cache; call ClassHeaderCache::save afterwards. Returns the
+/// number of headers which were scanned and written.
+pub fn write_class_forwarding_headers_cached<'a, P, V>(
+    path: P,
+    headers: V,
+    cache: &mut ClassHeaderCache,
+) -> Result<usize, QtBuildError>
+where
+    P: AsRef<Path>,
+    V: IntoIterator<Item = &'a PathBuf>,
+{
+    let path = path.as_ref();
+    std::fs::create_dir_all(path).map_err(QtBuildError::config_write(path))?;
+    let mut identity = |include: &str| include.to_string();
+    let mut scanned_headers = BTreeSet::new();
+    let mut stale_classes = Vec::new();
+    let mut written = 0;
+    for header_path in headers.into_iter() {
+        let is_scanned = write_class_forwarding_header_cached(
+            path,
+            header_path,
+            ClassHeaderFilter::AllClasses,
+            &IncludeStyle::Quoted,
+            &mut identity,
+            cache,
+            &mut stale_classes,
+        )?;
+        if is_scanned {
+            written += 1;
+        }
+        scanned_headers.insert(header_path.clone());
+    }
+    remove_stale_class_headers(path, &scanned_headers, cache, stale_classes)?;
+    Ok(written)
+}
+
+// Writes the class forwarding headers for the header at header_path, unless the header
+// is unchanged since the cache entry was recorded. Returns whether the header was scanned.
+// Classes which are no longer declared in the header are added to stale_classes.
+fn write_class_forwarding_header_cached(
+    destination_path: &Path,
+    header_path: &Path,
+    class_filter: ClassHeaderFilter,
+    style: &IncludeStyle,
+    transform: &mut dyn FnMut(&str) -> String,
+    cache: &mut ClassHeaderCache,
+    stale_classes: &mut Vec<String>,
+) -> Result<bool, QtBuildError> {
+    let bytes = std::fs::read(header_path).map_err(QtBuildError::missing_source(header_path))?;
+    // The class filter and include style change the written headers as well
+    let hash = util::content_hash(&bytes)
+        ^ util::content_hash(format!("{:?} {:?}", class_filter, style).as_bytes());
+    let cached_classes = cache.entries.remove(header_path);
+    if let Some((cached_hash, classes)) = &cached_classes {
+        if *cached_hash == hash
+            && classes
+                .iter()
+                .all(|class| destination_path.join(class).exists())
+        {
+            cache
+                .entries
+                .insert(header_path.to_path_buf(), cached_classes.unwrap());
+            return Ok(false);
+        }
+    }
+
+    let source = std::str::from_utf8(&bytes).map_err(|_| QtBuildError::InvalidUtf8Header {
+        path: header_path.to_path_buf(),
+    })?;
+    let classes: Vec<String> = find_qt_classes(source, class_filter)
+        .into_iter()
+        .map(|class| class.to_string())
+        .collect();
+    for class in &classes {
+        write_forwarding_header_impl(&destination_path.join(class), header_path, style, transform)?;
+    }
+    if let Some((_, cached_classes)) = cached_classes {
+        stale_classes.extend(
+            cached_classes
+                .into_iter()
+                .filter(|class| !classes.contains(class)),
+        );
+    }
+    cache
+        .entries
+        .insert(header_path.to_path_buf(), (hash, classes));
+    Ok(true)
+}
+
+// Removes the cache entries for headers which were not scanned (removed headers), and
+// deletes the class forwarding headers in destination_path for the stale classes and the
+// classes of the removed headers, unless another header declares the class.
+fn remove_stale_class_headers(
+    destination_path: &Path,
+    scanned_headers: &BTreeSet<PathBuf>,
+    cache: &mut ClassHeaderCache,
+    mut stale_classes: Vec<String>,
+) -> Result<(), QtBuildError> {
+    let removed_headers: Vec<PathBuf> = cache
+        .entries
+        .keys()
+        .filter(|header_path| !scanned_headers.contains(*header_path))
+        .cloned()
+        .collect();
+    for header_path in removed_headers {
+        if let Some((_, classes)) = cache.entries.remove(&header_path) {
+            stale_classes.extend(classes);
+        }
+    }
+    for class in stale_classes {
+        let is_declared = cache
+            .entries
+            .values()
+            .any(|(_, classes)| classes.contains(&class));
+        let class_header_path = destination_path.join(&class);
+        if is_declared || !class_header_path.exists() {
+            continue;
+        }
+        fs::remove_file(&class_header_path)
+            .map_err(QtBuildError::config_write(&class_header_path))?;
+    }
+    Ok(())
 }
 
 /// Writes class forwarding headers for all classes found in the provided headers.
 #[allow(dead_code)]
-pub fn write_class_forwarding_headers<'a, P, V>(path: P, headers: V)
+pub fn write_class_forwarding_headers<'a, P, V>(path: P, headers: V) -> Result<(), QtBuildError>
 where
     P: AsRef<Path> + Send + Sync,
     V: IntoIterator<Item = &'a PathBuf> + Send,
 {
-    std::fs::create_dir_all(&path).expect("Unable to create directory");
+    std::fs::create_dir_all(&path).map_err(QtBuildError::config_write(path.as_ref()))?;
     for header_realative_path in headers.into_iter() {
-        write_class_forwarding_header(&path, header_realative_path);
+        write_class_forwarding_header(&path, header_realative_path)?;
     }
     /*
         FIXME: enabling this causes build failures with missing headers
@@ -289,47 +2160,280 @@ where
             write_class_forwarding_header(&path, header);
         });
     */
+    Ok(())
+}
+
+/// Writes class forwarding headers ("QFoo") to path for the given classes only, for a minimal
+/// include tree. The public headers (not "_p.h") in source_path are scanned for the classes,
+/// and if a class is declared in several headers the first header in path order is used.
+/// Returns the requested classes which were not found as QtBuildError::MissingClasses; the
+/// headers for the classes which were found are written in either case.
+pub fn write_class_forwarding_headers_for<P, Q>(
+    path: P,
+    source_path: Q,
+    class_names: &[&str],
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let path = path.as_ref();
+    std::fs::create_dir_all(path).map_err(QtBuildError::config_write(path))?;
+    let mut header_paths: Vec<PathBuf> =
+        util::glob_files_excluding(source_path, OsStr::new("h"), util::DEFAULT_EXCLUDED_DIRS)
+            .filter(|header_path| !header_path.to_string_lossy().contains("_p.h"))
+            .collect();
+    header_paths.sort();
+
+    let mut class_headers: BTreeMap<&str, &PathBuf> = BTreeMap::new();
+    for header_path in &header_paths {
+        let bytes = fs::read(header_path).map_err(QtBuildError::missing_source(header_path))?;
+        let source = std::str::from_utf8(&bytes).map_err(|_| QtBuildError::InvalidUtf8Header {
+            path: header_path.clone(),
+        })?;
+        for class in find_qt_classes(source, ClassHeaderFilter::AllClasses) {
+            if let Some(class_name) = class_names.iter().find(|name| **name == class) {
+                class_headers.entry(class_name).or_insert(header_path);
+            }
+        }
+        if class_headers.len() == class_names.len() {
+            break;
+        }
+    }
+
+    let mut missing = Vec::new();
+    for class_name in class_names {
+        match class_headers.get(class_name) {
+            Some(header_path) => write_forwarding_header_2(path.join(class_name), header_path)?,
+            None => missing.push(class_name.to_string()),
+        }
+    }
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(QtBuildError::MissingClasses { classes: missing }),
+    }
 }
 
 /// Writes a Qt configuarion header containg defines and features to the given path.
-pub fn write_config_header<P>(path: P, defines: &[(String, String)], features: &[(String, bool)])
+pub fn write_config_header<P>(
+    path: P,
+    defines: &[(String, String)],
+    features: &[(String, bool)],
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+{
+    fs::write(path.as_ref(), make_config_header(defines, features))
+        .map_err(QtBuildError::config_write(path.as_ref()))
+}
+
+/// Writes a config header like write_config_header, through the content-addressed store:
+/// the content is stored once, and path refers to the stored copy. See store::ContentStore.
+pub fn write_config_header_to_store<P>(
+    store: &ContentStore,
+    path: P,
+    defines: &[(String, String)],
+    features: &[(String, bool)],
+) -> Result<(), QtBuildError>
 where
     P: AsRef<Path>,
 {
-    let qconfig_content = format!(
+    store
+        .write(&path, make_config_header(defines, features).as_bytes())
+        .map(|_| ())
+        .map_err(QtBuildError::config_write(path.as_ref()))
+}
+
+/// Creates the content of a config header with the given defines and features
+pub fn make_config_header(defines: &[(String, String)], features: &[(String, bool)]) -> String {
+    format!(
         "{}\n{}",
         make_define_string(defines),
         make_feature_defines(features)
-    );
-    fs::write(path.as_ref(), qconfig_content).expect("Unable to write file");
+    )
+}
+
+/// Gives the content of a forwarding header from its "#include" statement, see
+/// ForwardingHeaderOptions::transform
+pub type HeaderTransform<'a> = Box<dyn FnMut(&str) -> String + 'a>;
+
+/// Options for write_all_forwarding_headers_with_options. The default options write
+/// forwarding headers for all headers and all classes, with quoted "#include" statements.
+pub struct ForwardingHeaderOptions<'a> {
+    /// Header overrides, as (path relative to source_path, content) pairs. A patched copy
+    /// with the given content is written to patched_headers_path for each of these, and the
+    /// forwarding headers point to the patched copy instead. The source tree is not modified.
+    pub header_overrides: Vec<(PathBuf, String)>,
+    /// Where the patched headers are written; required if header_overrides is not empty
+    pub patched_headers_path: Option<PathBuf>,
+    /// Selects the classes which get class forwarding headers
+    pub class_filter: ClassHeaderFilter,
+    /// The "#include" style. Patched headers are included in the same style, and must be
+    /// under the source root for IncludeStyle::VirtualRoot.
+    pub style: IncludeStyle,
+    /// Names of directories which are skipped when scanning source_path
+    pub excluded_dirs: &'a [&'a str],
+    /// Gives the content of each forwarding header, from the generated "#include"
+    /// statement. See write_forwarding_header_with_transform.
+    pub transform: Option<HeaderTransform<'a>>,
+    /// Records the time spent scanning each public header for classes (and writing its
+    /// class forwarding headers). See timing::HeaderScanProfile.
+    pub profile: Option<&'a mut HeaderScanProfile>,
+    /// Appends a trace of the header scan of source_path to the file at this path, for
+    /// debugging missing headers. See util::log_header_scan.
+    pub scan_log: Option<PathBuf>,
+    /// Skips the class scan for public headers whose content is unchanged since the
+    /// previous run, and deletes the class forwarding headers for removed classes. Call
+    /// ClassHeaderCache::save afterwards. The cache is not used (or updated) if a transform
+    /// is set, since the forwarding header content then depends on the transform as well.
+    pub cache: Option<&'a mut ClassHeaderCache>,
+}
+
+impl Default for ForwardingHeaderOptions<'_> {
+    fn default() -> Self {
+        ForwardingHeaderOptions {
+            header_overrides: Vec::new(),
+            patched_headers_path: None,
+            class_filter: ClassHeaderFilter::AllClasses,
+            style: IncludeStyle::Quoted,
+            excluded_dirs: util::DEFAULT_EXCLUDED_DIRS,
+            transform: None,
+            profile: None,
+            scan_log: None,
+            cache: None,
+        }
+    }
 }
 
 /// Writes forwarding headers for all headers (.h) files found in source_path
 /// to destination_path. This includes public headers and private headers (_p.h).
 /// Private headers are placed under the "private/" prefix in the destination
-/// path. Finally, class forwarding headers are
-pub fn write_all_forwarding_headers<P, Q>(source_path: P, destination_path: Q)
+/// path. Finally, class forwarding headers are written for the classes found in
+/// the public headers. See write_all_forwarding_headers_with_options.
+pub fn write_all_forwarding_headers<P, Q>(
+    source_path: P,
+    destination_path: Q,
+) -> Result<(), QtBuildError>
 where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    let destination_private_path = destination_path.as_ref().join("private");
-    std::fs::create_dir_all(&destination_private_path).expect("Unable to create directory");
+    write_all_forwarding_headers_with_options(
+        source_path,
+        destination_path,
+        ForwardingHeaderOptions::default(),
+    )
+}
 
-    let header_paths = util::glob_files(source_path, OsStr::new("h"));
-    for header_path in header_paths {
-        let is_private = header_path
+/// Writes forwarding headers like write_all_forwarding_headers, with the given options.
+/// See ForwardingHeaderOptions.
+pub fn write_all_forwarding_headers_with_options<P, Q>(
+    source_path: P,
+    destination_path: Q,
+    mut options: ForwardingHeaderOptions,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut profile = options.profile.take();
+    let mut cache = options.cache.take().filter(|_| options.transform.is_none());
+    let mut scanned_headers = BTreeSet::new();
+    let mut stale_classes = Vec::new();
+    let mut identity = |include: &str| include.to_string();
+    let transform: &mut dyn FnMut(&str) -> String = match options.transform.as_mut() {
+        Some(transform) => transform.as_mut(),
+        None => &mut identity,
+    };
+    let style = &options.style;
+    let excluded_dirs = options.excluded_dirs;
+    if !source_path.as_ref().is_dir() {
+        return Err(QtBuildError::MissingSource {
+            path: source_path.as_ref().to_path_buf(),
+        });
+    }
+    let destination_path = destination_path.as_ref();
+    let destination_private_path = destination_path.join("private");
+    std::fs::create_dir_all(&destination_private_path)
+        .map_err(QtBuildError::config_write(&destination_private_path))?;
+
+    if let Some(log_path) = &options.scan_log {
+        util::log_header_scan(&source_path, OsStr::new("h"), excluded_dirs, log_path)?;
+    }
+    let header_paths = util::glob_files_excluding(&source_path, OsStr::new("h"), excluded_dirs);
+    for header_path in header_paths {
+        let header_override =
+            header_path
+                .strip_prefix(&source_path)
+                .ok()
+                .and_then(|relative_path| {
+                    options
+                        .header_overrides
+                        .iter()
+                        .find(|(override_path, _)| override_path == relative_path)
+                });
+        let header_path = match (header_override, &options.patched_headers_path) {
+            (Some((relative_path, content)), Some(patched_headers_path)) => {
+                let patched_header_path = patched_headers_path.join(relative_path);
+                let patched_header_dir = patched_header_path.parent().unwrap();
+                std::fs::create_dir_all(patched_header_dir)
+                    .map_err(QtBuildError::config_write(patched_header_dir))?;
+                fs::write(&patched_header_path, content)
+                    .map_err(QtBuildError::config_write(&patched_header_path))?;
+                patched_header_path
+            }
+            (Some(_), None) => {
+                return Err(QtBuildError::InvalidArgument {
+                    message: "header overrides require a patched headers path".to_string(),
+                })
+            }
+            (None, _) => header_path,
+        };
+        let is_private = header_path
             .file_name()
             .unwrap()
             .to_string_lossy()
             .contains("_p.h");
         if is_private {
-            write_forwarding_header(&destination_private_path, header_path);
+            write_named_forwarding_header(
+                &destination_private_path,
+                header_path,
+                style,
+                transform,
+            )?;
         } else {
-            write_forwarding_header(&destination_path, &header_path);
-            write_class_forwarding_header(&destination_path, &header_path)
+            write_named_forwarding_header(destination_path, &header_path, style, transform)?;
+            let scan_start = std::time::Instant::now();
+            match cache.as_deref_mut() {
+                Some(cache) => {
+                    write_class_forwarding_header_cached(
+                        destination_path,
+                        &header_path,
+                        options.class_filter,
+                        style,
+                        transform,
+                        cache,
+                        &mut stale_classes,
+                    )?;
+                    scanned_headers.insert(header_path.clone());
+                }
+                None => write_class_forwarding_header_with_transform(
+                    destination_path,
+                    &header_path,
+                    options.class_filter,
+                    style,
+                    transform,
+                )?,
+            }
+            if let Some(profile) = profile.as_deref_mut() {
+                profile.record(&header_path, scan_start.elapsed());
+            }
         }
     }
+    if let Some(cache) = cache {
+        remove_stale_class_headers(destination_path, &scanned_headers, cache, stale_classes)?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -340,20 +2444,1534 @@ mod qt_cargo_base_configure_tests {
 
     #[test]
     fn test_write_configuration() {
-        let temp = tempdir::TempDir::new("qt-cargo-base-configure-test").unwrap();
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_path: Option<&str> = None;
+
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        write_configuration(&config, temp.path(), qt_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_headers_to_store() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let store = ContentStore::new(temp.path().join("store")).unwrap();
+        let target_header = temp.path().join("source/qfoo.h");
+        for build in ["build1", "build2"] {
+            let build_path = temp.path().join(build);
+            write_forwarding_header_to_store(&store, &build_path, &target_header).unwrap();
+            write_config_header_to_store(
+                &store,
+                build_path.join("qconfig.h"),
+                &[("QT_FOO".to_string(), "1".to_string())],
+                &[("foo".to_string(), true)],
+            )
+            .unwrap();
+        }
+        let forwarding_header = fs::read_to_string(temp.path().join("build1/qfoo.h")).unwrap();
+        assert_eq!(forwarding_header, "#include \"../source/qfoo.h\"\n");
+        assert_eq!(
+            fs::read_to_string(temp.path().join("build2/qfoo.h")).unwrap(),
+            forwarding_header
+        );
+        assert!(fs::read_to_string(temp.path().join("build2/qconfig.h"))
+            .unwrap()
+            .contains("#define QT_FEATURE_foo 1\n"));
+        // One blob each for the forwarding header and the config header
+        let blob_count = walkdir::WalkDir::new(store.root())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .count();
+        assert_eq!(blob_count, 2);
+    }
+
+    #[test]
+    fn test_write_configuration_with_overrides() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
         let qt_path: Option<&str> = None;
 
         let mut config = QtConfiguration::new();
         set_default_configuration(&mut config);
-        write_configuration(&config, temp.path(), qt_path);
+        config.set_animation(false);
+        write_configuration_with_overrides(
+            &config,
+            &[
+                ("animation".to_string(), true),
+                ("thread".to_string(), false),
+            ],
+            temp.path(),
+            qt_path,
+        )
+        .unwrap();
+        assert_eq!(config.feature("animation"), Some(false));
+        assert_eq!(config.feature("thread"), Some(true));
+
+        let qtcore_config =
+            std::fs::read_to_string(temp.path().join("QtCore/qtcore-config.h")).unwrap();
+        assert!(qtcore_config.contains("#define QT_FEATURE_animation 1\n"));
+        let qconfig = std::fs::read_to_string(temp.path().join("QtCore/qconfig.h")).unwrap();
+        assert!(qconfig.contains("#define QT_FEATURE_thread -1\n"));
+    }
+
+    #[test]
+    fn test_generate_config_headers() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_feature("thread", false);
+
+        let headers = generate_config_headers(&config);
+        let mut paths: Vec<_> = headers.keys().cloned().collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "QtCore/private/qconfig_p.h",
+                "QtCore/private/qtcore-config_p.h",
+                "QtCore/qconfig.h",
+                "QtCore/qtcore-config.h",
+                "QtCore/qtcoreversion.h",
+            ]
+            .map(PathBuf::from)
+        );
+        let qconfig = &headers[Path::new("QtCore/qconfig.h")];
+        assert!(qconfig.contains("#define QT_VERSION_STR \"6.2.0\"\n"));
+        assert!(qconfig.contains("#define QT_FEATURE_thread -1\n"));
+        assert!(!headers[Path::new("QtCore/private/qconfig_p.h")].contains("QT_VERSION_STR"));
+    }
+
+    #[test]
+    fn test_print_config_header() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_feature("thread", false);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        print_config_header(&config, ConfigHeaderKind::QConfig, &mut buffer).unwrap();
+        let content = String::from_utf8(buffer).unwrap();
+        assert_eq!(
+            content,
+            generate_config_headers(&config)[Path::new("QtCore/qconfig.h")]
+        );
+        assert!(content.contains("#define QT_FEATURE_thread -1\n"));
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let error = print_config_header(
+            &QtConfiguration::new(),
+            ConfigHeaderKind::QtCoreVersion,
+            &mut buffer,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_verify_config_headers() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_qt_version(QtVersion::new(6, 2, 0));
+        let no_path: Option<PathBuf> = None;
+        write_configuration(&config, temp.path(), no_path).unwrap();
+        assert_eq!(verify_config_headers(&config, temp.path()), Ok(()));
+
+        // Corrupt a header, and change the configuration
+        let qconfig_path = temp.path().join("QtCore/qconfig.h");
+        let qconfig = fs::read_to_string(&qconfig_path).unwrap();
+        let qconfig = qconfig.replace(
+            "#define QT_FEATURE_thread 1",
+            "#define QT_FEATURE_thread -1",
+        );
+        fs::write(&qconfig_path, qconfig + "#define QT_STALE 1\n").unwrap();
+        fs::remove_file(temp.path().join("QtCore/qtcoreversion.h")).unwrap();
+        config.set_feature("cborstreamreader", false);
+
+        let discrepancies = verify_config_headers(&config, temp.path()).unwrap_err();
+        let qconfig_header = PathBuf::from("QtCore/qconfig.h");
+        let qtcore_config_header = PathBuf::from("QtCore/qtcore-config.h");
+        assert!(discrepancies.contains(&Discrepancy::WrongValue {
+            header: qconfig_header.clone(),
+            key: "QT_FEATURE_thread".to_string(),
+            expected: "1".to_string(),
+            actual: "-1".to_string(),
+        }));
+        assert!(discrepancies.contains(&Discrepancy::UnexpectedDefine {
+            header: qconfig_header,
+            key: "QT_STALE".to_string(),
+            actual: "1".to_string(),
+        }));
+        assert!(discrepancies.contains(&Discrepancy::WrongValue {
+            header: qtcore_config_header,
+            key: "QT_FEATURE_cborstreamreader".to_string(),
+            expected: "-1".to_string(),
+            actual: "1".to_string(),
+        }));
+        assert!(discrepancies.contains(&Discrepancy::MissingHeader {
+            header: PathBuf::from("QtCore/qtcoreversion.h"),
+        }));
+        assert_eq!(discrepancies.len(), 4);
+    }
+
+    #[test]
+    fn test_set_bindable_properties() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_path: Option<&str> = None;
+
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_bindable_properties(false);
+        write_configuration(&config, temp.path(), qt_path).unwrap();
+
+        let qtcore_config = fs::read_to_string(temp.path().join("QtCore/qtcore-config.h")).unwrap();
+        assert!(qtcore_config.contains("#define QT_FEATURE_properties -1\n"));
+        assert_eq!(qtcore_config.matches("QT_FEATURE_properties").count(), 1);
+    }
+
+    #[test]
+    fn test_set_feature() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert_eq!(config.feature("thread"), Some(true));
+        assert_eq!(config.feature("no_such_feature"), None);
+
+        config.set_feature("thread", false);
+        config.set_feature("no_such_feature", true);
+        assert_eq!(config.feature("thread"), Some(false));
+        assert_eq!(config.feature("no_such_feature"), Some(true));
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), qt_path).unwrap();
+        let qconfig = fs::read_to_string(temp.path().join("QtCore/qconfig.h")).unwrap();
+        assert!(qconfig.contains("#define QT_FEATURE_thread -1\n"));
+    }
+
+    #[test]
+    fn test_set_private_feature_and_define() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_feature("QT_FEATURE_thread", false);
+        assert_eq!(config.feature("thread"), Some(false));
+        assert_eq!(config.feature("QT_FEATURE_thread"), Some(false));
+
+        config.set_private_feature("no_such_private_feature", true);
+        config.set_private_feature("QT_FEATURE_no_such_private_feature", false);
+        assert_eq!(config.feature("no_such_private_feature"), Some(false));
+
+        config.set_define("QT_NO_DEBUG", "1");
+        config.set_define("QT_NO_DEBUG", "");
+        assert_eq!(config.define("QT_NO_DEBUG"), Some(""));
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), qt_path).unwrap();
+        let qtcore_config = fs::read_to_string(temp.path().join("QtCore/qtcore-config.h")).unwrap();
+        assert_eq!(qtcore_config.matches("#define QT_NO_DEBUG").count(), 1);
+        let qtcore_private_config =
+            fs::read_to_string(temp.path().join("QtCore/private/qtcore-config_p.h")).unwrap();
+        assert_eq!(
+            qtcore_private_config
+                .matches("QT_FEATURE_no_such_private_feature")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_set_dbus() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_dbus(true);
+        config.set_private_feature("dbus_linked", true);
+        assert!(config.link_libraries().contains(&"dbus-1"));
+
+        config.set_dbus(false);
+        for feature in features::DBUS_FEATURES {
+            assert_eq!(config.feature(feature), Some(false));
+        }
+        assert!(!config.link_libraries().contains(&"dbus-1"));
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), qt_path).unwrap();
+        let qconfig = fs::read_to_string(temp.path().join("QtCore/qconfig.h")).unwrap();
+        assert!(qconfig.contains("#define QT_FEATURE_dbus -1\n"));
+        let qconfig_p = fs::read_to_string(temp.path().join("QtCore/private/qconfig_p.h")).unwrap();
+        assert!(qconfig_p.contains("#define QT_FEATURE_dbus_linked -1\n"));
+    }
+
+    #[test]
+    fn test_version_header() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert_eq!(config.qt_version(), Some(QtVersion::new(6, 2, 0)));
+        config.set_qt_version(QtVersion::new(6, 3, 1));
+        assert_eq!(config.qt_version(), Some(QtVersion::new(6, 3, 1)));
+
+        let (mut builder, temp) = util::test_builder();
+        let no_qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), no_qt_path).unwrap();
+
+        // QT_VERSION_CHECK and QT_VERSION as defined by qglobal.h
+        let source = r#"
+            #include <QtCore/qconfig.h>
+            #include <QtCore/qtcoreversion.h>
+            #define QT_VERSION_CHECK(major, minor, patch) ((major<<16)|(minor<<8)|(patch))
+            #define QT_VERSION QT_VERSION_CHECK(QT_VERSION_MAJOR, QT_VERSION_MINOR, QT_VERSION_PATCH)
+            static_assert(QTCORE_VERSION == QT_VERSION_CHECK(6, 3, 1), "QTCORE_VERSION");
+            static_assert(QTCORE_VERSION == QT_VERSION, "QT_VERSION");
+            static_assert(sizeof(QTCORE_VERSION_STR) == sizeof(QT_VERSION_STR), "QTCORE_VERSION_STR");
+        "#;
+        builder.include(temp.path());
+        util::try_compile(&builder, source, &[]).unwrap();
+    }
+
+    #[test]
+    fn test_set_disable_deprecated_before() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_path: Option<&str> = None;
+
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_disable_deprecated_before(QtVersion::new(5, 15, 0));
+        config.set_disable_deprecated_before(QtVersion::new(6, 2, 10));
+        write_configuration(&config, temp.path(), qt_path).unwrap();
+
+        let qconfig = fs::read_to_string(temp.path().join("QtCore/qconfig.h")).unwrap();
+        assert!(qconfig.contains("#define QT_DISABLE_DEPRECATED_BEFORE 0x06020A\n"));
+        assert_eq!(qconfig.matches("QT_DISABLE_DEPRECATED_BEFORE").count(), 1);
+    }
+
+    #[test]
+    fn test_set_cbor_streams() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert!(config.requires_tinycbor());
+        let sources = [
+            "serialization/qcborstreamwriter.cpp",
+            "serialization/qcborvalue.cpp",
+            "serialization/qjsondocument.cpp",
+        ];
+
+        config.set_cbor_streams(false);
+        assert!(!config.requires_tinycbor());
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            [
+                "serialization/qcborvalue.cpp",
+                "serialization/qjsondocument.cpp"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_logging_backend() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_ipc(false);
+        config.set_plugins(false);
+        assert_eq!(config.feature("journald"), Some(false));
+        assert!(config.link_libraries().is_empty());
+
+        config.set_logging_backend(LoggingBackend::Journald);
+        assert_eq!(config.feature("journald"), Some(true));
+        assert_eq!(config.feature("syslog"), Some(false));
+        assert_eq!(config.link_libraries(), ["systemd"]);
+
+        config.set_logging_backend(LoggingBackend::Stderr);
+        assert_eq!(config.feature("journald"), Some(false));
+        assert!(config.link_libraries().is_empty());
+    }
+
+    #[test]
+    fn test_default_link_libraries() {
+        // The default configuration links only the libraries for the IPC classes and
+        // dynamic loading, which can be disabled
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert_eq!(config.link_libraries(), ["rt", "dl"]);
+        config.set_ipc(false);
+        config.set_plugins(false);
+        assert!(config.link_libraries().is_empty());
+    }
+
+    #[test]
+    fn test_set_glib() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_ipc(false);
+        config.set_plugins(false);
+        // QT_NO_GLIB is set by default, so glib is not used or linked
+        assert_eq!(config.define("QT_NO_GLIB"), Some("1"));
+        assert!(config.link_libraries().is_empty());
+
+        config.set_glib(true);
+        assert_eq!(config.define("QT_NO_GLIB"), None);
+        assert_eq!(config.link_libraries(), ["glib-2.0"]);
+        let sources = [
+            "kernel/qeventdispatcher_glib.cpp",
+            "kernel/qeventdispatcher_unix.cpp",
+        ];
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+
+        config.set_glib(false);
+        assert_eq!(config.feature("glib"), Some(false));
+        assert_eq!(config.define("QT_NO_GLIB"), Some("1"));
+        assert!(config.link_libraries().is_empty());
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["kernel/qeventdispatcher_unix.cpp"]
+        );
+    }
+
+    #[test]
+    fn test_set_network_features() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        let sources = ["io/qtldurl.cpp", "io/qurl.cpp", "io/qurlidna.cpp"];
+
+        config.set_network_features(true);
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+
+        config.set_network_features(false);
+        assert_eq!(config.feature("network"), Some(false));
+        assert_eq!(config.feature("topleveldomain"), Some(false));
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["io/qurl.cpp", "io/qurlidna.cpp"]
+        );
+    }
+
+    #[test]
+    fn test_set_ipc() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_plugins(false);
+        config.set_ipc(true);
+        assert_eq!(config.link_libraries(), ["rt"]);
+        assert_eq!(
+            crate::sources::filter_sources(crate::sources::BOOTSTRAP_SOURCES_UNIX, &config),
+            crate::sources::BOOTSTRAP_SOURCES_UNIX
+        );
+
+        config.set_ipc(false);
+        assert!(config.link_libraries().is_empty());
+        assert_eq!(
+            crate::sources::filter_sources(crate::sources::BOOTSTRAP_SOURCES_UNIX, &config),
+            [
+                "io/qfilesystemengine_unix.cpp",
+                "io/qfilesystemiterator_unix.cpp",
+                "io/qfsfileengine_unix.cpp",
+                "kernel/qcore_unix.cpp",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_process() {
+        let process_sources = [
+            "io/forkfd_qt.cpp",
+            "io/qprocess.cpp",
+            "io/qprocess_unix.cpp",
+            "io/qstandardpaths.cpp",
+        ];
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert!(config.requires_forkfd());
+        assert_eq!(
+            crate::sources::filter_sources(&process_sources, &config),
+            process_sources
+        );
+
+        config.set_process(false);
+        assert_eq!(config.feature("process"), Some(false));
+        assert_eq!(config.feature("processenvironment"), Some(false));
+        assert!(!config.requires_forkfd());
+        assert_eq!(
+            crate::sources::filter_sources(&process_sources, &config),
+            ["io/qstandardpaths.cpp"]
+        );
+
+        // QProcessEnvironment can be enabled on its own
+        config.set_feature("processenvironment", true);
+        assert_eq!(
+            crate::sources::filter_sources(&process_sources, &config),
+            [
+                "io/qprocess.cpp",
+                "io/qprocess_unix.cpp",
+                "io/qstandardpaths.cpp"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_concurrent() {
+        let thread_sources = [
+            "thread/qthread.cpp",
+            "thread/qthread_unix.cpp",
+            "thread/qthreadpool.cpp",
+            "thread/qfutureinterface.cpp",
+        ];
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+
+        // QThread and QThreadPool without QFuture and QtConcurrent
+        config.set_concurrent(false);
+        assert_eq!(config.feature("thread"), Some(true));
+        assert_eq!(config.feature("future"), Some(false));
+        assert_eq!(config.feature("concurrent"), Some(false));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        assert_eq!(
+            crate::sources::filter_sources(&thread_sources, &config),
+            [
+                "thread/qthread.cpp",
+                "thread/qthread_unix.cpp",
+                "thread/qthreadpool.cpp"
+            ]
+        );
+
+        config.set_thread(false);
+        config.set_feature("concurrent", true);
+        assert_eq!(
+            config.validate_feature_dependencies(),
+            Err(vec![
+                "feature \"concurrent\" requires \"thread\", which is disabled".to_string(),
+                "feature \"concurrent\" requires \"future\", which is disabled".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_set_animation() {
+        let sources = [
+            "animation/qpropertyanimation.cpp",
+            "animation/qvariantanimation.cpp",
+            "kernel/qobject.cpp",
+            "tools/qeasingcurve.cpp",
+        ];
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+
+        config.set_animation(false);
+        assert_eq!(config.feature("animation"), Some(false));
+        assert_eq!(config.feature("easingcurve"), Some(true));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["kernel/qobject.cpp", "tools/qeasingcurve.cpp"]
+        );
+        let qtcore_config = &generate_config_headers(&config)[Path::new("QtCore/qtcore-config.h")];
+        assert!(qtcore_config.contains("#define QT_FEATURE_animation -1\n"));
+
+        config.set_animation(true);
+        config.set_feature("easingcurve", false);
+        assert_eq!(
+            config.validate_feature_dependencies(),
+            Err(vec![
+                "feature \"animation\" requires \"easingcurve\", which is disabled".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_set_windows_msvc_configuration() {
+        let mut config = QtConfiguration::new();
+        set_windows_msvc_configuration(&mut config);
+        assert_eq!(config.mkspec().as_deref(), Some("win32-msvc"));
+        assert_eq!(config.feature("glibc"), Some(false));
+        assert_eq!(config.feature("thread"), Some(true));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        assert!(config.link_libraries().contains(&"ws2_32"));
+        assert!(!config.link_libraries().contains(&"dl"));
+
+        let headers = generate_config_headers(&config);
+        let qconfig_private = &headers[Path::new("QtCore/private/qconfig_p.h")];
+        assert!(qconfig_private.contains("#define QT_FEATURE_reduce_relocations -1\n"));
+        let qconfig = &headers[Path::new("QtCore/qconfig.h")];
+        assert!(!qconfig.contains("QT_VISIBILITY_AVAILABLE"));
+        let qtcore_config = &headers[Path::new("QtCore/qtcore-config.h")];
+        assert!(qtcore_config.contains("#define QT_FEATURE_glib -1\n"));
+    }
+
+    #[test]
+    fn test_set_macos_configuration() {
+        let mut config = QtConfiguration::new();
+        set_macos_configuration(&mut config);
+        assert_eq!(config.mkspec().as_deref(), Some("macx-clang"));
+        assert_eq!(config.feature("glibc"), Some(false));
+        assert_eq!(config.feature("dlopen"), Some(true));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        let link_libraries = config.link_libraries();
+        assert!(link_libraries.contains(&"framework=CoreFoundation"));
+        assert!(link_libraries.contains(&"framework=Foundation"));
+        assert!(!link_libraries.contains(&"rt"));
+        assert!(!link_libraries.contains(&"dl"));
+
+        let headers = generate_config_headers(&config);
+        let qconfig = &headers[Path::new("QtCore/qconfig.h")];
+        assert!(qconfig.contains("QT_VISIBILITY_AVAILABLE"));
+        assert!(!qconfig.contains("QT_REDUCE_RELOCATIONS"));
+    }
+
+    #[test]
+    fn test_set_android_configuration() {
+        let mut config = QtConfiguration::new();
+        set_android_configuration(&mut config);
+        assert_eq!(config.mkspec().as_deref(), Some("android-clang"));
+        assert_eq!(config.feature("glibc"), Some(false));
+        assert_eq!(config.feature("sharedmemory"), Some(false));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        config.set_plugins(true);
+        assert_eq!(config.link_libraries(), vec!["dl", "log"]);
+
+        let headers = generate_config_headers(&config);
+        let qconfig = &headers[Path::new("QtCore/qconfig.h")];
+        assert!(!qconfig.contains("QT_REDUCE_RELOCATIONS"));
+        let qtcore_config = &headers[Path::new("QtCore/qtcore-config.h")];
+        assert!(qtcore_config.contains("#define QT_FEATURE_sharedmemory -1\n"));
+    }
+
+    #[test]
+    fn test_set_testlib() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        let default_headers = generate_config_headers(&config);
+        // Disabling testlib leaves the default QtCore configuration unchanged
+        config.set_testlib(false);
+        assert_eq!(config.feature("testlib"), Some(false));
+        assert_eq!(config.feature("private_tests"), Some(true));
+        let headers = generate_config_headers(&config);
+        assert!(!headers.contains_key(Path::new("QtTest/qttestlib-config.h")));
+        assert_eq!(
+            headers[Path::new("QtCore/qconfig.h")],
+            default_headers[Path::new("QtCore/qconfig.h")]
+        );
+        assert!(headers[Path::new("QtCore/qconfig.h")].contains("#define QT_BUILD_INTERNAL"));
+
+        config.set_testlib(true);
+        assert_eq!(config.feature("testlib"), Some(true));
+        assert_eq!(config.feature("private_tests"), Some(true));
+        let headers = generate_config_headers(&config);
+        assert!(headers[Path::new("QtCore/qconfig.h")].contains("#define QT_BUILD_INTERNAL"));
+        assert!(headers[Path::new("QtTest/qttestlib-config.h")]
+            .contains("#define QT_FEATURE_itemmodeltester 1\n"));
+        assert!(headers[Path::new("QtTest/private/qttestlib-config_p.h")]
+            .contains("#define QT_FEATURE_valgrind -1\n"));
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let no_qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), no_qt_path).unwrap();
+        assert!(temp
+            .path()
+            .join("QtTest/private/qttestlib-config_p.h")
+            .exists());
+    }
+
+    #[test]
+    fn test_set_plugins() {
+        let sources = [
+            "plugin/qfactoryloader.cpp",
+            "plugin/qlibrary.cpp",
+            "plugin/qlibrary_unix.cpp",
+            "plugin/qpluginloader.cpp",
+        ];
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_ipc(false);
+        config.set_plugins(true);
+        assert_eq!(config.link_libraries(), ["dl"]);
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+
+        config.set_plugins(false);
+        assert_eq!(config.feature("library"), Some(false));
+        assert!(config.link_libraries().is_empty());
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["plugin/qfactoryloader.cpp"]
+        );
+        let qtcore_config = &generate_config_headers(&config)[Path::new("QtCore/qtcore-config.h")];
+        assert!(qtcore_config.contains("#define QT_FEATURE_library -1\n"));
+    }
+
+    #[test]
+    fn test_set_regular_expression() {
+        let sources = ["text/qregularexpression.cpp", "text/qstring.cpp"];
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert!(config.requires_pcre2());
+        assert!(!config.link_libraries().contains(&"pcre2-16"));
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+
+        config.set_feature("system_pcre2", true);
+        assert!(!config.requires_pcre2());
+        assert!(config.link_libraries().contains(&"pcre2-16"));
+
+        config.set_regular_expression(false);
+        assert_eq!(config.feature("regularexpression"), Some(false));
+        assert!(!config.requires_pcre2());
+        assert!(!config.link_libraries().contains(&"pcre2-16"));
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["text/qstring.cpp"]
+        );
+        let qtcore_config = &generate_config_headers(&config)[Path::new("QtCore/qtcore-config.h")];
+        assert!(qtcore_config.contains("#define QT_FEATURE_regularexpression -1\n"));
+    }
+
+    #[test]
+    fn test_set_xml_stream() {
+        let sources = [
+            "kernel/qobject.cpp",
+            "serialization/qxmlstream.cpp",
+            "serialization/qxmlstreamgrammar.cpp",
+            "serialization/qxmlutils.cpp",
+        ];
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_feature("xml", false);
+        config.set_xml_stream(true, false);
+        assert_eq!(config.feature("xmlstream"), Some(true));
+        assert_eq!(config.feature("xmlstreamreader"), Some(true));
+        assert_eq!(config.feature("xmlstreamwriter"), Some(false));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+        let qtcore_config = &generate_config_headers(&config)[Path::new("QtCore/qtcore-config.h")];
+        assert!(qtcore_config.contains("#define QT_FEATURE_xmlstreamreader 1\n"));
+        assert!(qtcore_config.contains("#define QT_FEATURE_xmlstreamwriter -1\n"));
+
+        config.set_xml_stream(false, false);
+        assert_eq!(config.feature("xmlstream"), Some(false));
+        assert_eq!(config.validate_feature_dependencies(), Ok(()));
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["kernel/qobject.cpp"]
+        );
+    }
+
+    #[test]
+    fn test_set_qt_version_string() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_qt_version_string("6.5.0-mycompany");
+        config.set_qt_version(QtVersion::new(6, 5, 0));
+        assert_eq!(config.qt_version(), Some(QtVersion::new(6, 5, 0)));
+        assert_eq!(config.qt_version_string().unwrap(), "6.5.0-mycompany");
+
+        let headers = generate_config_headers(&config);
+        let qconfig = &headers[Path::new("QtCore/qconfig.h")];
+        assert!(qconfig.contains("#define QT_VERSION_STR \"6.5.0-mycompany\"\n"));
+        assert!(qconfig.contains("#define QT_VERSION_MINOR 5\n"));
+        let version_header = &headers[Path::new("QtCore/qtcoreversion.h")];
+        assert!(version_header.contains("#define QTCORE_VERSION_STR \"6.5.0-mycompany\"\n"));
+        assert!(version_header.contains("#define QTCORE_VERSION 0x060500\n"));
+    }
+
+    #[test]
+    fn test_set_config_prelude() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_config_prelude(ConfigPrelude::Content("#pragma once".to_string()));
+        let qconfig = &generate_config_headers(&config)[Path::new("QtCore/qconfig.h")];
+        assert!(qconfig.starts_with("#pragma once\n#define "));
+
+        config.set_config_prelude(ConfigPrelude::Include(PathBuf::from(
+            "/app/qconfig_prelude.h",
+        )));
+        let headers = generate_config_headers(&config);
+        assert!(headers[Path::new("QtCore/qconfig.h")]
+            .starts_with("#include \"/app/qconfig_prelude.h\"\n"));
+        assert!(!headers[Path::new("QtCore/qtcore-config.h")].contains("qconfig_prelude.h"));
+    }
+
+    #[test]
+    fn test_set_foreach() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert!(config.foreach());
+        assert!(config.compile_defines().is_empty());
+
+        config.set_foreach(false);
+        assert!(!config.foreach());
+        assert_eq!(config.compile_defines(), [("QT_NO_FOREACH", None)]);
+
+        // The define is not written to the config headers
+        assert!(generate_config_headers(&config)
+            .values()
+            .all(|content| !content.contains("QT_NO_FOREACH")));
+    }
+
+    #[test]
+    fn test_set_license() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_source = temp.path().join("qt-src");
+        std::fs::create_dir_all(qt_source.join("qtbase")).unwrap();
+        assert_eq!(detect_license(&qt_source), QtLicense::OpenSource);
+        std::fs::write(qt_source.join("qtbase/LICENSE.QT-LICENSE-AGREEMENT"), "").unwrap();
+        assert_eq!(detect_license(&qt_source), QtLicense::Commercial);
+
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        assert_eq!(config.license(), None);
+        config.set_license(QtLicense::OpenSource);
+        config.set_license(QtLicense::Commercial);
+        assert_eq!(config.license(), Some(QtLicense::Commercial));
+
+        let no_qt_path: Option<&str> = None;
+        let config_path = temp.path().join("config");
+        write_configuration(&config, &config_path, no_qt_path).unwrap();
+        let qconfig = fs::read_to_string(config_path.join("QtCore/qconfig.h")).unwrap();
+        assert!(qconfig.contains("#define QT_EDITION QT_EDITION_COMMERCIAL\n"));
+        assert!(!qconfig.contains("QT_EDITION_OPENSOURCE"));
+    }
+
+    #[test]
+    fn test_set_target_data_layout() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_target_data_layout(TargetDataLayout::from_cargo_env());
+        config.set_target_data_layout(TargetDataLayout::from_target_triple(
+            "powerpc-unknown-linux-gnu",
+        ));
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let no_qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), no_qt_path).unwrap();
+        let qconfig = fs::read_to_string(temp.path().join("QtCore/qconfig.h")).unwrap();
+        assert_eq!(qconfig.matches("#define QT_POINTER_SIZE ").count(), 1);
+        assert!(qconfig.contains("#define QT_POINTER_SIZE 4\n"));
+        assert!(qconfig.contains("#define Q_BYTE_ORDER Q_BIG_ENDIAN\n"));
+    }
+
+    #[test]
+    fn test_set_global_define() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_global_define("QT_NO_EXCEPTIONS", "");
+        config.set_global_define("QT_CARGO_TEST_HEAP", "1");
+        config.set_global_define("QT_CARGO_TEST_HEAP", "2");
+        config.remove_global_define("QT_LARGEFILE_SUPPORT");
+
+        let (mut builder, temp) = util::test_builder();
+        let no_qt_path: Option<&str> = None;
+        write_configuration(&config, temp.path(), no_qt_path).unwrap();
+        let qconfig = fs::read_to_string(temp.path().join("QtCore/qconfig.h")).unwrap();
+        assert!(!qconfig.contains("QT_LARGEFILE_SUPPORT"));
+
+        // The defines reach code compiled against the configuration
+        let source = r#"
+            #include <QtCore/qconfig.h>
+            #if !defined(QT_NO_EXCEPTIONS) || QT_CARGO_TEST_HEAP != 2
+            #error "allocator defines not set"
+            #endif
+        "#;
+        builder.include(temp.path());
+        util::try_compile(&builder, source, &[]).unwrap();
+    }
+
+    #[test]
+    fn test_set_timezone_backend() {
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        let sources = [
+            "time/qtimezoneprivate_tz.cpp",
+            "time/qtimezoneprivate_icu.cpp",
+        ];
+
+        config.set_timezone_backend(TimezoneBackend::Icu);
+        assert_eq!(
+            (config.feature("timezone"), config.feature("icu")),
+            (Some(true), Some(true))
+        );
+        assert_eq!(crate::sources::filter_sources(&sources, &config), sources);
+
+        config.set_timezone_backend(TimezoneBackend::System);
+        assert_eq!(config.feature("icu"), Some(false));
+        assert_eq!(
+            crate::sources::filter_sources(&sources, &config),
+            ["time/qtimezoneprivate_tz.cpp"]
+        );
+
+        config.set_timezone_backend(TimezoneBackend::Disabled);
+        assert_eq!(config.feature("timezone"), Some(false));
+        assert!(crate::sources::filter_sources(&sources, &config).is_empty());
+    }
+
+    #[test]
+    fn test_locale_and_timezone_backends() {
+        // ICU stays enabled while either backend uses it, in either call order
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_locale_backend(LocaleBackend::Icu);
+        config.set_timezone_backend(TimezoneBackend::System);
+        assert_eq!(config.feature("icu"), Some(true));
+        assert_eq!(config.locale_backend(), LocaleBackend::Icu);
+
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        config.set_timezone_backend(TimezoneBackend::Icu);
+        config.set_locale_backend(LocaleBackend::Builtin);
+        assert_eq!(config.feature("icu"), Some(true));
+        assert_eq!(config.locale_backend(), LocaleBackend::Builtin);
+
+        config.set_timezone_backend(TimezoneBackend::System);
+        assert_eq!(config.feature("icu"), Some(false));
+    }
+
+    #[test]
+    fn test_write_qplatformdefs_forwarding_header() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let qt_source = temp.path().join("qt-src");
+        let mkspec_path = qt_source.join("qtbase/mkspecs/linux-g++");
+        std::fs::create_dir_all(&mkspec_path).unwrap();
+        std::fs::write(mkspec_path.join("qplatformdefs.h"), "").unwrap();
+
+        let destination = temp.path().join("config/QtCore");
+        write_qplatformdefs_forwarding_header(&destination, &qt_source, "linux-g++").unwrap();
+
+        let include = fs::read_to_string(destination.join("qplatformdefs.h")).unwrap();
+        assert_eq!(
+            include,
+            "#include \"../../qt-src/qtbase/mkspecs/linux-g++/qplatformdefs.h\"\n"
+        );
+        assert_eq!(read_dir(&destination).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_forwarding_headers_with_overrides() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        let destination = temp.path().join("forwarding");
+        let patched = temp.path().join("patched");
+        let original_content = "class Q_CORE_EXPORT QFoo { };\n";
+        let patched_content = "class Q_CORE_EXPORT QFoo { int patched; };\n";
+        std::fs::create_dir_all(source.join("global")).unwrap();
+        std::fs::write(source.join("global/qfoo.h"), original_content).unwrap();
+
+        let overrides = vec![(PathBuf::from("global/qfoo.h"), patched_content.to_string())];
+        write_all_forwarding_headers_with_options(
+            &source,
+            &destination,
+            ForwardingHeaderOptions {
+                header_overrides: overrides,
+                patched_headers_path: Some(patched.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let patched_header = patched.join("global/qfoo.h");
+        assert_eq!(
+            fs::read_to_string(&patched_header).unwrap(),
+            patched_content
+        );
+        assert_eq!(
+            fs::read_to_string(source.join("global/qfoo.h")).unwrap(),
+            original_content
+        );
+        for forwarding_header in ["qfoo.h", "QFoo"] {
+            let include = fs::read_to_string(destination.join(forwarding_header)).unwrap();
+            assert_eq!(include, "#include \"../patched/global/qfoo.h\"\n");
+        }
+    }
+
+    #[test]
+    fn test_exported_class_forwarding_headers() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join("qfoo.h"),
+            "class Q_CORE_EXPORT QFoo { };\nclass QFooHelper { };\nclass Q_DECL_HIDDEN QBar { };\n",
+        )
+        .unwrap();
+
+        let class_headers = |class_filter| {
+            let destination = temp.path().join(format!("{:?}", class_filter));
+            write_all_forwarding_headers_with_options(
+                &source,
+                &destination,
+                ForwardingHeaderOptions {
+                    class_filter,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            ["QFoo", "QFooHelper", "QBar"]
+                .into_iter()
+                .filter(|class| destination.join(class).exists())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            class_headers(ClassHeaderFilter::AllClasses),
+            ["QFoo", "QFooHelper", "QBar"]
+        );
+        assert_eq!(class_headers(ClassHeaderFilter::ExportedClasses), ["QFoo"]);
+    }
+
+    #[test]
+    fn test_type_macro_forwarding_headers() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join("qfooflags.h"),
+            "#define Q_DECLARE_FLAGS(Flags, Enum) typedef QFlags<Enum> Flags;\n\
+             enum QFooFlag { QFooNone = 0 };\n\
+             Q_DECLARE_FLAGS(QFooFlags, QFooFlag)\n\
+             class Q_CORE_EXPORT QFoo\n{\n    Q_DECLARE_FLAGS(Options, QFooFlag)\n};\n",
+        )
+        .unwrap();
+
+        let class_headers = |class_filter| {
+            let destination = temp.path().join(format!("{:?}", class_filter));
+            write_all_forwarding_headers_with_options(
+                &source,
+                &destination,
+                ForwardingHeaderOptions {
+                    class_filter,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let mut headers: Vec<String> = fs::read_dir(&destination)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.contains('.') && name != "private")
+                .collect();
+            headers.sort();
+            headers
+        };
+        assert_eq!(class_headers(ClassHeaderFilter::AllClasses), ["QFoo"]);
+        assert_eq!(
+            class_headers(ClassHeaderFilter::AllClassesAndTypeMacros),
+            ["QFoo", "QFooFlags"]
+        );
+        assert!(
+            fs::read_to_string(temp.path().join("AllClassesAndTypeMacros/QFooFlags"))
+                .unwrap()
+                .contains("qfooflags.h")
+        );
+    }
+
+    #[test]
+    fn test_write_qt_classes_module() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        std::fs::create_dir_all(source.join("kernel")).unwrap();
+        std::fs::create_dir_all(source.join("text")).unwrap();
+        std::fs::write(
+            source.join("kernel/qobject.h"),
+            "class QObjectData;\nclass Q_CORE_EXPORT QObject\n{\n};\n",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("text/qstring.h"),
+            "class Q_CORE_EXPORT QString { };\nclass Q_CORE_EXPORT QObject;\n",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("kernel/qobject_p.h"),
+            "class Q_CORE_EXPORT QObjectPrivate { };\n",
+        )
+        .unwrap();
+
+        let classes =
+            find_qt_classes_in_headers(&source, ClassHeaderFilter::ExportedClasses).unwrap();
+        assert_eq!(classes, ["QObject", "QString"]);
+        assert!(matches!(
+            find_qt_classes_in_headers(temp.path().join("missing"), ClassHeaderFilter::AllClasses),
+            Err(QtBuildError::MissingSource { .. })
+        ));
+
+        let module_path = temp.path().join("out/qt_classes.rs");
+        write_qt_classes_module(&module_path, &classes).unwrap();
+        assert_eq!(
+            fs::read_to_string(&module_path).unwrap(),
+            "// Generated by qt-cargo-base: the Qt classes found in the Qt headers.\n\
+             pub const QT_CLASSES: &[&str] = &[\n    \"QObject\",\n    \"QString\",\n];\n"
+        );
+    }
+
+    #[test]
+    fn test_quoted_with_fallback_style() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source_root = temp.path().join("a/b/c/src");
+        let header = source_root.join("corelib/qfoo.h");
+        std::fs::create_dir_all(header.parent().unwrap()).unwrap();
+        std::fs::write(&header, "").unwrap();
+        let near = temp.path().join("a/b/c/out");
+        let distant = temp.path().join("x/y/z/out");
+        std::fs::create_dir_all(&near).unwrap();
+        std::fs::create_dir_all(&distant).unwrap();
+
+        let style = |fallback| IncludeStyle::QuotedWithFallback {
+            max_parent_dirs: 1,
+            fallback,
+        };
+        let include_statement = |forwarding_header: &Path, style: &IncludeStyle| {
+            write_forwarding_header_with_style(forwarding_header, &header, style).unwrap();
+            fs::read_to_string(forwarding_header).unwrap()
+        };
+        assert_eq!(
+            include_statement(&near.join("qfoo.h"), &style(DistantHeaderFallback::Error)),
+            "#include \"../src/corelib/qfoo.h\"\n"
+        );
+        assert_eq!(
+            include_statement(
+                &distant.join("qfoo.h"),
+                &style(DistantHeaderFallback::VirtualRoot {
+                    source_root: source_root.clone(),
+                    virtual_root: PathBuf::from("/qt-src"),
+                })
+            ),
+            "#include \"/qt-src/corelib/qfoo.h\"\n"
+        );
+        let absolute = include_statement(
+            &distant.join("qfoo.h"),
+            &style(DistantHeaderFallback::Absolute),
+        );
+        assert_eq!(
+            absolute,
+            format!(
+                "#include \"{}\"\n",
+                util::safe_canonicalize(&header).unwrap().display()
+            )
+        );
+
+        let distant_header = distant.join("qfoo_error.h");
+        let result = write_forwarding_header_with_style(
+            &distant_header,
+            &header,
+            &style(DistantHeaderFallback::Error),
+        );
+        assert!(matches!(result, Err(QtBuildError::DistantHeader { .. })));
+        assert!(!distant_header.exists());
+    }
+
+    #[test]
+    fn test_write_class_forwarding_headers_for() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        std::fs::create_dir_all(source.join("kernel")).unwrap();
+        std::fs::create_dir_all(source.join("text")).unwrap();
+        std::fs::write(
+            source.join("kernel/qobject.h"),
+            "class Q_CORE_EXPORT QObject { };\nclass QObjectUserData { };\n",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("text/qstring.h"),
+            "class Q_CORE_EXPORT QString { };\nclass Q_CORE_EXPORT QChar { };\n",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("text/qbytearray.h"),
+            "class Q_CORE_EXPORT QByteArray { };\nclass QByteRef { };\n",
+        )
+        .unwrap();
+
+        let destination = temp.path().join("forwarding");
+        write_class_forwarding_headers_for(
+            &destination,
+            &source,
+            &["QString", "QByteArray", "QObject"],
+        )
+        .unwrap();
+        let mut written: Vec<String> = fs::read_dir(&destination)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        written.sort();
+        assert_eq!(written, ["QByteArray", "QObject", "QString"]);
+        assert!(fs::read_to_string(destination.join("QString"))
+            .unwrap()
+            .contains("qstring.h"));
+
+        let result = write_class_forwarding_headers_for(
+            temp.path().join("forwarding_missing"),
+            &source,
+            &["QString", "QVariant"],
+        );
+        match result {
+            Err(QtBuildError::MissingClasses { classes }) => assert_eq!(classes, ["QVariant"]),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_class_forwarding_headers_cached() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let header = temp.path().join("qfoo.h");
+        let destination = temp.path().join("forwarding");
+        let cache_path = temp.path().join("class_headers.cache");
+        std::fs::write(&header, "class Q_CORE_EXPORT QFoo { };\n").unwrap();
+        let headers = vec![header.clone()];
+
+        let write_cached = || {
+            let mut cache = ClassHeaderCache::load(&cache_path);
+            let written =
+                write_class_forwarding_headers_cached(&destination, &headers, &mut cache).unwrap();
+            cache.save().unwrap();
+            written
+        };
+        assert_eq!(write_cached(), 1);
+        assert!(destination.join("QFoo").exists());
+
+        // Rewriting the header with the same content updates the mtime only
+        std::fs::write(&header, "class Q_CORE_EXPORT QFoo { };\n").unwrap();
+        assert_eq!(write_cached(), 0);
+
+        std::fs::write(&header, "class Q_CORE_EXPORT QFoo { };\nclass QBar { };\n").unwrap();
+        assert_eq!(write_cached(), 1);
+        assert!(destination.join("QBar").exists());
+
+        // Removing a class deletes its class forwarding header
+        std::fs::write(&header, "class QBar { };\n").unwrap();
+        assert_eq!(write_cached(), 1);
+        assert!(!destination.join("QFoo").exists());
+        assert!(destination.join("QBar").exists());
+    }
+
+    #[test]
+    fn test_write_all_forwarding_headers_cached() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        let destination = temp.path().join("QtCore");
+        let cache_path = temp.path().join("class_headers.cache");
+        std::fs::create_dir_all(source.join("kernel")).unwrap();
+        std::fs::write(
+            source.join("kernel/qfoo.h"),
+            "class Q_CORE_EXPORT QFoo { };\n",
+        )
+        .unwrap();
+        std::fs::write(
+            source.join("kernel/qbar.h"),
+            "class Q_CORE_EXPORT QBar { };\n",
+        )
+        .unwrap();
+
+        let write_cached = || {
+            let mut cache = ClassHeaderCache::load(&cache_path);
+            write_all_forwarding_headers_with_options(
+                &source,
+                &destination,
+                ForwardingHeaderOptions {
+                    cache: Some(&mut cache),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            cache.save().unwrap();
+        };
+        write_cached();
+        assert!(destination.join("QFoo").exists());
+        assert!(destination.join("QBar").exists());
+
+        // An unchanged header is not scanned again: its class header is left as is
+        fs::write(destination.join("QBar"), "// unchanged\n").unwrap();
+        write_cached();
+        assert_eq!(
+            fs::read_to_string(destination.join("QBar")).unwrap(),
+            "// unchanged\n"
+        );
+
+        // A class moved to another header keeps its class header, and the class
+        // headers of removed headers are deleted
+        std::fs::write(
+            source.join("kernel/qbar.h"),
+            "class Q_CORE_EXPORT QBar { };\nclass Q_CORE_EXPORT QFoo { };\n",
+        )
+        .unwrap();
+        std::fs::write(source.join("kernel/qfoo.h"), "").unwrap();
+        write_cached();
+        assert!(destination.join("QFoo").exists());
+        std::fs::remove_file(source.join("kernel/qbar.h")).unwrap();
+        write_cached();
+        assert!(!destination.join("QFoo").exists());
+        assert!(!destination.join("QBar").exists());
+    }
+
+    #[test]
+    fn test_class_header_cache_escaping() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let header = temp.path().join("with\ttab\\qfoo.h");
+        let destination = temp.path().join("forwarding");
+        let cache_path = temp.path().join("class_headers.cache");
+        std::fs::write(&header, "class Q_CORE_EXPORT QFoo { };\n").unwrap();
+        let headers = vec![header.clone()];
+
+        let write_cached = || {
+            let mut cache = ClassHeaderCache::load(&cache_path);
+            let written =
+                write_class_forwarding_headers_cached(&destination, &headers, &mut cache).unwrap();
+            cache.save().unwrap();
+            written
+        };
+        assert_eq!(write_cached(), 1);
+        assert_eq!(write_cached(), 0);
+        assert!(ClassHeaderCache::load(&cache_path)
+            .entries
+            .contains_key(&header));
+    }
+
+    #[test]
+    fn test_class_header_cache_with_transform() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        let destination = temp.path().join("QtCore");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("qfoo.h"), "class Q_CORE_EXPORT QFoo { };\n").unwrap();
+
+        // The transform changes the content, so the cache is not used
+        let mut cache = ClassHeaderCache::load(temp.path().join("class_headers.cache"));
+        write_all_forwarding_headers_with_options(
+            &source,
+            &destination,
+            ForwardingHeaderOptions {
+                transform: Some(Box::new(|include: &str| {
+                    format!("// transformed\n{}", include)
+                })),
+                cache: Some(&mut cache),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(cache.entries.is_empty());
+        assert!(fs::read_to_string(destination.join("QFoo"))
+            .unwrap()
+            .starts_with("// transformed\n"));
+    }
+
+    #[test]
+    fn test_write_subsystem_umbrella_headers() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        let destination = temp.path().join("QtCore");
+        for header in ["io/qfile.h", "io/qdir.h", "io/qfile_p.h", "text/qstring.h"] {
+            let header_path = source.join(header);
+            std::fs::create_dir_all(header_path.parent().unwrap()).unwrap();
+            std::fs::write(header_path, "").unwrap();
+        }
+
+        let umbrella_headers =
+            write_subsystem_umbrella_headers(&source, &destination, "QtCore").unwrap();
+        assert_eq!(
+            umbrella_headers,
+            [destination.join("io"), destination.join("text")]
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("io")).unwrap(),
+            "#include <QtCore/qdir.h>\n#include <QtCore/qfile.h>\n"
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("text")).unwrap(),
+            "#include <QtCore/qstring.h>\n"
+        );
+    }
+
+    #[test]
+    fn test_write_forwarding_headers_excluding() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        for header in ["io/qfile.h", "build/io/qfile.h", "tests/qtest_stub.h"] {
+            let header_path = source.join(header);
+            std::fs::create_dir_all(header_path.parent().unwrap()).unwrap();
+            std::fs::write(header_path, "").unwrap();
+        }
+
+        let destination = temp.path().join("default");
+        write_all_forwarding_headers(&source, &destination).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("qfile.h")).unwrap(),
+            "#include \"../corelib/io/qfile.h\"\n"
+        );
+        assert!(!destination.join("qtest_stub.h").exists());
+
+        let destination = temp.path().join("custom");
+        write_all_forwarding_headers_with_options(
+            &source,
+            &destination,
+            ForwardingHeaderOptions {
+                excluded_dirs: &["build"],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(destination.join("qtest_stub.h").exists());
+    }
+
+    #[test]
+    fn test_write_forwarding_headers_with_transform() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("corelib");
+        let destination = temp.path().join("forwarding");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("qfoo.h"), "class Q_CORE_EXPORT QFoo { };\n").unwrap();
+        std::fs::write(source.join("qfoo_p.h"), "").unwrap();
+
+        let banner = "// SPDX-License-Identifier: LGPL-3.0-only\n";
+        write_all_forwarding_headers_with_options(
+            &source,
+            &destination,
+            ForwardingHeaderOptions {
+                transform: Some(Box::new(|include| {
+                    format!("{}#pragma once\n{}", banner, include)
+                })),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for (forwarding_header, target) in [
+            ("qfoo.h", "../corelib/qfoo.h"),
+            ("QFoo", "../corelib/qfoo.h"),
+            ("private/qfoo_p.h", "../../corelib/qfoo_p.h"),
+        ] {
+            let content = fs::read_to_string(destination.join(forwarding_header)).unwrap();
+            assert_eq!(
+                content,
+                format!("{}#pragma once\n#include \"{}\"\n", banner, target)
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_forwarding_header_with_style() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let include_path = temp.path().join("include");
+        let header = include_path.join("QtCore/qfoo.h");
+        let destination = temp.path().join("forwarding");
+        std::fs::create_dir_all(header.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(&header, "").unwrap();
+
+        write_forwarding_header_with_style(
+            destination.join("qfoo.h"),
+            &header,
+            &IncludeStyle::AngleBrackets(include_path),
+        )
+        .unwrap();
+        let content = fs::read_to_string(destination.join("qfoo.h")).unwrap();
+        assert_eq!(content, "#include <QtCore/qfoo.h>\n");
+
+        // The default style is quoted and relative
+        write_forwarding_header_with_style(
+            destination.join("qfoo.h"),
+            &header,
+            &IncludeStyle::default(),
+        )
+        .unwrap();
+        let content = fs::read_to_string(destination.join("qfoo.h")).unwrap();
+        assert_eq!(content, "#include \"../include/QtCore/qfoo.h\"\n");
+    }
+
+    #[test]
+    fn test_qt_src_placeholder() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("qt-src");
+        let corelib = source.join("qtbase/src/corelib");
+        std::fs::create_dir_all(corelib.join("io")).unwrap();
+        std::fs::write(
+            corelib.join("io/qfile.h"),
+            "class Q_CORE_EXPORT QFile { };\n",
+        )
+        .unwrap();
+        let qplatformdefs = source.join(qplatformdefs_path("linux-clang"));
+        std::fs::create_dir_all(qplatformdefs.parent().unwrap()).unwrap();
+        std::fs::write(&qplatformdefs, "").unwrap();
+
+        let generated = temp.path().join("generated");
+        let style = IncludeStyle::qt_src_placeholder(&source);
+        write_all_forwarding_headers_with_options(
+            &corelib,
+            generated.join("QtCore"),
+            ForwardingHeaderOptions {
+                style: style.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut config = QtConfiguration::new();
+        set_default_configuration(&mut config);
+        write_configuration_with_style(&config, generated.join("config"), Some(&source), &style)
+            .unwrap();
+        for (forwarding_header, target) in [
+            ("QtCore/qfile.h", "qtbase/src/corelib/io/qfile.h"),
+            ("QtCore/QFile", "qtbase/src/corelib/io/qfile.h"),
+            (
+                "config/QtCore/qplatformdefs.h",
+                "qtbase/mkspecs/linux-clang/qplatformdefs.h",
+            ),
+        ] {
+            let content = fs::read_to_string(generated.join(forwarding_header)).unwrap();
+            assert_eq!(content, format!("#include \"$QT_SRC/{}\"\n", target));
+        }
+
+        // Relocate the Qt source, and substitute the new location
+        let relocated = temp.path().join("relocated-qt-src");
+        std::fs::rename(&source, &relocated).unwrap();
+        assert_eq!(
+            substitute_qt_src_placeholder(&generated, &relocated).unwrap(),
+            3
+        );
+        let content = fs::read_to_string(generated.join("QtCore/QFile")).unwrap();
+        let target = PathBuf::from(&content["#include \"".len()..content.len() - "\"\n".len()]);
+        assert!(target.exists(), "{}", content);
+        assert_eq!(
+            substitute_qt_src_placeholder(&generated, &relocated).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_write_forwarding_headers_with_virtual_root() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
+        let source = temp.path().join("qt-src");
+        let corelib = source.join("qtbase/src/corelib");
+        let destination = temp.path().join("forwarding");
+        std::fs::create_dir_all(corelib.join("io")).unwrap();
+        std::fs::write(
+            corelib.join("io/qfile.h"),
+            "class Q_CORE_EXPORT QFile { };\n",
+        )
+        .unwrap();
+        std::fs::write(corelib.join("io/qfile_p.h"), "").unwrap();
+
+        let style = IncludeStyle::VirtualRoot {
+            source_root: source.clone(),
+            virtual_root: PathBuf::from("/qt-src"),
+        };
+        write_all_forwarding_headers_with_options(
+            &corelib,
+            &destination,
+            ForwardingHeaderOptions {
+                style: style.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for (forwarding_header, target) in [
+            ("qfile.h", "qfile.h"),
+            ("QFile", "qfile.h"),
+            ("private/qfile_p.h", "qfile_p.h"),
+        ] {
+            let content = fs::read_to_string(destination.join(forwarding_header)).unwrap();
+            assert_eq!(
+                content,
+                format!("#include \"/qt-src/qtbase/src/corelib/io/{}\"\n", target)
+            );
+        }
     }
 
     #[test]
     fn test_write_forwarding_headers() {
-        let temp = tempdir::TempDir::new("qt-cargo-base-configure-test").unwrap();
+        let temp = util::scratch_temp_dir("qt-cargo-base-configure-test").unwrap();
         let qt_path = util::qt_src_path();
 
-        write_all_forwarding_headers(qt_path.join("qtbase/src/corelib"), &temp);
+        write_all_forwarding_headers(qt_path.join("qtbase/src/corelib"), &temp).unwrap();
         let expected_file_count = 523; // for current Qt version and implementation; change as needed.
         assert_eq!(read_dir(&temp).unwrap().count(), expected_file_count);
     }
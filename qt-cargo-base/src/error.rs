@@ -0,0 +1,186 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::util::ToolchainError;
+
+// Errors from configuring a Qt build
+//
+// The functions which write the Qt configuration and the forwarding headers,
+// and the configure_* functions which call them, return QtBuildError instead
+// of panicking, so that callers outside of build.rs (tests, tools) can
+// recover. Build scripts can unwrap: the Display output names the path
+// involved, which is what a build failure log needs.
+//
+// Invalid arguments (an include style which does not match the header location,
+// an unsupported module or PCRE2 code unit width) are errors as well. The
+// functions which compile code with cc::Build::compile still panic, since cc
+// panics on compile errors. Tools run outside of cc (moc, rcc, and the compiler
+// when linking or compiling with a response file) return HostTool.
+
+/// Error type for configuring a Qt build, see configure_qtcore_for_linux
+#[derive(Debug)]
+pub enum QtBuildError {
+    /// A source file or directory does not exist, for example the Qt source path
+    MissingSource { path: PathBuf },
+    /// An I/O error without a known path, see From<io::Error>
+    Io(io::Error),
+    /// Writing a generated file (config header, forwarding header) or creating its
+    /// directory failed
+    ConfigWrite { path: PathBuf, source: io::Error },
+    /// A header scanned for classes is not valid UTF-8
+    InvalidUtf8Header { path: PathBuf },
+    /// OUT_DIR and the given build path conflict, with STRICT_BUILD_PATH_ENV_VAR set.
+    /// See configure_for_qt_build.
+    BuildPathConflict { message: String },
+    /// A header is too far from its forwarding header for a relative include, with
+    /// IncludeStyle::QuotedWithFallback and DistantHeaderFallback::Error
+    DistantHeader {
+        forwarding_header: PathBuf,
+        target_header: PathBuf,
+        max_parent_dirs: usize,
+    },
+    /// A header can't be included relative to base (an include path or a source root), for
+    /// example with IncludeStyle::AngleBrackets and a header outside the include path
+    UnrelatedHeaderPath { header: PathBuf, base: PathBuf },
+    /// A path which is written to a generated header is not valid UTF-8
+    NonUtf8Path { path: PathBuf },
+    /// An invalid argument or option, for example an unsupported module
+    InvalidArgument { message: String },
+    /// The compiler given with QtBuildOptions::compiler was not found
+    Toolchain(ToolchainError),
+    /// Classes requested with write_class_forwarding_headers_for which are not declared
+    /// in the scanned headers
+    MissingClasses { classes: Vec<String> },
+    /// A host tool such as moc, rcc or the compiler could not be run, or failed for input
+    HostTool {
+        tool: PathBuf,
+        input: PathBuf,
+        message: String,
+    },
+}
+
+impl QtBuildError {
+    /// Returns a closure which maps an io::Error for writing path to ConfigWrite, for
+    /// use with map_err
+    pub(crate) fn config_write<P: Into<PathBuf>>(
+        path: P,
+    ) -> impl FnOnce(io::Error) -> QtBuildError {
+        let path = path.into();
+        move |source| QtBuildError::ConfigWrite { path, source }
+    }
+
+    /// Returns a closure which maps an io::Error for reading path to MissingSource if the
+    /// path does not exist, and to Io otherwise, for use with map_err
+    pub(crate) fn missing_source<P: Into<PathBuf>>(
+        path: P,
+    ) -> impl FnOnce(io::Error) -> QtBuildError {
+        let path = path.into();
+        move |source| match source.kind() {
+            io::ErrorKind::NotFound => QtBuildError::MissingSource { path },
+            _ => QtBuildError::Io(source),
+        }
+    }
+}
+
+impl fmt::Display for QtBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QtBuildError::MissingSource { path } => write!(f, "missing Qt source {:?}", path),
+            QtBuildError::Io(source) => write!(f, "I/O error: {}", source),
+            QtBuildError::ConfigWrite { path, source } => {
+                write!(f, "unable to write {:?}: {}", path, source)
+            }
+            QtBuildError::InvalidUtf8Header { path } => {
+                write!(f, "header {:?} is not valid UTF-8", path)
+            }
+            QtBuildError::BuildPathConflict { message } => write!(f, "{}", message),
+            QtBuildError::DistantHeader {
+                forwarding_header,
+                target_header,
+                max_parent_dirs,
+            } => write!(
+                f,
+                "header {:?} is too far from forwarding header {:?} for a relative include \
+                 (at most {} parent directories)",
+                target_header, forwarding_header, max_parent_dirs
+            ),
+            QtBuildError::UnrelatedHeaderPath { header, base } => {
+                write!(
+                    f,
+                    "header {:?} can't be included relative to {:?}",
+                    header, base
+                )
+            }
+            QtBuildError::NonUtf8Path { path } => write!(f, "path {:?} is not valid UTF-8", path),
+            QtBuildError::InvalidArgument { message } => write!(f, "{}", message),
+            QtBuildError::Toolchain(error) => write!(f, "{}", error),
+            QtBuildError::MissingClasses { classes } => {
+                write!(
+                    f,
+                    "classes not found in the Qt headers: {}",
+                    classes.join(", ")
+                )
+            }
+            QtBuildError::HostTool {
+                tool,
+                input,
+                message,
+            } => write!(f, "{:?} failed for {:?}: {}", tool, input, message),
+        }
+    }
+}
+
+impl std::error::Error for QtBuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QtBuildError::Io(source) | QtBuildError::ConfigWrite { source, .. } => Some(source),
+            QtBuildError::Toolchain(error) => Some(error),
+            QtBuildError::MissingSource { .. }
+            | QtBuildError::InvalidUtf8Header { .. }
+            | QtBuildError::BuildPathConflict { .. }
+            | QtBuildError::DistantHeader { .. }
+            | QtBuildError::UnrelatedHeaderPath { .. }
+            | QtBuildError::HostTool { .. }
+            | QtBuildError::NonUtf8Path { .. }
+            | QtBuildError::InvalidArgument { .. }
+            | QtBuildError::MissingClasses { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for QtBuildError {
+    fn from(error: io::Error) -> QtBuildError {
+        QtBuildError::Io(error)
+    }
+}
+
+impl From<ToolchainError> for QtBuildError {
+    fn from(error: ToolchainError) -> QtBuildError {
+        QtBuildError::Toolchain(error)
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_error_tests {
+    use super::*;
+
+    #[test]
+    fn test_qt_build_error() {
+        fn read_missing() -> Result<Vec<u8>, QtBuildError> {
+            Ok(std::fs::read("/nonexistent/qt-cargo-base/qglobal.h")?)
+        }
+        let error = read_missing().unwrap_err();
+        assert!(matches!(error, QtBuildError::Io(_)));
+        assert!(std::error::Error::source(&error).is_some());
+
+        let error = QtBuildError::config_write("QtCore/qconfig.h")(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "denied",
+        ));
+        assert_eq!(
+            error.to_string(),
+            "unable to write \"QtCore/qconfig.h\": denied"
+        );
+    }
+}
@@ -266,3 +266,263 @@ pub fn qt_core_defines() -> Vec<(&'static str, &'static str)> {
         ("QT_NO_INOTIFY", "1"),
     ]
 }
+
+// QtTest features, for QtTest/qttestlib-config.h. See
+// QtConfiguration::set_testlib. The valgrind benchmark backend needs the
+// valgrind headers, and is disabled.
+pub fn qt_testlib_features() -> Vec<(&'static str, bool)> {
+    vec![("itemmodeltester", true), ("batch_test_support", false)]
+}
+
+pub fn qt_testlib_private_features() -> Vec<(&'static str, bool)> {
+    vec![("testlib_selfcover", false), ("valgrind", false)]
+}
+
+// Feature values which differ from the tables above for Windows with MSVC
+// (the win32-msvc mkspec), see configure::set_windows_msvc_configuration.
+// These are the platform detection features: the POSIX and glibc
+// functions, and the GCC specific build options. Features which are not
+// listed have the same value as on linux.
+pub fn windows_msvc_feature_overrides() -> Vec<(&'static str, bool)> {
+    vec![
+        ("reduce_exports", false),
+        ("reduce_relocations", false),
+        ("alloca_h", false),
+        ("alloca_malloc_h", true),
+        ("dlopen", false),
+        ("system_zlib", false),
+        ("eventfd", false),
+        ("glib", false),
+        ("inotify", false),
+        ("clock_gettime", false),
+        ("futimens", false),
+        ("glibc", false),
+        ("poll_poll", false),
+        ("backtrace", false),
+        ("forkfd_pidfd", false),
+    ]
+}
+
+// Feature values which differ from the tables above for macOS (the
+// macx-clang mkspec), see configure::set_macos_configuration. Darwin is
+// POSIX, so most of the unix features are shared with linux; the
+// differences are the glibc and linux specific APIs, and the ELF specific
+// build options.
+pub fn macos_feature_overrides() -> Vec<(&'static str, bool)> {
+    vec![
+        ("reduce_relocations", false),
+        ("glib", false),
+        ("glibc", false),
+        ("eventfd", false),
+        ("inotify", false),
+        ("getauxval", false),
+        ("forkfd_pidfd", false),
+    ]
+}
+
+// Feature values which differ from the tables above for Android (the
+// android-clang mkspec), see configure::set_android_configuration. Android
+// uses the bionic libc: no glibc or glib, no System V IPC (which QSharedMemory
+// and QSystemSemaphore use), and no backtrace() before API level 33.
+pub fn android_feature_overrides() -> Vec<(&'static str, bool)> {
+    vec![
+        ("cross_compile", true),
+        ("reduce_relocations", false),
+        ("glib", false),
+        ("glibc", false),
+        ("sharedmemory", false),
+        ("systemsemaphore", false),
+        ("backtrace", false),
+        ("forkfd_pidfd", false),
+    ]
+}
+
+// The D-Bus features QtCore's configuration carries. QtCore itself contains no D-Bus
+// code; the features are read by QtDBus and by the platform integration code which uses
+// it. "dbus" (qconfig.h) enables D-Bus support, "dbus_linked" (qconfig_p.h) links
+// libdbus-1 at build time instead of loading it at run time. See
+// QtConfiguration::set_dbus.
+pub const DBUS_FEATURES: [&str; 2] = ["dbus", "dbus_linked"];
+
+// Short descriptions of the features, for build output and documentation.
+// Covers the features which are commonly toggled with
+// QtConfiguration::set_feature, not the compiler and platform detection
+// features.
+pub fn feature_descriptions() -> Vec<(&'static str, &'static str)> {
+    vec![
+        (
+            "thread",
+            "QThread with a separate thread of execution, and the locking primitives",
+        ),
+        ("future", "QFuture, QPromise and QFutureWatcher"),
+        ("concurrent", "The Qt Concurrent module"),
+        ("dbus", "The Qt D-Bus module"),
+        (
+            "doubleconversion",
+            "Locale independent conversion between doubles and strings",
+        ),
+        ("glib", "Event dispatcher based on the GLib main loop"),
+        ("inotify", "File system watching using inotify"),
+        ("eventfd", "Wakeup of event loops using eventfd"),
+        (
+            "sharedmemory",
+            "QSharedMemory, memory shared between processes",
+        ),
+        (
+            "systemsemaphore",
+            "QSystemSemaphore, semaphores shared between processes",
+        ),
+        (
+            "process",
+            "QProcess, starting and communicating with external programs",
+        ),
+        ("processenvironment", "QProcessEnvironment"),
+        (
+            "textdate",
+            "Date and time strings with textual month and day names",
+        ),
+        (
+            "datestring",
+            "Conversion of dates and times to and from strings",
+        ),
+        (
+            "datetimeparser",
+            "Parsing of dates and times from strings with a format",
+        ),
+        ("jalalicalendar", "The Jalali (Persian) calendar"),
+        ("islamiccivilcalendar", "The Islamic civil calendar"),
+        ("hijricalendar", "Base support for the Hijri calendars"),
+        ("timezone", "QTimeZone, time zone support"),
+        ("icu", "Use ICU for time zones and other locale data"),
+        ("xmlstream", "XML stream reading and writing"),
+        ("xmlstreamreader", "QXmlStreamReader"),
+        ("xmlstreamwriter", "QXmlStreamWriter"),
+        (
+            "cborstreamreader",
+            "QCborStreamReader, streaming CBOR decoding",
+        ),
+        (
+            "cborstreamwriter",
+            "QCborStreamWriter, streaming CBOR encoding",
+        ),
+        (
+            "itemmodel",
+            "QAbstractItemModel and the model/view base classes",
+        ),
+        ("proxymodel", "QAbstractProxyModel"),
+        ("sortfilterproxymodel", "QSortFilterProxyModel"),
+        ("identityproxymodel", "QIdentityProxyModel"),
+        ("transposeproxymodel", "QTransposeProxyModel"),
+        (
+            "concatenatetablesproxymodel",
+            "QConcatenateTablesProxyModel",
+        ),
+        ("stringlistmodel", "QStringListModel"),
+        ("translation", "QTranslator, loading of translations"),
+        ("easingcurve", "QEasingCurve"),
+        (
+            "animation",
+            "The animation framework (QPropertyAnimation and friends)",
+        ),
+        ("gestures", "Gesture event support"),
+        ("commandlineparser", "QCommandLineParser"),
+        ("temporaryfile", "QTemporaryFile"),
+        (
+            "library",
+            "QLibrary and QPluginLoader, loading of shared libraries",
+        ),
+        ("settings", "QSettings, persistent application settings"),
+        ("filesystemwatcher", "QFileSystemWatcher"),
+        (
+            "filesystemiterator",
+            "Fast directory iteration using the native file system API",
+        ),
+        ("mimetype", "QMimeDatabase, MIME type detection"),
+        (
+            "mimetype_database",
+            "Built-in copy of the freedesktop.org MIME database",
+        ),
+        ("properties", "Dynamic properties with QObject::setProperty"),
+        ("regularexpression", "QRegularExpression, using PCRE2"),
+        ("shortcut", "QKeySequence and shortcut support"),
+        (
+            "topleveldomain",
+            "Top level domain checks for QUrl, using the public suffix list",
+        ),
+        ("journald", "Logging to the systemd journal"),
+        ("syslog", "Logging to syslog"),
+        ("backtrace", "Backtraces in fatal error messages"),
+        (
+            "system_pcre2",
+            "Use the system PCRE2 instead of the bundled copy",
+        ),
+        (
+            "system_doubleconversion",
+            "Use the system double-conversion instead of the bundled copy",
+        ),
+    ]
+}
+
+/// Returns a short description of the named feature, for example "thread", or None
+/// for features without a description.
+pub fn feature_description(name: &str) -> Option<&'static str> {
+    feature_descriptions()
+        .into_iter()
+        .find(|(feature, _)| *feature == name)
+        .map(|(_, description)| description)
+}
+
+// Dependencies between the features, as (feature, required features). A
+// feature which is enabled while one of its required features is disabled
+// gives build errors, see QtConfiguration::validate_feature_dependencies.
+//
+// QThreadPool is part of "thread" (there is no separate feature for it),
+// "future" adds QFuture and QPromise on top, and "concurrent" (the
+// QtConcurrent map/filter/run machinery) builds on both.
+pub fn feature_dependencies() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![
+        ("future", &["thread"]),
+        ("concurrent", &["thread", "future"]),
+        ("process", &["processenvironment"]),
+        ("xmlstreamreader", &["xmlstream"]),
+        ("xmlstreamwriter", &["xmlstream"]),
+        ("sortfilterproxymodel", &["proxymodel"]),
+        ("identityproxymodel", &["proxymodel"]),
+        ("transposeproxymodel", &["proxymodel"]),
+        ("proxymodel", &["itemmodel"]),
+        ("stringlistmodel", &["itemmodel"]),
+        ("concatenatetablesproxymodel", &["itemmodel"]),
+        ("animation", &["properties", "easingcurve"]),
+        ("datetimeparser", &["datestring"]),
+        ("mimetype_database", &["mimetype"]),
+    ]
+}
+
+#[cfg(test)]
+mod qt_cargo_base_features_tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_description() {
+        assert!(feature_description("thread").unwrap().contains("QThread"));
+        assert!(feature_description("regularexpression")
+            .unwrap()
+            .contains("PCRE2"));
+        assert_eq!(feature_description("no_such_feature"), None);
+
+        // Every described feature is a known feature
+        let known: Vec<&str> = [
+            global_features(),
+            qt_bootstrap_features(),
+            qt_core_features(),
+            qt_core_private_features(),
+        ]
+        .concat()
+        .into_iter()
+        .map(|(feature, _)| feature)
+        .collect();
+        for (feature, _) in feature_descriptions() {
+            assert!(known.contains(&feature), "{}", feature);
+        }
+    }
+}
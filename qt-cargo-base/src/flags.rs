@@ -0,0 +1,149 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// Build flags inspection
+//
+// cc::Build does not expose the configured include paths, defines and
+// flags. BuildFlags reconstructs them from the compiler command line
+// the builder would use, and can apply an edited set of flags to a new
+// builder. This is an escape hatch for toolchains which need a flag
+// removed or replaced after the crate has configured the build.
+
+/// The compiler, include paths, defines and other flags of a configured cc::Build
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildFlags {
+    pub compiler: PathBuf,
+    /// Include paths, in search order
+    pub include_paths: Vec<PathBuf>,
+    /// Defines, as (key, optional value)
+    pub defines: Vec<(String, Option<String>)>,
+    /// All other flags, including the default flags added by cc
+    pub flags: Vec<String>,
+}
+
+impl BuildFlags {
+    /// Creates BuildFlags from compiler arguments
+    pub fn from_args<I>(args: I) -> BuildFlags
+    where
+        I: IntoIterator<Item = OsString>,
+    {
+        let mut build_flags = BuildFlags::default();
+        let mut args = args
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().into_owned());
+        while let Some(arg) = args.next() {
+            if arg == "-I" {
+                if let Some(include_path) = args.next() {
+                    build_flags.include_paths.push(include_path.into());
+                }
+            } else if let Some(include_path) = arg.strip_prefix("-I") {
+                build_flags.include_paths.push(include_path.into());
+            } else if let Some(define) = arg.strip_prefix("-D") {
+                let (key, value) = match define.split_once('=') {
+                    Some((key, value)) => (key, Some(value.to_string())),
+                    None => (define, None),
+                };
+                build_flags.defines.push((key.to_string(), value));
+            } else {
+                build_flags.flags.push(arg);
+            }
+        }
+        build_flags
+    }
+
+    pub fn remove_include_path<P: Into<PathBuf>>(&mut self, include_path: P) {
+        let include_path = include_path.into();
+        self.include_paths.retain(|path| *path != include_path);
+    }
+
+    /// Sets the define, replacing any existing define with the same key
+    pub fn set_define(&mut self, key: &str, value: Option<&str>) {
+        self.remove_define(key);
+        self.defines
+            .push((key.to_string(), value.map(|value| value.to_string())));
+    }
+
+    pub fn remove_define(&mut self, key: &str) {
+        self.defines.retain(|(define, _)| define != key);
+    }
+
+    pub fn remove_flag(&mut self, flag: &str) {
+        self.flags.retain(|existing| existing != flag);
+    }
+
+    /// Replaces all occurrences of the flag with the new flag
+    pub fn replace_flag(&mut self, flag: &str, new_flag: &str) {
+        for existing in self.flags.iter_mut().filter(|existing| *existing == flag) {
+            *existing = new_flag.to_string();
+        }
+    }
+
+    /// Applies the flags to the given builder, which should be a new cc::Build (with host,
+    /// target and output directory set as needed). The cc default flags are disabled, since
+    /// they are already included in the flags. (cc adds its warning flags regardless, which
+    /// may then appear twice on the compiler command line.)
+    pub fn apply(&self, builder: &mut cc::Build) {
+        builder.no_default_flags(true).compiler(&self.compiler);
+        for include_path in &self.include_paths {
+            builder.include(include_path);
+        }
+        for (key, value) in &self.defines {
+            builder.define(key, value.as_deref());
+        }
+        for flag in &self.flags {
+            builder.flag(flag);
+        }
+    }
+}
+
+/// Returns the compiler, include paths, defines and flags configured for the builder.
+pub fn describe_build_flags(builder: &cc::Build) -> BuildFlags {
+    let compiler = builder.get_compiler();
+    let mut build_flags = BuildFlags::from_args(compiler.args().iter().cloned());
+    build_flags.compiler = compiler.path().to_path_buf();
+    build_flags
+}
+
+#[cfg(test)]
+mod qt_cargo_base_flags_tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_describe_build_flags() {
+        let (mut builder, _build_temp) = util::test_builder();
+        builder
+            .include("/qt/qt_config_headers")
+            .define("QT_NO_DEBUG", None)
+            .define("QT_VERSION_MAJOR", "6")
+            .flag("-std=c++17");
+
+        let mut build_flags = describe_build_flags(&builder);
+        assert_eq!(
+            build_flags.include_paths,
+            vec![PathBuf::from("/qt/qt_config_headers")]
+        );
+        assert!(build_flags
+            .defines
+            .contains(&("QT_NO_DEBUG".to_string(), None)));
+        assert!(build_flags
+            .defines
+            .contains(&("QT_VERSION_MAJOR".to_string(), Some("6".to_string()))));
+        assert!(build_flags.flags.contains(&"-std=c++17".to_string()));
+
+        build_flags.remove_define("QT_NO_DEBUG");
+        build_flags.replace_flag("-std=c++17", "-std=c++20");
+        let (mut new_builder, _new_build_temp) = util::test_builder();
+        build_flags.apply(&mut new_builder);
+
+        let new_build_flags = describe_build_flags(&new_builder);
+        assert_eq!(new_build_flags.include_paths, build_flags.include_paths);
+        assert_eq!(new_build_flags.defines, build_flags.defines);
+        assert!(!new_build_flags
+            .defines
+            .iter()
+            .any(|(key, _)| key == "QT_NO_DEBUG"));
+        assert!(new_build_flags.flags.contains(&"-std=c++20".to_string()));
+        assert!(!new_build_flags.flags.contains(&"-std=c++17".to_string()));
+    }
+}
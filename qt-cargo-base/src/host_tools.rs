@@ -0,0 +1,293 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::QtBuildError;
+
+// Running the Qt host tools
+//
+// Helpers for running host tools such as moc from a build script, with
+// the output written to a given directory. The generated files are
+// typically compiled in a second cc::Build, which needs to be able to
+// find the original header, so the include statement moc writes for it
+// can be controlled.
+//
+// Resource files (.qrc) are compiled into C++ sources with rcc, which
+// embed the listed files and register them with the Qt resource system
+// (":/path" file names) when linked in. The resource system is always
+// part of QtCore, see sources::QTCORE_FILE_ENGINE_SOURCES. rcc is not
+// built from this tree, so rcc_path must point to an existing rcc.
+
+/// Options for run_moc
+#[derive(Clone, Debug, Default)]
+pub struct MocOptions {
+    /// Directory where the moc_<name>.cpp file is written
+    pub output_dir: PathBuf,
+    /// Include paths for moc, for example the QtBuildPaths include paths. moc uses these to
+    /// resolve includes in the header, and they should also be used when compiling the
+    /// generated file.
+    pub include_paths: Vec<PathBuf>,
+    /// Include prefix for the original header. If set, the generated file includes the
+    /// header as "<prefix>/<header file name>", which must resolve using the include paths
+    /// of the build compiling the generated file. If not set, moc includes the header
+    /// using a path relative to the output directory.
+    pub include_prefix: Option<String>,
+}
+
+// Returns a closure which maps an io::Error from running tool to HostTool, for use with
+// map_err
+fn host_tool_error<'a>(
+    tool: &'a Path,
+    input: &'a Path,
+) -> impl FnOnce(io::Error) -> QtBuildError + 'a {
+    move |error| QtBuildError::HostTool {
+        tool: tool.to_path_buf(),
+        input: input.to_path_buf(),
+        message: error.to_string(),
+    }
+}
+
+// Runs command, which runs tool on input, and returns an error with the tool output
+// if it fails
+pub(crate) fn run_host_tool(
+    mut command: Command,
+    tool: &Path,
+    input: &Path,
+) -> Result<(), QtBuildError> {
+    let output = command.output().map_err(host_tool_error(tool, input))?;
+    if !output.status.success() {
+        return Err(QtBuildError::HostTool {
+            tool: tool.to_path_buf(),
+            input: input.to_path_buf(),
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Runs moc (at moc_path) on the header, and returns the path of the generated file,
+/// "moc_<header file stem>.cpp" in the output directory.
+pub fn run_moc(
+    moc_path: &Path,
+    header: &Path,
+    options: &MocOptions,
+) -> Result<PathBuf, QtBuildError> {
+    std::fs::create_dir_all(&options.output_dir)
+        .map_err(QtBuildError::config_write(&options.output_dir))?;
+    let (stem, file_name) = match (header.file_stem(), header.file_name()) {
+        (Some(stem), Some(file_name)) => (stem.to_string_lossy(), file_name.to_string_lossy()),
+        _ => {
+            return Err(QtBuildError::InvalidArgument {
+                message: format!("Invalid header path {:?}", header),
+            })
+        }
+    };
+    let output_path = options.output_dir.join(format!("moc_{}.cpp", stem));
+
+    let mut command = Command::new(moc_path);
+    for include_path in &options.include_paths {
+        command.arg(format!("-I{}", include_path.display()));
+    }
+    if let Some(include_prefix) = &options.include_prefix {
+        command.arg(format!(
+            "-f{}/{}",
+            include_prefix.trim_end_matches('/'),
+            file_name
+        ));
+    }
+    command.arg(header).arg("-o").arg(&output_path);
+    run_host_tool(command, moc_path, header)?;
+    Ok(output_path)
+}
+
+/// Runs rcc (at rcc_path) on the resource file qrc, and writes the generated C++ source to
+/// out_cpp. The resource name (for Q_INIT_RESOURCE) is the file stem of qrc. Files listed
+/// in the resource file are resolved relative to it.
+pub fn compile_qrc(rcc_path: &Path, qrc: &Path, out_cpp: &Path) -> Result<(), QtBuildError> {
+    if let Some(parent) = out_cpp.parent() {
+        std::fs::create_dir_all(parent).map_err(QtBuildError::config_write(parent))?;
+    }
+    let name = qrc
+        .file_stem()
+        .ok_or_else(|| QtBuildError::InvalidArgument {
+            message: format!("Invalid resource file path {:?}", qrc),
+        })?;
+    let mut command = Command::new(rcc_path);
+    command
+        .arg("-name")
+        .arg(name)
+        .arg("-o")
+        .arg(out_cpp)
+        .arg(qrc);
+    run_host_tool(command, rcc_path, qrc)
+}
+
+/// Compiles the resource file qrc to "qrc_<file stem>.cpp" in out_dir (see compile_qrc),
+/// and adds the generated source to the builder. Returns the path of the generated file.
+pub fn add_qrc(
+    builder: &mut cc::Build,
+    rcc_path: &Path,
+    qrc: &Path,
+    out_dir: &Path,
+) -> Result<PathBuf, QtBuildError> {
+    let stem = qrc.file_stem().unwrap_or_default().to_string_lossy();
+    let out_cpp = out_dir.join(format!("qrc_{}.cpp", stem));
+    compile_qrc(rcc_path, qrc, &out_cpp)?;
+    println!("cargo:rerun-if-changed={}", qrc.display());
+    builder.file(&out_cpp);
+    Ok(out_cpp)
+}
+
+#[cfg(test)]
+mod qt_cargo_base_host_tools_tests {
+    use super::*;
+    use crate::util;
+
+    // Checks the arguments passed to moc with a stand-in script, see
+    // run_moc_with_bootstrap_moc for running the moc built from qt-src
+    #[test]
+    #[cfg(unix)]
+    fn test_run_moc() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-host-tools-test").unwrap();
+        let headers_path = temp.path().join("include/myapp");
+        std::fs::create_dir_all(&headers_path).unwrap();
+        let header = headers_path.join("widget.h");
+        std::fs::write(&header, "struct Widget { int value = 42; };\n").unwrap();
+
+        // Stand-in for moc which writes the include statement for the -f argument,
+        // as moc does
+        let moc_path = temp.path().join("moc");
+        std::fs::write(
+            &moc_path,
+            "#!/bin/sh\n\
+             for arg; do case $arg in -f*) include=${arg#-f};; esac; done\n\
+             while [ \"$1\" != \"-o\" ]; do shift; done\n\
+             printf '#include \"%s\"\\nint moc_test() { return Widget().value; }\\n' \"$include\" > \"$2\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&moc_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let options = MocOptions {
+            output_dir: temp.path().join("moc_output"),
+            include_paths: vec![temp.path().join("include")],
+            include_prefix: Some("myapp".to_string()),
+        };
+        let generated = run_moc(&moc_path, &header, &options).unwrap();
+        assert_eq!(generated, temp.path().join("moc_output/moc_widget.cpp"));
+
+        // The generated file compiles in a separate build with the same include paths
+        let (mut builder, _build_temp) = util::test_builder();
+        for include_path in &options.include_paths {
+            builder.include(include_path);
+        }
+        let source = std::fs::read_to_string(&generated).unwrap();
+        assert!(source.contains("#include \"myapp/widget.h\""));
+        util::try_compile(&builder, &source, &[]).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires the Qt source in qt-src"]
+    fn run_moc_with_bootstrap_moc() {
+        let qt_source = util::qt_src_path();
+        let temp = util::scratch_temp_dir("qt-cargo-base-host-tools-test").unwrap();
+        let (moc_path, qt_build_paths) =
+            crate::qt_cargo_base_tests::build_moc_executable(&qt_source, temp.path());
+
+        let headers_path = temp.path().join("include/myapp");
+        std::fs::create_dir_all(&headers_path).unwrap();
+        let header = headers_path.join("counter.h");
+        std::fs::write(
+            &header,
+            "#include <QtCore/qobject.h>\n\
+             class Counter : public QObject\n\
+             {\n\
+                 Q_OBJECT\n\
+             public:\n\
+                 void setValue(int value) { emit valueChanged(value); }\n\
+             signals:\n\
+                 void valueChanged(int value);\n\
+             };\n",
+        )
+        .unwrap();
+
+        let mut include_paths = qt_build_paths.include_paths.clone();
+        include_paths.push(temp.path().join("include"));
+        let options = MocOptions {
+            output_dir: temp.path().join("moc_output"),
+            include_paths,
+            include_prefix: Some("myapp".to_string()),
+        };
+        let generated = run_moc(&moc_path, &header, &options).unwrap();
+        let source = std::fs::read_to_string(&generated).unwrap();
+        assert!(source.contains("#include \"myapp/counter.h\""));
+        assert!(source.contains("Counter::staticMetaObject"));
+
+        // The generated file compiles with the configured QtCore include paths
+        let (mut builder, _build_temp) = util::test_builder();
+        crate::configure_qtcore_for_linux(&mut builder, Some(temp.path()), &qt_source).unwrap();
+        builder.include(temp.path().join("include"));
+        util::try_compile(&builder, &source, &[]).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_moc_failure() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-host-tools-test").unwrap();
+        let options = MocOptions {
+            output_dir: temp.path().join("moc_output"),
+            ..Default::default()
+        };
+        let missing_moc = temp.path().join("missing_moc");
+        let error = run_moc(&missing_moc, Path::new("widget.h"), &options).unwrap_err();
+        assert!(matches!(error, QtBuildError::HostTool { tool, .. } if tool == missing_moc));
+        assert!(run_moc(&PathBuf::from("false"), Path::new("widget.h"), &options).is_err());
+    }
+
+    // Checks the arguments passed to rcc, and that the generated file is added to the
+    // builder, with a stand-in script. rcc is not built from qt-src in this tree.
+    #[test]
+    #[cfg(unix)]
+    fn test_compile_qrc() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-host-tools-test").unwrap();
+        let qrc = temp.path().join("resources/app.qrc");
+        std::fs::create_dir_all(qrc.parent().unwrap()).unwrap();
+        std::fs::write(temp.path().join("resources/hello.txt"), "Hello\n").unwrap();
+        std::fs::write(
+            &qrc,
+            "<RCC>\n  <qresource prefix=\"/\">\n    <file>hello.txt</file>\n  </qresource>\n</RCC>\n",
+        )
+        .unwrap();
+
+        // Stand-in for rcc which writes the resource init function for -name, as rcc does
+        let rcc_path = temp.path().join("rcc");
+        std::fs::write(
+            &rcc_path,
+            "#!/bin/sh\n\
+             while [ $# -gt 1 ]; do case $1 in -name) name=$2;; -o) out=$2;; esac; shift; done\n\
+             [ -f \"$1\" ] || { echo \"missing $1\" >&2; exit 1; }\n\
+             printf 'int qInitResources_%s() { return 1; }\\n' \"$name\" > \"$out\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&rcc_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (mut builder, _build_temp) = util::test_builder();
+        let out_dir = temp.path().join("rcc_output");
+        let generated = add_qrc(&mut builder, &rcc_path, &qrc, &out_dir).unwrap();
+        assert_eq!(generated, out_dir.join("qrc_app.cpp"));
+        assert_eq!(
+            builder.get_files().collect::<Vec<_>>(),
+            [generated.as_path()]
+        );
+
+        let source = std::fs::read_to_string(&generated).unwrap();
+        assert!(source.contains("qInitResources_app"));
+        util::try_compile(&builder, &source, &[]).unwrap();
+
+        let missing_qrc = temp.path().join("missing.qrc");
+        assert!(compile_qrc(&rcc_path, &missing_qrc, &out_dir.join("qrc_missing.cpp")).is_err());
+    }
+}
@@ -25,18 +25,79 @@ use std::{
     str::FromStr,
 };
 
-mod configure;
+use error::QtBuildError;
+
+pub mod configure;
+pub mod error;
+pub mod features;
+pub mod flags;
+pub mod host_tools;
+pub mod library;
+pub mod mkspec;
+pub mod modules;
+pub mod pch;
+pub mod plan;
+pub mod sbom;
+pub mod smoke_test;
 pub mod sources;
+pub mod store;
+pub mod summary;
+pub mod target;
+pub mod timing;
+pub mod toolchain;
+pub mod universal;
 pub mod util;
+pub mod version;
+
+/// Environment variable which turns the warning for conflicting OUT_DIR and qt_build_path
+/// locations in configure_for_qt_build into an error
+pub const STRICT_BUILD_PATH_ENV_VAR: &str = "QT_CARGO_STRICT_BUILD_PATH";
+
+// Returns a message if OUT_DIR and qt_build_path are set to divergent locations, that is
+// if neither contains the other. OUT_DIR takes precedence in this case.
+fn build_path_conflict(out_dir: &Path, qt_build_path: &Path) -> Option<String> {
+    let resolve =
+        |path: &Path| util::safe_canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let out_dir = resolve(out_dir);
+    let qt_build_path = resolve(qt_build_path);
+    if out_dir.starts_with(&qt_build_path) || qt_build_path.starts_with(&out_dir) {
+        return None;
+    }
+    Some(format!(
+        "qt_build_path {:?} is ignored: OUT_DIR {:?} is set and takes precedence. \
+         Pass None as the build path from build scripts, or set {} to make this an error.",
+        qt_build_path, out_dir, STRICT_BUILD_PATH_ENV_VAR
+    ))
+}
 
 /// Configures the given cc::Build object for building Qt. qt_build_path can optionally
 /// spesificy where build output files should be placed; if not specified then the OUT_DIR
 /// environment varibale must be set (this will be the case when calling from a build.rs script).
 ///
-/// Returns the path where Qt config headers should be placed.
+/// Returns the path where Qt config headers should be placed, or
+/// QtBuildError::BuildPathConflict if OUT_DIR and qt_build_path conflict in strict mode
+/// (see STRICT_BUILD_PATH_ENV_VAR).
 ///
-/// This function currently hardcodes "x86_64-unknown-linux" as the build target.
-pub fn configure_for_qt_build<P>(builder: &mut cc::Build, qt_build_path: Option<P>) -> PathBuf
+/// Outside of build.rs the builder targets the machine this code runs on, see
+/// configure_for_qt_build_with_triples for selecting the triples.
+pub fn configure_for_qt_build<P>(
+    builder: &mut cc::Build,
+    qt_build_path: Option<P>,
+) -> Result<PathBuf, QtBuildError>
+where
+    P: AsRef<Path>,
+{
+    configure_for_qt_build_with_triples(builder, qt_build_path, &target::BuildTriples::from_env())
+}
+
+/// Like configure_for_qt_build, with the host and target triples to use when not called
+/// from build.rs (cargo selects the triples for build.rs). See target::BuildTriples::resolve
+/// for resolving the triples, which callers can log or pass on.
+pub fn configure_for_qt_build_with_triples<P>(
+    builder: &mut cc::Build,
+    qt_build_path: Option<P>,
+    triples: &target::BuildTriples,
+) -> Result<PathBuf, QtBuildError>
 where
     P: AsRef<Path>,
 {
@@ -45,10 +106,10 @@ where
     // variables like OUT_DIR, HOST, and TARGET for us, but in the second case
     // they have to be provided here.
     let out_dir_env = std::env::var("OUT_DIR");
-    if let Err(_) = out_dir_env {
+    if out_dir_env.is_err() {
         builder
-            .host("x86_64-unknown-linux") // ### FIXME make configurable
-            .target("x86_64-unknown-linux")
+            .host(&triples.host)
+            .target(&triples.target)
             .opt_level(0);
 
         // The CC crate defaults to 4 parallel compile tasks, increase
@@ -69,7 +130,19 @@ where
     //  - Both are used if both are set. This enables sharing Qt configure output
     //    across several module builds.
     let qt_config_out_dir = match out_dir_env {
-        Ok(var) => PathBuf::from_str(&var).expect("OUT_DIR is not a valid path"),
+        Ok(var) => {
+            let out_dir = PathBuf::from_str(&var).expect("OUT_DIR is not a valid path");
+            if let Some(message) = qt_build_path
+                .as_ref()
+                .and_then(|qt_build_path| build_path_conflict(&out_dir, qt_build_path.as_ref()))
+            {
+                match std::env::var_os(STRICT_BUILD_PATH_ENV_VAR).is_some() {
+                    true => return Err(QtBuildError::BuildPathConflict { message }),
+                    false => println!("cargo:warning={}", message),
+                }
+            }
+            out_dir
+        }
         Err(_) => {
             let qt_config_dir =
                 qt_build_path.expect("build_dir must be provided if not called from build.rs");
@@ -78,46 +151,424 @@ where
         }
     };
 
-    builder.cpp(true).flag("-std=c++17");
+    let is_like_msvc = builder
+        .try_get_compiler()
+        .is_ok_and(|compiler| compiler.is_like_msvc());
+    builder.cpp(true).flag(match is_like_msvc {
+        true => "/std:c++17",
+        false => "-std=c++17",
+    });
 
-    qt_config_out_dir
+    Ok(qt_config_out_dir)
 }
 
-/// Writes the default (linux) Qt configuration
+/// Writes the default (linux) Qt configuration. The Qt version and license edition are
+/// detected from the Qt source, and the pointer size and byte order are taken from the
+/// cargo target. Returns the include paths added to the builder.
 pub fn write_default_qt_configuration<P, Q>(
     builder: &mut cc::Build,
     destination_path: P,
     qt_source_path: Q,
-) where
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    let config_headers_path = destination_path.as_ref().join("qt_config_headers");
+    let qt_configuration =
+        default_qt_configuration(&qt_source_path, configure::set_default_configuration);
+    write_qt_configuration(builder, destination_path, qt_source_path, &qt_configuration)
+}
+
+// The configuration written by write_default_qt_configuration, with the platform
+// configuration set by set_platform_configuration
+fn default_qt_configuration<Q: AsRef<Path>>(
+    qt_source_path: Q,
+    set_platform_configuration: fn(&mut configure::QtConfiguration),
+) -> configure::QtConfiguration {
     let mut qt_configuration = configure::QtConfiguration::new();
-    configure::set_default_configuration(&mut qt_configuration);
-    configure::write_configuration(
-        &qt_configuration,
+    set_platform_configuration(&mut qt_configuration);
+    qt_configuration.set_license(configure::detect_license(&qt_source_path));
+    if let Some(qt_version) = version::detect_qt_version(&qt_source_path) {
+        qt_configuration.set_qt_version(qt_version);
+    }
+    qt_configuration.set_target_data_layout(target::TargetDataLayout::from_cargo_env());
+    qt_configuration
+}
+
+/// Writes the given Qt configuration. Returns the include paths added to the builder.
+pub fn write_qt_configuration<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: P,
+    qt_source_path: Q,
+    qt_configuration: &configure::QtConfiguration,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    write_qt_configuration_with_style(
+        builder,
+        destination_path,
+        qt_source_path,
+        qt_configuration,
+        &configure::IncludeStyle::Quoted,
+    )
+}
+
+/// Writes the given Qt configuration like write_qt_configuration, with the qplatformdefs.h
+/// forwarding header in the given style. Returns the include paths added to the builder.
+pub fn write_qt_configuration_with_style<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: P,
+    qt_source_path: Q,
+    qt_configuration: &configure::QtConfiguration,
+    style: &configure::IncludeStyle,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let config_headers_path = destination_path.as_ref().join("qt_config_headers");
+    configure::write_configuration_with_style(
+        qt_configuration,
         &config_headers_path,
         Some(&qt_source_path),
-    );
-    builder.include(&config_headers_path);
-    builder.include(&config_headers_path.join("QtCore"));
+        style,
+    )?;
+    let include_paths = vec![
+        config_headers_path.clone(),
+        config_headers_path.join("QtCore"),
+    ];
+    for include_path in &include_paths {
+        builder.include(include_path);
+    }
+    Ok(include_paths)
 }
 
-/// Writes forwarding headers for QtCore
+/// Writes forwarding headers for QtCore. Returns the include paths added to the builder.
 pub fn write_qtcore_forwarding_headers<P, Q>(
     builder: &mut cc::Build,
     destination_path: P,
     headers_search_path: Q,
-) where
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    write_qtcore_forwarding_headers_with_options(
+        builder,
+        destination_path,
+        headers_search_path,
+        configure::ForwardingHeaderOptions::default(),
+    )
+}
+
+/// Writes forwarding headers for QtCore with the given options, see
+/// configure::ForwardingHeaderOptions. Patched headers for the header overrides (see
+/// QtBuildOptions::override_header) are written to "qt_patched_headers" under
+/// destination_path, unless the options give a path. Returns the include paths added to
+/// the builder.
+pub fn write_qtcore_forwarding_headers_with_options<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: P,
+    headers_search_path: Q,
+    mut options: configure::ForwardingHeaderOptions,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
     let forwarding_headers_path = destination_path.as_ref().join("qt_forwarding_headers");
     let forwarding_headers_dest = forwarding_headers_path.join("QtCore"); // FIXME
-    configure::write_all_forwarding_headers(headers_search_path, &forwarding_headers_dest);
+    options
+        .patched_headers_path
+        .get_or_insert_with(|| destination_path.as_ref().join("qt_patched_headers"));
+    configure::write_all_forwarding_headers_with_options(
+        headers_search_path,
+        &forwarding_headers_dest,
+        options,
+    )?;
     builder.include(&forwarding_headers_path);
     builder.include(&forwarding_headers_dest);
+    Ok(vec![forwarding_headers_path, forwarding_headers_dest])
+}
+
+/// Returns the path of the class forwarding header ("QtCore/QFoo") for class_name in the
+/// forwarding headers written to destination_path by write_qtcore_forwarding_headers, or
+/// None if no header was written for the class. Only the generated tree is queried; the
+/// Qt source is not scanned.
+pub fn forwarding_header_for_class<P: AsRef<Path>>(
+    destination_path: P,
+    class_name: &str,
+) -> Option<PathBuf> {
+    let is_class_name = !class_name.is_empty()
+        && class_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_class_name {
+        return None;
+    }
+    let header_path = destination_path
+        .as_ref()
+        .join("qt_forwarding_headers")
+        .join("QtCore")
+        .join(class_name);
+    header_path.is_file().then_some(header_path)
+}
+
+/// Writes "qt_classes.rs" to destination_path (typically OUT_DIR), with the QtCore classes
+/// selected by class_header_filter as "pub const QT_CLASSES: &[&str]". Use with
+/// include!(concat!(env!("OUT_DIR"), "/qt_classes.rs")). Returns the path of the file.
+pub fn write_qtcore_classes_module<P, Q>(
+    destination_path: P,
+    qt_source_path: Q,
+    class_header_filter: configure::ClassHeaderFilter,
+) -> Result<PathBuf, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let classes = configure::find_qt_classes_in_headers(
+        qt_source_path.as_ref().join(sources::BOOTSTRAP_PATH),
+        class_header_filter,
+    )?;
+    let module_path = destination_path.as_ref().join("qt_classes.rs");
+    configure::write_qt_classes_module(&module_path, &classes)?;
+    Ok(module_path)
+}
+
+// Generated entries in the output directory, as .gitignore patterns
+const GENERATED_OUTPUT_ENTRIES: &[&str] = &[
+    "/qt_config_headers/",
+    "/qt_forwarding_headers/",
+    "/qt_patched_headers/",
+    "/qt_classes.rs",
+    "/qt_class_headers.cache",
+    "*.o",
+    "*.obj",
+    "*.a",
+    "*.lib",
+];
+
+/// Writes a ".gitignore" to dir which ignores the generated headers and build artifacts,
+/// for output directories inside a version controlled tree. An existing .gitignore is
+/// replaced. Returns the path of the file.
+pub fn write_output_gitignore<P: AsRef<Path>>(dir: P) -> Result<PathBuf, QtBuildError> {
+    let gitignore_path = dir.as_ref().join(".gitignore");
+    let mut content = String::from("# Generated by qt-cargo-base\n");
+    for entry in GENERATED_OUTPUT_ENTRIES {
+        content.push_str(entry);
+        content.push('\n');
+    }
+    std::fs::create_dir_all(dir.as_ref()).map_err(QtBuildError::config_write(dir.as_ref()))?;
+    std::fs::write(&gitignore_path, content)
+        .map_err(QtBuildError::config_write(&gitignore_path))?;
+    Ok(gitignore_path)
+}
+
+/// Writes forwarding headers for the helper headers in qtbase/src/tools/shared, which
+/// moc and the other tools include by file name. The headers are written to
+/// "qt_forwarding_headers/QtToolsShared" under destination_path. Returns the include
+/// paths added to the builder.
+pub fn write_tools_shared_forwarding_headers<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: P,
+    qt_source_path: Q,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let forwarding_headers_dest = destination_path
+        .as_ref()
+        .join("qt_forwarding_headers")
+        .join("QtToolsShared");
+    configure::write_all_forwarding_headers(
+        qt_source_path.as_ref().join(sources::TOOLS_SHARED_PATH),
+        &forwarding_headers_dest,
+    )?;
+    builder.include(&forwarding_headers_dest);
+    Ok(vec![forwarding_headers_dest])
+}
+
+/// Writes forwarding headers for QtTest ("QtTest/qtest.h", "QtTest/QTest", ...) to
+/// "qt_forwarding_headers/QtTest" under destination_path, for compiling
+/// sources::TESTLIB_SOURCES and tests which use QtTest. The QtTest config headers are
+/// written with the Qt configuration, see QtConfiguration::set_testlib. Returns the include
+/// paths added to the builder.
+pub fn write_testlib_forwarding_headers<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: P,
+    qt_source_path: Q,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let forwarding_headers_dest = destination_path
+        .as_ref()
+        .join("qt_forwarding_headers")
+        .join("QtTest");
+    configure::write_all_forwarding_headers(
+        qt_source_path.as_ref().join(sources::TESTLIB_PATH),
+        &forwarding_headers_dest,
+    )?;
+    builder.include(&forwarding_headers_dest);
+    Ok(vec![forwarding_headers_dest])
+}
+
+/// Writes a header-only include tree for the given Qt module to destination_path, for use
+/// with bindgen or other tools which parse Qt headers without compiling Qt. The tree contains
+/// the default Qt configuration headers and the module forwarding headers, and is self-
+/// contained together with the Qt source. Returns the include paths, in search order; pass
+/// these as "-I" arguments (e.g. bindgen::Builder::clang_arg).
+///
+/// Only "QtCore" is currently supported.
+pub fn export_bindgen_includes<P, Q>(
+    qt_source_path: P,
+    module: &str,
+    destination_path: Q,
+) -> Result<Vec<PathBuf>, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    if module != "QtCore" {
+        return Err(QtBuildError::InvalidArgument {
+            message: format!("export_bindgen_includes: unsupported module {}", module),
+        });
+    }
+    let qt_source_path = qt_source_path.as_ref();
+    let destination_path = destination_path.as_ref();
+
+    // The include paths are recorded on a builder which is not used for compiling
+    let mut builder = cc::Build::new();
+    let mut include_paths =
+        write_default_qt_configuration(&mut builder, destination_path, qt_source_path)?;
+    include_paths.extend(write_qtcore_forwarding_headers(
+        &mut builder,
+        destination_path,
+        qt_source_path.join("qtbase/src/corelib"),
+    )?);
+    Ok(include_paths)
+}
+
+/// Paths for a configured Qt build, as returned by configure_qtcore_for_linux
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QtBuildPaths {
+    /// The path where Qt config and forwarding headers are written
+    pub build_path: PathBuf,
+    /// The include paths added to the builder, in search order
+    pub include_paths: Vec<PathBuf>,
+}
+
+impl QtBuildPaths {
+    /// Returns the include paths as "-I<path>" compiler flags, in search order, for
+    /// tools like clangd (compile_flags.txt) or include-what-you-use.
+    pub fn as_include_flags(&self) -> Vec<String> {
+        self.include_paths
+            .iter()
+            .map(|include_path| format!("-I{}", include_path.display()))
+            .collect()
+    }
+}
+
+/// Options which control how Qt is compiled. The default options give the
+/// same build as configure_qtcore_for_linux.
+#[derive(Clone, Debug, Default)]
+pub struct QtBuildOptions {
+    /// Compile with frame pointers (-fno-omit-frame-pointer), which profilers like perf
+    /// need in order to unwind through Qt code. Independent of the optimization level.
+    pub frame_pointers: bool,
+    /// Header overrides, as (path relative to qtbase/src/corelib, content) pairs.
+    /// See override_header.
+    pub header_overrides: Vec<(PathBuf, String)>,
+    /// Include paths which are searched before the generated config and forwarding
+    /// header paths. See prepend_include.
+    pub prepended_include_paths: Vec<PathBuf>,
+    /// The Qt configuration to write, for example with individual features disabled.
+    /// The default configuration is written if not set. Use sources::filter_sources
+    /// to select the matching source files.
+    pub qt_configuration: Option<configure::QtConfiguration>,
+    /// Unix permission mode for the generated files, for example 0o664 for a shared
+    /// build cache. Directories get the execute bit in addition. Ignored on other platforms.
+    pub file_mode: Option<u32>,
+    /// Selects the classes which get "QFoo"-type class forwarding headers. Set to
+    /// ExportedClasses for a public API only include tree.
+    pub class_header_filter: configure::ClassHeaderFilter,
+    /// The C++ compiler to use, as a path or a name to look up in PATH (e.g. "g++-12"),
+    /// instead of the cc crate default selection (which uses the CXX environment variable).
+    /// See util::find_compiler.
+    pub compiler: Option<PathBuf>,
+    /// Workaround flags for the compiler. If not set, the known-needed flags for the
+    /// detected compiler family and version are added, see toolchain::workaround_flags.
+    /// Set to replace them, or to an empty list to add none.
+    pub compiler_workarounds: Option<Vec<String>>,
+    /// The target pointer size and byte order, for the QT_POINTER_SIZE and Q_BYTE_ORDER
+    /// defines. Taken from the cargo target if not set, see TargetDataLayout::from_cargo_env;
+    /// set this when the builder targets a different architecture than the cargo target.
+    /// Applies to qt_configuration as well.
+    pub target_data_layout: Option<target::TargetDataLayout>,
+    /// The "#include" style of the generated forwarding headers, including qplatformdefs.h.
+    /// Set to IncludeStyle::qt_src_placeholder for a generated tree which can be used with
+    /// the Qt source at a different location; header_overrides must then be empty, since
+    /// the patched headers are not in the Qt source.
+    pub include_style: configure::IncludeStyle,
+    /// The host and target triples, for builds which are not run by cargo (tests and
+    /// tools), e.g. to cross-compile for Android. Taken from the environment if not set,
+    /// see configure_for_qt_build. Cargo selects the triples for build.rs.
+    pub build_triples: Option<target::BuildTriples>,
+}
+
+impl QtBuildOptions {
+    /// Replaces the content of a QtCore header for this build, without modifying the
+    /// Qt source tree. relative_path is relative to qtbase/src/corelib, for example
+    /// "global/qglobal.h". The forwarding headers will point to a patched copy of the
+    /// header, written to the build directory.
+    pub fn override_header<P: AsRef<Path>>(&mut self, relative_path: P, content: &str) {
+        let relative_path = relative_path.as_ref().to_path_buf();
+        self.header_overrides
+            .retain(|(path, _)| *path != relative_path);
+        self.header_overrides
+            .push((relative_path, content.to_string()));
+    }
+
+    /// Adds an include path which is searched before the generated include paths, which
+    /// makes it possible to replace individual generated headers (e.g. QtCore/qconfig.h).
+    pub fn prepend_include<P: AsRef<Path>>(&mut self, path: P) {
+        self.prepended_include_paths
+            .push(path.as_ref().to_path_buf());
+    }
+}
+
+/// Applies the given build options to the builder. Include paths are searched in the
+/// order they are added to the builder; call this function before adding the generated
+/// include paths. Returns QtBuildError::Toolchain if the compiler option is set to a
+/// compiler which is not found.
+pub fn apply_build_options(
+    builder: &mut cc::Build,
+    options: &QtBuildOptions,
+) -> Result<(), QtBuildError> {
+    if let Some(compiler) = &options.compiler {
+        builder.compiler(util::find_compiler(compiler)?);
+    }
+    for include_path in &options.prepended_include_paths {
+        builder.include(include_path);
+    }
+    if options.frame_pointers {
+        builder.flag("-fno-omit-frame-pointer");
+    }
+    match &options.compiler_workarounds {
+        Some(flags) => {
+            for flag in flags {
+                builder.flag(flag);
+            }
+        }
+        None => {
+            toolchain::apply_compiler_workarounds(builder);
+        }
+    }
+    Ok(())
 }
 
 /// Configures the build for the linux target; writes Qt QtCore configuration files and forwarding heders;
@@ -125,21 +576,391 @@ pub fn write_qtcore_forwarding_headers<P, Q>(
 /// optionally destination_path can be set to specify where Qt configuration files should be written. The builder
 /// writes build artifacts to the location pointed to by the OUT_DIR environment variable (typically set by Cargo).
 /// If OUT_DIR is not set then the builder is configured to use destination_path.
+///
+/// Returns the build and include paths, or an error if the Qt source is missing or the
+/// generated files can't be written. See error::QtBuildError.
 pub fn configure_qtcore_for_linux<P, Q>(
     builder: &mut cc::Build,
     destination_path: Option<P>,
     qt_source_path: Q,
-) where
+) -> Result<QtBuildPaths, QtBuildError>
+where
     P: AsRef<Path>,
     Q: AsRef<Path>,
 {
-    let qt_config_path = configure_for_qt_build(builder, destination_path);
-    write_default_qt_configuration(builder, &qt_config_path, &qt_source_path);
-    write_qtcore_forwarding_headers(
+    configure_qtcore_for_linux_with_options(
         builder,
-        qt_config_path,
-        &qt_source_path.as_ref().join("qtbase/src/corelib"),
-    );
+        destination_path,
+        qt_source_path,
+        &QtBuildOptions::default(),
+    )
+}
+
+/// Like configure_qtcore_for_linux, but additionally applies the given build options.
+pub fn configure_qtcore_for_linux_with_options<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+    options: &QtBuildOptions,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    configure_qtcore(
+        builder,
+        destination_path,
+        qt_source_path,
+        options,
+        configure::set_default_configuration,
+    )
+}
+
+/// Configures the builder for building QtCore for Windows with MSVC (the win32-msvc
+/// mkspec), like configure_qtcore_for_linux: writes the Windows configuration (see
+/// configure::set_windows_msvc_configuration) and the forwarding headers, and adds the
+/// include paths and the Windows defines to the builder. Compile the
+/// sources::BOOTSTRAP_SOURCES_WIN32 platform sources instead of the unix sources.
+pub fn configure_qtcore_for_windows_msvc<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    configure_qtcore_for_windows_msvc_with_options(
+        builder,
+        destination_path,
+        qt_source_path,
+        &QtBuildOptions::default(),
+    )
+}
+
+/// Like configure_qtcore_for_windows_msvc, but additionally applies the given build options.
+pub fn configure_qtcore_for_windows_msvc_with_options<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+    options: &QtBuildOptions,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    // The win32-msvc mkspec DEFINES
+    for define in [
+        "UNICODE",
+        "_UNICODE",
+        "WIN32",
+        "_ENABLE_EXTENDED_ALIGNED_STORAGE",
+    ] {
+        builder.define(define, None);
+    }
+    configure_qtcore(
+        builder,
+        destination_path,
+        qt_source_path,
+        options,
+        configure::set_windows_msvc_configuration,
+    )
+}
+
+/// Configures the builder for building QtCore for macOS (the macx-clang mkspec), like
+/// configure_qtcore_for_linux: writes the macOS configuration (see
+/// configure::set_macos_configuration) and the forwarding headers, and adds the include
+/// paths and libc++ to the builder. Compile the sources::BOOTSTRAP_SOURCES_MACOS platform
+/// sources in addition to the unix sources, with a builder from objective_cpp_builder.
+///
+/// The configuration links against the CoreFoundation and Foundation frameworks, see
+/// emit_link_libraries.
+pub fn configure_qtcore_for_macos<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    configure_qtcore_for_macos_with_options(
+        builder,
+        destination_path,
+        qt_source_path,
+        &QtBuildOptions::default(),
+    )
+}
+
+/// Like configure_qtcore_for_macos, but additionally applies the given build options.
+pub fn configure_qtcore_for_macos_with_options<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+    options: &QtBuildOptions,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    builder.flag("-stdlib=libc++");
+    configure_qtcore(
+        builder,
+        destination_path,
+        qt_source_path,
+        options,
+        configure::set_macos_configuration,
+    )
+}
+
+/// Configures the builder for building QtCore for Android (the android-clang mkspec), like
+/// configure_qtcore_for_linux: writes the Android configuration (see
+/// configure::set_android_configuration) and the forwarding headers, and adds the include
+/// paths and the Android defines to the builder.
+///
+/// The builder must target Android, e.g. "aarch64-linux-android": from build.rs with
+/// "cargo build --target", or with QtBuildOptions::build_triples otherwise. cc then
+/// selects the NDK compiler for the target ("aarch64-linux-android21-clang++" in PATH,
+/// or CXX_<target>). Compile the sources::BOOTSTRAP_SOURCES_UNIX platform sources.
+pub fn configure_qtcore_for_android<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    configure_qtcore_for_android_with_options(
+        builder,
+        destination_path,
+        qt_source_path,
+        &QtBuildOptions::default(),
+    )
+}
+
+/// Like configure_qtcore_for_android, but additionally applies the given build options.
+pub fn configure_qtcore_for_android_with_options<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+    options: &QtBuildOptions,
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    // The android-clang mkspec DEFINES. Q_OS_ANDROID is derived from the __ANDROID__
+    // define of the NDK compiler, see qsystemdetection.h.
+    builder.define("ANDROID", None);
+    configure_qtcore(
+        builder,
+        destination_path,
+        qt_source_path,
+        options,
+        configure::set_android_configuration,
+    )
+}
+
+/// Returns a copy of the configured builder for compiling Objective-C++ (".mm") sources,
+/// e.g. sources::BOOTSTRAP_SOURCES_MACOS. Call before adding files to the builder.
+///
+/// cc passes the same flags to all files and leaves the language to the compiler, which
+/// compiles ".mm" files as C++ after the "-std=c++17" the builder is configured with. The
+/// copy compiles with "-x objective-c++" instead, and should be compiled to a separate
+/// archive.
+pub fn objective_cpp_builder(builder: &cc::Build) -> cc::Build {
+    let mut objective_cpp_builder = builder.clone();
+    objective_cpp_builder.flag("-x").flag("objective-c++");
+    objective_cpp_builder
+}
+
+fn configure_qtcore<P, Q>(
+    builder: &mut cc::Build,
+    destination_path: Option<P>,
+    qt_source_path: Q,
+    options: &QtBuildOptions,
+    set_platform_configuration: fn(&mut configure::QtConfiguration),
+) -> Result<QtBuildPaths, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let triples = options
+        .build_triples
+        .clone()
+        .unwrap_or_else(target::BuildTriples::from_env);
+    let qt_config_path = configure_for_qt_build_with_triples(builder, destination_path, &triples)?;
+    apply_build_options(builder, options)?;
+    let mut include_paths = options.prepended_include_paths.clone();
+    let mut qt_configuration = options
+        .qt_configuration
+        .clone()
+        .unwrap_or_else(|| default_qt_configuration(&qt_source_path, set_platform_configuration));
+    if let Some(layout) = options.target_data_layout {
+        qt_configuration.set_target_data_layout(layout);
+    }
+    include_paths.extend(write_qt_configuration_with_style(
+        builder,
+        &qt_config_path,
+        &qt_source_path,
+        &qt_configuration,
+        &options.include_style,
+    )?);
+    // Profile the header scan to the build output if requested, see
+    // timing::HEADER_SCAN_PROFILE_ENV_VAR
+    let mut header_scan_profile = timing::HeaderScanProfile::from_env();
+    // Skip the class scan for headers which are unchanged since the previous build
+    let mut class_header_cache =
+        configure::ClassHeaderCache::load(qt_config_path.join("qt_class_headers.cache"));
+    include_paths.extend(write_qtcore_forwarding_headers_with_options(
+        builder,
+        &qt_config_path,
+        qt_source_path.as_ref().join("qtbase/src/corelib"),
+        configure::ForwardingHeaderOptions {
+            header_overrides: options.header_overrides.clone(),
+            class_filter: options.class_header_filter,
+            style: options.include_style.clone(),
+            profile: header_scan_profile.as_mut(),
+            scan_log: util::header_scan_log_from_env(),
+            cache: Some(&mut class_header_cache),
+            ..Default::default()
+        },
+    )?);
+    class_header_cache.save()?;
+    if let Some(profile) = &header_scan_profile {
+        profile.print_report();
+    }
+    if let Some(file_mode) = options.file_mode {
+        for generated_path in [
+            "qt_config_headers",
+            "qt_forwarding_headers",
+            "qt_patched_headers",
+        ] {
+            let generated_path = qt_config_path.join(generated_path);
+            if generated_path.exists() {
+                util::set_permissions_recursive(&generated_path, file_mode)
+                    .map_err(QtBuildError::config_write(&generated_path))?;
+            }
+        }
+    }
+
+    Ok(QtBuildPaths {
+        build_path: qt_config_path,
+        include_paths,
+    })
+}
+
+/// The change in generated output between two Qt source trees, as returned by
+/// compare_qt_versions. Class and feature lists are sorted by name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VersionDelta {
+    /// The Qt version detected in the old source, if any
+    pub old_version: Option<version::QtVersion>,
+    /// The Qt version detected in the new source, if any
+    pub new_version: Option<version::QtVersion>,
+    /// Classes with a "QFoo" class forwarding header for the new source only
+    pub added_classes: Vec<String>,
+    /// Classes with a "QFoo" class forwarding header for the old source only
+    pub removed_classes: Vec<String>,
+    /// Features with a different default value, as (feature, old value, new value). A
+    /// value is None if the feature is not configured for that source.
+    pub changed_features: Vec<(String, Option<bool>, Option<bool>)>,
+    /// All generated files which differ, relative to the build path, with the new
+    /// output as the generated tree and the old output as the golden tree
+    pub output: util::OutputDiff,
+}
+
+impl VersionDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added_classes.is_empty()
+            && self.removed_classes.is_empty()
+            && self.changed_features.is_empty()
+            && self.output.is_empty()
+    }
+}
+
+/// Generates the default Qt configuration and the QtCore forwarding headers for
+/// old_qt_source_path and new_qt_source_path, under "old" and "new" in destination_path,
+/// and compares the results. Use when updating the Qt source to review which class
+/// headers appear or disappear and which features change default.
+///
+/// Both trees are written with IncludeStyle::qt_src_placeholder, so that the generated
+/// headers do not depend on the location of the source trees and only actual changes
+/// are reported.
+pub fn compare_qt_versions<P, Q, R>(
+    old_qt_source_path: P,
+    new_qt_source_path: Q,
+    destination_path: R,
+) -> Result<VersionDelta, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let generate = |qt_source_path: &Path, name: &str| {
+        let build_path = destination_path.as_ref().join(name);
+        let qt_configuration =
+            default_qt_configuration(qt_source_path, configure::set_default_configuration);
+        let style = configure::IncludeStyle::qt_src_placeholder(qt_source_path);
+        let mut builder = cc::Build::new();
+        write_qt_configuration_with_style(
+            &mut builder,
+            &build_path,
+            qt_source_path,
+            &qt_configuration,
+            &style,
+        )?;
+        write_qtcore_forwarding_headers_with_options(
+            &mut builder,
+            &build_path,
+            qt_source_path.join("qtbase/src/corelib"),
+            configure::ForwardingHeaderOptions {
+                style,
+                ..Default::default()
+            },
+        )?;
+        Ok::<_, QtBuildError>((build_path, qt_configuration))
+    };
+    let (old_build_path, old_configuration) = generate(old_qt_source_path.as_ref(), "old")?;
+    let (new_build_path, new_configuration) = generate(new_qt_source_path.as_ref(), "new")?;
+
+    let output = util::diff_against_golden(&new_build_path, &old_build_path);
+    // Class forwarding headers are the extensionless files next to the QtCore headers
+    let class_names = |paths: &[PathBuf]| -> Vec<String> {
+        paths
+            .iter()
+            .filter(|path| path.parent() == Some(Path::new("qt_forwarding_headers/QtCore")))
+            .filter(|path| path.extension().is_none())
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect()
+    };
+    let old_features = old_configuration.features();
+    let new_features = new_configuration.features();
+    let changed_features = old_features
+        .keys()
+        .chain(new_features.keys())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .map(|feature| {
+            (
+                feature.clone(),
+                old_features.get(feature).copied(),
+                new_features.get(feature).copied(),
+            )
+        })
+        .filter(|(_, old, new)| old != new)
+        .collect();
+
+    Ok(VersionDelta {
+        old_version: old_configuration.qt_version(),
+        new_version: new_configuration.qt_version(),
+        added_classes: class_names(&output.added),
+        removed_classes: class_names(&output.removed),
+        changed_features,
+        output,
+    })
 }
 
 pub fn add_path_prefixed_files<P, Q>(builder: &mut cc::Build, path: P, files: Q)
@@ -152,7 +973,201 @@ where
     builder.files(prefixed_files);
 }
 
-mod features;
+/// Emits "cargo:rustc-link-lib" lines for the system libraries the configuration requires
+/// (see QtConfiguration::link_libraries). Call after compiling the Qt archive, so that the
+/// libraries follow it on the linker command line.
+pub fn emit_link_libraries(qt_configuration: &configure::QtConfiguration) {
+    for library in qt_configuration.link_libraries() {
+        println!("cargo:rustc-link-lib={}", library);
+    }
+}
+
+/// Emits a "cargo:rustc-link-arg" line for each of the given linker arguments, for example
+/// "-Wl,--start-group" and "-Wl,--end-group" around libraries with circular dependencies.
+/// The arguments are passed to the linker for all targets of the package.
+pub fn emit_link_args(args: &[&str]) {
+    for directive in link_arg_directives(args) {
+        println!("{}", directive);
+    }
+}
+
+fn link_arg_directives(args: &[&str]) -> Vec<String> {
+    args.iter()
+        .map(|arg| format!("cargo:rustc-link-arg={}", arg))
+        .collect()
+}
+
+/// Adds the compile defines of the configuration to the builder, see
+/// QtConfiguration::compile_defines.
+pub fn apply_compile_defines(
+    builder: &mut cc::Build,
+    qt_configuration: &configure::QtConfiguration,
+) {
+    for (key, value) in qt_configuration.compile_defines() {
+        builder.define(key, value);
+    }
+}
+
+/// Adds the QT_VERSION_MAJOR, QT_VERSION_MINOR, QT_VERSION_PATCH and QT_VERSION_STR
+/// defines for qt_version to the builder. See version::detect_qt_version.
+pub fn apply_version_defines(builder: &mut cc::Build, qt_version: version::QtVersion) {
+    for (key, value) in qt_version.defines() {
+        builder.define(key, value.as_str());
+    }
+}
+
+/// Adds the defines for compiling the bootstrap library, and the host tools which use it
+/// (moc, rcc, ...), to the builder. qt_version is the version of the Qt source, see
+/// version::detect_qt_version.
+pub fn configure_bootstrap(builder: &mut cc::Build, qt_version: version::QtVersion) {
+    builder.define("HAVE_CONFIG_H", None);
+    apply_version_defines(builder, qt_version);
+    builder.define("QT_USE_QSTRINGBUILDER", None);
+    builder.define("QT_BOOTSTRAPPED", None);
+    builder.define("QT_NO_CAST_FROM_ASCII", None);
+    builder.define("QT_NO_CAST_TO_ASCII", None);
+    // The bootstrap build disables foreach, see QtConfiguration::set_foreach
+    let mut bootstrap_configuration = configure::QtConfiguration::new();
+    bootstrap_configuration.set_foreach(false);
+    apply_compile_defines(builder, &bootstrap_configuration);
+}
+
+/// Compiles the bootstrap library as a standalone archive, which the host tools link
+/// against instead of compiling the bootstrap sources for each tool. The Qt configuration
+/// is written to qt_build_path (or OUT_DIR, see configure_for_qt_build). Returns the path
+/// of the archive.
+///
+/// The archive is named after modules::QtModule::Bootstrap, and cc emits the
+/// "cargo:rustc-link-lib" line for it when called from build.rs. Static archives are
+/// linked in the order they are compiled, so compile the host tool archives first.
+pub fn build_bootstrap_archive<P, Q>(
+    qt_source_path: Q,
+    qt_build_path: Option<P>,
+) -> Result<PathBuf, QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let qt_source_path = qt_source_path.as_ref();
+    let mut builder = cc::Build::new();
+    let target = target::BuildTriples::from_env().target;
+    let qt_build_paths = if is_macos_target() {
+        configure_qtcore_for_macos(&mut builder, qt_build_path, qt_source_path)?
+    } else if target.contains("-windows-msvc") {
+        configure_qtcore_for_windows_msvc(&mut builder, qt_build_path, qt_source_path)?
+    } else {
+        configure_qtcore_for_linux(&mut builder, qt_build_path, qt_source_path)?
+    };
+    build_bootstrap_archive_with_builder(builder, qt_source_path, &qt_build_paths)
+}
+
+/// Compiles the bootstrap library like build_bootstrap_archive, with a builder which is
+/// already configured with configure_qtcore_for_linux (or configure_qtcore_for_macos,
+/// configure_qtcore_for_windows_msvc) and the paths it returned, so that the Qt
+/// configuration is not written twice. Pass a clone of the builder taken before adding
+/// sources and tool specific defines to it. Returns InvalidArgument for targets without
+/// bootstrap platform sources, see bootstrap_platform_sources.
+pub fn build_bootstrap_archive_with_builder<Q>(
+    mut builder: cc::Build,
+    qt_source_path: Q,
+    qt_build_paths: &QtBuildPaths,
+) -> Result<PathBuf, QtBuildError>
+where
+    Q: AsRef<Path>,
+{
+    let qt_source_path = qt_source_path.as_ref();
+    let platform_sources = bootstrap_platform_sources(&target::BuildTriples::from_env().target)?;
+    builder.include(qt_source_path.join(sources::TINYCBOR_INCLUDE_PATH));
+    let qt_version =
+        version::detect_qt_version(qt_source_path).unwrap_or(version::DEFAULT_QT_VERSION);
+    configure_bootstrap(&mut builder, qt_version);
+    let mut objective_cpp_builder = objective_cpp_builder(&builder);
+    add_path_prefixed_files(
+        &mut builder,
+        qt_source_path.join(sources::BOOTSTRAP_PATH),
+        sources::BOOTSTRAP_SOURCES,
+    );
+    add_path_prefixed_files(
+        &mut builder,
+        qt_source_path.join(sources::BOOTSTRAP_PATH),
+        platform_sources,
+    );
+
+    let name = modules::QtModule::Bootstrap.library_name();
+    let archive_name = library::static_library_name(&builder, name);
+    builder.compile(name);
+
+    // The Objective-C++ sources go in a second archive, linked after the bootstrap archive
+    if is_macos_target() {
+        add_path_prefixed_files(
+            &mut objective_cpp_builder,
+            qt_source_path.join(sources::BOOTSTRAP_PATH),
+            sources::BOOTSTRAP_SOURCES_MACOS,
+        );
+        objective_cpp_builder.compile(&format!("{}_objcpp", name));
+    }
+    Ok(qt_build_paths.build_path.join(archive_name))
+}
+
+// Returns the platform specific bootstrap sources for the target triple: the Windows
+// sources for MSVC targets and the unix sources otherwise. Windows targets with other
+// toolchains (such as windows-gnu) are not supported.
+fn bootstrap_platform_sources(target: &str) -> Result<&'static [&'static str], QtBuildError> {
+    if target.contains("-windows-msvc") {
+        Ok(sources::BOOTSTRAP_SOURCES_WIN32)
+    } else if target.contains("-windows") {
+        Err(QtBuildError::InvalidArgument {
+            message: format!("The bootstrap library can't be built for target {}", target),
+        })
+    } else {
+        Ok(sources::BOOTSTRAP_SOURCES_UNIX)
+    }
+}
+
+// Returns true if the build targets macOS, see build_bootstrap_archive
+fn is_macos_target() -> bool {
+    target::BuildTriples::from_env()
+        .target
+        .contains("-apple-darwin")
+}
+
+/// The PCRE2 code unit width used by QtCore: QString is UTF-16, and QRegularExpression
+/// calls the 16-bit PCRE2 API.
+pub const PCRE2_DEFAULT_CODE_UNIT_WIDTH: u8 = 16;
+
+/// Configures the given cc::Build object for building the bundled PCRE2 library with the
+/// given code unit width (8, 16 or 32). Use PCRE2_DEFAULT_CODE_UNIT_WIDTH when building
+/// for QtCore; QtCore will fail to link against a PCRE2 built with a different width.
+///
+/// PCRE2 is C code and should be compiled with a separate builder, not with the builder
+/// configured for compiling Qt C++ code. Returns QtBuildError::InvalidArgument for an
+/// unsupported code unit width.
+pub fn configure_pcre2<Q>(
+    builder: &mut cc::Build,
+    qt_source_path: Q,
+    code_unit_width: u8,
+) -> Result<(), QtBuildError>
+where
+    Q: AsRef<Path>,
+{
+    if ![8, 16, 32].contains(&code_unit_width) {
+        return Err(QtBuildError::InvalidArgument {
+            message: format!("unsupported PCRE2 code unit width {}", code_unit_width),
+        });
+    }
+
+    let pcre2_path = qt_source_path.as_ref().join(sources::PCRE2_PATH);
+    builder.cpp(false);
+    builder.include(&pcre2_path);
+    builder.define("HAVE_CONFIG_H", None);
+    builder.define("PCRE2_STATIC", None);
+    builder.define(
+        "PCRE2_CODE_UNIT_WIDTH",
+        code_unit_width.to_string().as_str(),
+    );
+    add_path_prefixed_files(builder, &pcre2_path, sources::PCRE2_SOURCES);
+    Ok(())
+}
 
 #[cfg(test)]
 mod qt_cargo_base_tests {
@@ -160,7 +1175,7 @@ mod qt_cargo_base_tests {
 
     fn qt_build_temp_dir() -> tempdir::TempDir {
         // Set up a temp dir for build artifacts
-        tempdir::TempDir::new("qt-cargo-base-test").expect("unable to create temp dir")
+        util::scratch_temp_dir("qt-cargo-base-test").expect("unable to create temp dir")
     }
 
     #[test]
@@ -171,7 +1186,7 @@ mod qt_cargo_base_tests {
         //std::mem::forget(temp); // leak build config in /temp/ for inspection
 
         let mut builder = cc::Build::new();
-        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source);
+        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source).unwrap();
         builder.file(qt_source.join("qtbase/src/corelib/global/qglobal.cpp"));
 
         builder.compile("qglobal"); // No panic -> test pass
@@ -184,15 +1199,15 @@ mod qt_cargo_base_tests {
         let qt_build = temp.path();
 
         let mut builder = cc::Build::new();
-        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source);
+        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source).unwrap();
         add_path_prefixed_files(
             &mut builder,
             qt_source.join(crate::sources::MOC_PATH),
             crate::sources::MOC_SOURCES,
         );
 
-        builder.include(qt_source.join("qtbase/src/3rdparty/tinycbor/src/"));
-        builder.include(qt_source.join("qtbase/src/tools/shared"));
+        builder.include(qt_source.join(crate::sources::TINYCBOR_INCLUDE_PATH));
+        write_tools_shared_forwarding_headers(&mut builder, qt_build, &qt_source).unwrap();
 
         builder.compile("moc"); // No panic -> test pass
     }
@@ -205,26 +1220,999 @@ mod qt_cargo_base_tests {
 
         let mut builder = cc::Build::new();
 
-        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source);
+        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source).unwrap();
         add_path_prefixed_files(
             &mut builder,
             qt_source.join(crate::sources::BOOTSTRAP_PATH),
             crate::sources::BOOTSTRAP_SOURCES,
         );
 
-        builder.define("HAVE_CONFIG_H", None);
-        builder.define("QT_VERSION_MAJOR", "6");
-        builder.define("QT_VERSION_MINOR", "2");
-        builder.define("QT_VERSION_PATCH", "0");
-        builder.define("QT_VERSION_STR", "\"6.2.0\"");
-        builder.define("QT_USE_QSTRINGBUILDER", None);
-        builder.define("QT_BOOTSTRAPPED", None);
-        builder.define("QT_NO_CAST_FROM_ASCII", None);
-        builder.define("QT_NO_CAST_TO_ASCII", None);
-        builder.define("QT_NO_FOREACH", None);
+        let qt_version =
+            version::detect_qt_version(&qt_source).unwrap_or(version::DEFAULT_QT_VERSION);
+        configure_bootstrap(&mut builder, qt_version);
 
-        builder.include(qt_source.join("qtbase/src/3rdparty/tinycbor/src/"));
+        builder.include(qt_source.join(crate::sources::TINYCBOR_INCLUDE_PATH));
 
         builder.compile("bootstrap"); // No panic -> test pass
     }
+
+    // Builds moc as an executable, from the moc archive and the bootstrap archive, in
+    // qt_build. Returns the path of moc and the QtCore build paths it was configured with.
+    pub(crate) fn build_moc_executable(
+        qt_source: &Path,
+        qt_build: &Path,
+    ) -> (PathBuf, QtBuildPaths) {
+        // moc archive, without the bootstrap sources
+        let mut builder = cc::Build::new();
+        let qt_build_paths =
+            configure_qtcore_for_linux(&mut builder, Some(qt_build), qt_source).unwrap();
+        let bootstrap_builder = builder.clone();
+        add_path_prefixed_files(
+            &mut builder,
+            qt_source.join(crate::sources::MOC_PATH),
+            crate::sources::MOC_SOURCES,
+        );
+        builder.include(qt_source.join(crate::sources::TINYCBOR_INCLUDE_PATH));
+        write_tools_shared_forwarding_headers(&mut builder, qt_build, qt_source).unwrap();
+        builder.define("main", "hiddenmocmain");
+        let qt_version =
+            version::detect_qt_version(qt_source).unwrap_or(version::DEFAULT_QT_VERSION);
+        configure_bootstrap(&mut builder, qt_version);
+        builder.compile(modules::QtModule::HostTools.library_name());
+
+        let bootstrap_archive =
+            build_bootstrap_archive_with_builder(bootstrap_builder, qt_source, &qt_build_paths)
+                .unwrap();
+        assert!(bootstrap_archive.exists());
+
+        // Link moc from the two archives
+        let main_path = qt_build.join("moc_main.cpp");
+        std::fs::write(
+            &main_path,
+            "int runMoc(int argc, char **argv);\n\
+             int main(int argc, char **argv) { return runMoc(argc, argv); }\n",
+        )
+        .unwrap();
+        let moc_path = qt_build.join("moc");
+        let mut command = builder.get_compiler().to_command();
+        command
+            .arg(&main_path)
+            .arg("-o")
+            .arg(&moc_path)
+            .arg(format!("-L{}", qt_build.display()));
+        for library in modules::link_order(&[modules::QtModule::HostTools]) {
+            command.arg(format!("-l{}", library));
+        }
+        let output = command.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        (moc_path, qt_build_paths)
+    }
+
+    #[test]
+    fn build_moc_with_bootstrap_archive() {
+        let qt_source = util::qt_src_path();
+        let temp = qt_build_temp_dir();
+        let qt_build = temp.path();
+
+        let (moc_path, _) = build_moc_executable(&qt_source, qt_build);
+        let output = std::process::Command::new(&moc_path)
+            .arg("--version")
+            .output()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("moc "));
+    }
+
+    #[test]
+    fn build_testlib() {
+        let qt_source = util::qt_src_path();
+        let temp = qt_build_temp_dir();
+        let qt_build = temp.path();
+
+        let testlib_test = qt_build.join("testlib_test.cpp");
+        std::fs::write(
+            &testlib_test,
+            "#include <QtTest/qtest.h>\n\
+             bool testlib_test()\n\
+             {\n\
+                 return QTest::qCompare(QString(\"qt\"), QStringLiteral(\"qt\"), \"actual\",\n\
+                                        \"expected\", __FILE__, __LINE__);\n\
+             }\n",
+        )
+        .unwrap();
+
+        let mut qt_configuration = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut qt_configuration);
+        qt_configuration.set_testlib(true);
+        let options = QtBuildOptions {
+            qt_configuration: Some(qt_configuration),
+            ..Default::default()
+        };
+        let mut builder = cc::Build::new();
+        configure_qtcore_for_linux_with_options(
+            &mut builder,
+            Some(&qt_build),
+            &qt_source,
+            &options,
+        )
+        .unwrap();
+        write_testlib_forwarding_headers(&mut builder, qt_build, &qt_source).unwrap();
+        add_path_prefixed_files(
+            &mut builder,
+            qt_source.join(crate::sources::TESTLIB_PATH),
+            crate::sources::TESTLIB_SOURCES,
+        );
+        builder.file(&testlib_test);
+
+        builder.compile("testlib"); // No panic -> test pass
+    }
+
+    #[test]
+    fn build_logging() {
+        let qt_source = util::qt_src_path();
+        let temp = qt_build_temp_dir();
+        let qt_build = temp.path();
+
+        let logging_test = qt_build.join("logging_test.cpp");
+        std::fs::write(
+            &logging_test,
+            "#include <QtCore/qloggingcategory.h>\n\
+             Q_DECLARE_LOGGING_CATEGORY(lcTest)\n\
+             Q_LOGGING_CATEGORY(lcTest, \"qt.cargo.test\")\n\
+             void logging_test() { qCDebug(lcTest) << \"logging\"; }\n",
+        )
+        .unwrap();
+
+        let mut builder = cc::Build::new();
+        configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source).unwrap();
+        add_path_prefixed_files(
+            &mut builder,
+            qt_source.join(crate::sources::BOOTSTRAP_PATH),
+            crate::sources::QTCORE_LOGGING_SOURCES,
+        );
+        builder.file(&logging_test);
+
+        builder.compile("logging"); // No panic -> test pass
+    }
+
+    // Compiles a test source against QtCore configured with qt_configuration, together
+    // with the given QtCore sources (relative to BOOTSTRAP_PATH) which are enabled in the
+    // configuration, see sources::filter_sources
+    fn build_with_configuration(
+        name: &str,
+        qt_configuration: configure::QtConfiguration,
+        qtcore_sources: &[&str],
+        test_source: &str,
+    ) {
+        let qt_source = util::qt_src_path();
+        let temp = qt_build_temp_dir();
+        let qt_build = temp.path();
+
+        let test_path = qt_build.join(format!("{}_test.cpp", name));
+        std::fs::write(&test_path, test_source).unwrap();
+
+        let mut builder = cc::Build::new();
+        let options = QtBuildOptions {
+            qt_configuration: Some(qt_configuration.clone()),
+            ..Default::default()
+        };
+        configure_qtcore_for_linux_with_options(
+            &mut builder,
+            Some(&qt_build),
+            &qt_source,
+            &options,
+        )
+        .unwrap();
+        if qt_configuration.requires_forkfd() {
+            builder.include(qt_source.join(crate::sources::FORKFD_INCLUDE_PATH));
+        }
+        if qt_configuration.requires_pcre2() {
+            builder.include(qt_source.join(crate::sources::PCRE2_PATH));
+            builder.define("PCRE2_STATIC", None);
+        }
+        add_path_prefixed_files(
+            &mut builder,
+            qt_source.join(crate::sources::BOOTSTRAP_PATH),
+            crate::sources::filter_sources(qtcore_sources, &qt_configuration),
+        );
+        builder.file(&test_path);
+
+        builder.compile(name); // No panic -> test pass
+    }
+
+    // Returns the QtCore sources gated on the feature, see sources::QTCORE_FEATURE_SOURCES
+    fn feature_sources(feature: &str) -> &'static [&'static str] {
+        crate::sources::QTCORE_FEATURE_SOURCES
+            .iter()
+            .find(|(name, _)| *name == feature)
+            .map(|(_, sources)| *sources)
+            .unwrap()
+    }
+
+    // Returns the default configuration, as configure_qtcore_for_linux writes it
+    fn default_configuration() -> configure::QtConfiguration {
+        let mut qt_configuration = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut qt_configuration);
+        qt_configuration
+    }
+
+    #[test]
+    #[ignore = "requires the Qt source in qt-src"]
+    fn build_bindable_properties() {
+        let mut qt_configuration = default_configuration();
+        qt_configuration.set_bindable_properties(true);
+        build_with_configuration(
+            "bindable_properties",
+            qt_configuration,
+            &[],
+            "#include <QtCore/qproperty.h>\n\
+             int bindable_properties_test()\n\
+             {\n\
+                 QProperty<int> value(1);\n\
+                 QProperty<int> doubled;\n\
+                 doubled.setBinding([&] { return value * 2; });\n\
+                 value = 2;\n\
+                 return doubled.value();\n\
+             }\n",
+        );
+    }
+
+    #[test]
+    #[ignore = "requires the Qt source in qt-src"]
+    fn build_timezone() {
+        let mut qt_configuration = default_configuration();
+        qt_configuration.set_timezone_backend(configure::TimezoneBackend::System);
+        assert_eq!(qt_configuration.feature("icu"), Some(false));
+        build_with_configuration(
+            "timezone",
+            qt_configuration,
+            &[feature_sources("timezone"), feature_sources("icu")].concat(),
+            "#include <QtCore/qtimezone.h>\n\
+             QByteArray timezone_test() { return QTimeZone(\"Europe/Oslo\").id(); }\n",
+        );
+    }
+
+    #[test]
+    #[ignore = "requires the Qt source in qt-src"]
+    fn build_xml_stream_reader() {
+        let mut qt_configuration = default_configuration();
+        qt_configuration.set_xml_stream(true, false);
+        qt_configuration.set_feature("xml", false);
+        build_with_configuration(
+            "xml_stream_reader",
+            qt_configuration,
+            feature_sources("xmlstream"),
+            "#include <QtCore/qxmlstream.h>\n\
+             #if QT_CONFIG(xmlstreamwriter)\n\
+             #error \"xmlstreamwriter is enabled\"\n\
+             #endif\n\
+             bool xml_stream_reader_test()\n\
+             {\n\
+                 QXmlStreamReader reader(\"<qt/>\");\n\
+                 reader.readNext();\n\
+                 return reader.hasError();\n\
+             }\n",
+        );
+    }
+
+    // Features which can be disabled with a QtConfiguration setter, as (name, setter,
+    // QT_CONFIG features which the setter turns off, QtCore sources gated on them, test
+    // source which uses the feature when enabled)
+    type FeatureSetterCase = (
+        &'static str,
+        fn(&mut configure::QtConfiguration, bool),
+        &'static [&'static str],
+        Vec<&'static str>,
+        Option<&'static str>,
+    );
+
+    fn feature_setter_cases() -> Vec<FeatureSetterCase> {
+        vec![
+            (
+                "thread",
+                |config, enabled| {
+                    config.set_thread(enabled);
+                    config.set_concurrent(enabled);
+                },
+                &["thread"],
+                [feature_sources("thread"), feature_sources("future")].concat(),
+                None,
+            ),
+            (
+                "glib",
+                |config, enabled| config.set_glib(enabled),
+                &["glib"],
+                feature_sources("glib").to_vec(),
+                None,
+            ),
+            (
+                "network_features",
+                |config, enabled| config.set_network_features(enabled),
+                &["topleveldomain"],
+                feature_sources("topleveldomain").to_vec(),
+                None,
+            ),
+            (
+                "ipc",
+                |config, enabled| config.set_ipc(enabled),
+                &["sharedmemory", "systemsemaphore"],
+                [
+                    feature_sources("sharedmemory"),
+                    feature_sources("systemsemaphore"),
+                ]
+                .concat(),
+                Some(
+                    "#include <QtCore/qsharedmemory.h>\n\
+                     #include <QtCore/qsystemsemaphore.h>\n\
+                     bool with_ipc_test()\n\
+                     {\n\
+                         QSystemSemaphore semaphore(QStringLiteral(\"qt-cargo-test\"));\n\
+                         QSharedMemory memory(QStringLiteral(\"qt-cargo-test\"));\n\
+                         return memory.create(16);\n\
+                     }\n",
+                ),
+            ),
+            (
+                "process",
+                |config, enabled| config.set_process(enabled),
+                &["process"],
+                [
+                    feature_sources("process"),
+                    feature_sources("processenvironment"),
+                ]
+                .concat(),
+                Some(
+                    "#include <QtCore/qprocess.h>\n\
+                     int with_process_test() { QProcess process; return int(process.state()); }\n",
+                ),
+            ),
+            (
+                "animation",
+                |config, enabled| config.set_animation(enabled),
+                &["animation"],
+                [feature_sources("animation"), feature_sources("easingcurve")].concat(),
+                Some(
+                    "#include <QtCore/qpropertyanimation.h>\n\
+                     int with_animation_test()\n\
+                     {\n\
+                         QPropertyAnimation animation;\n\
+                         animation.setDuration(100);\n\
+                         return animation.duration();\n\
+                     }\n",
+                ),
+            ),
+            (
+                "plugins",
+                |config, enabled| config.set_plugins(enabled),
+                &["library"],
+                feature_sources("library").to_vec(),
+                Some(
+                    "#include <QtCore/qpluginloader.h>\n\
+                     bool with_plugins_test() { QPluginLoader loader(\"qt-cargo-test\"); return loader.load(); }\n",
+                ),
+            ),
+            (
+                "regular_expression",
+                |config, enabled| config.set_regular_expression(enabled),
+                &["regularexpression"],
+                feature_sources("regularexpression").to_vec(),
+                Some(
+                    "#include <QtCore/qregularexpression.h>\n\
+                     bool with_regular_expression_test()\n\
+                     {\n\
+                         return QRegularExpression(\"^q[a-z]+$\").match(QString(\"qt\")).hasMatch();\n\
+                     }\n",
+                ),
+            ),
+            (
+                "dbus",
+                |config, enabled| config.set_dbus(enabled),
+                &["dbus"],
+                Vec::new(),
+                None,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_feature_setter_cases() {
+        for (name, set_enabled, features, _, _) in feature_setter_cases() {
+            let mut qt_configuration = default_configuration();
+            set_enabled(&mut qt_configuration, false);
+            assert!(
+                qt_configuration.validate_feature_dependencies().is_ok(),
+                "{}",
+                name
+            );
+            for feature in features {
+                assert_eq!(
+                    qt_configuration.feature(feature),
+                    Some(false),
+                    "{}",
+                    feature
+                );
+            }
+        }
+    }
+
+    // Builds QtCore with each feature of feature_setter_cases disabled, checking that
+    // QT_CONFIG reports it as disabled, and with it enabled and used where possible
+    #[test]
+    #[ignore = "requires the Qt source in qt-src"]
+    fn build_feature_setters() {
+        for (name, set_enabled, features, sources, with_source) in feature_setter_cases() {
+            let mut qt_configuration = default_configuration();
+            set_enabled(&mut qt_configuration, false);
+            let mut without_source = String::from("#include <QtCore/private/qglobal_p.h>\n");
+            for feature in features {
+                without_source.push_str(&format!(
+                    "#if QT_CONFIG({0})\n#error \"{0} is enabled\"\n#endif\n",
+                    feature
+                ));
+            }
+            build_with_configuration(
+                &format!("without_{}", name),
+                qt_configuration,
+                &sources,
+                &without_source,
+            );
+
+            if let Some(with_source) = with_source {
+                let mut qt_configuration = default_configuration();
+                set_enabled(&mut qt_configuration, true);
+                build_with_configuration(
+                    &format!("with_{}", name),
+                    qt_configuration,
+                    &sources,
+                    with_source,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_pointers_option() {
+        let has_frame_pointer_flag = |options: &QtBuildOptions| {
+            let (mut builder, _temp) = util::test_builder();
+            builder.opt_level(2);
+            apply_build_options(&mut builder, options).unwrap();
+            builder
+                .get_compiler()
+                .args()
+                .iter()
+                .any(|arg| arg == "-fno-omit-frame-pointer")
+        };
+
+        assert!(!has_frame_pointer_flag(&QtBuildOptions::default()));
+        assert!(has_frame_pointer_flag(&QtBuildOptions {
+            frame_pointers: true,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_target_data_layout_option() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        std::fs::create_dir_all(qt_source.join("qtbase/src/corelib")).unwrap();
+        let qconfig = |options: &QtBuildOptions| {
+            let qt_build = temp.path().join("build");
+            let mut builder = cc::Build::new();
+            configure_qtcore_for_linux_with_options(
+                &mut builder,
+                Some(&qt_build),
+                &qt_source,
+                options,
+            )
+            .unwrap();
+            std::fs::read_to_string(qt_build.join("qt_config_headers/QtCore/qconfig.h")).unwrap()
+        };
+
+        let big_endian = Some(target::TargetDataLayout::from_target_triple(
+            "s390x-unknown-linux-gnu",
+        ));
+        assert!(qconfig(&QtBuildOptions {
+            target_data_layout: big_endian,
+            ..Default::default()
+        })
+        .contains("#define Q_BYTE_ORDER Q_BIG_ENDIAN\n"));
+
+        let mut qt_configuration = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut qt_configuration);
+        let qconfig = qconfig(&QtBuildOptions {
+            qt_configuration: Some(qt_configuration),
+            target_data_layout: big_endian,
+            ..Default::default()
+        });
+        assert!(qconfig.contains("#define Q_BYTE_ORDER Q_BIG_ENDIAN\n"));
+        assert!(qconfig.contains("#define QT_POINTER_SIZE 8\n"));
+    }
+
+    #[test]
+    fn test_configure_qtcore_for_windows_msvc() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        std::fs::create_dir_all(qt_source.join("qtbase/src/corelib")).unwrap();
+        let qt_build = temp.path().join("build");
+        let triples = target::BuildTriples::resolve(
+            Some(&target::default_triple()),
+            Some("x86_64-pc-windows-msvc"),
+        );
+        let mut builder = cc::Build::new();
+        // Not run, cc selects the MSVC flags from the compiler name and target
+        builder.compiler("cl.exe");
+        let options = QtBuildOptions {
+            build_triples: Some(triples),
+            ..Default::default()
+        };
+        configure_qtcore_for_windows_msvc_with_options(
+            &mut builder,
+            Some(&qt_build),
+            &qt_source,
+            &options,
+        )
+        .unwrap();
+
+        let args = builder.get_compiler().args().to_vec();
+        // The MSVC runtime flag, which cc adds for msvc targets only
+        assert!(args.iter().any(|arg| arg == "-MD"), "{:?}", args);
+        assert_eq!(args.iter().filter(|arg| *arg == "/std:c++17").count(), 1);
+        assert!(!args.iter().any(|arg| arg == "-std=c++17"));
+        assert!(args
+            .iter()
+            .any(|arg| arg == "-DUNICODE" || arg == "/DUNICODE"));
+        let qplatformdefs =
+            std::fs::read_to_string(qt_build.join("qt_config_headers/QtCore/qplatformdefs.h"))
+                .unwrap();
+        assert!(qplatformdefs.contains("win32-msvc"), "{}", qplatformdefs);
+    }
+
+    #[test]
+    fn test_apply_version_defines() {
+        let (mut builder, _temp) = util::test_builder();
+        apply_version_defines(&mut builder, version::QtVersion::new(6, 5, 3));
+        let args = builder.get_compiler().args().to_vec();
+        for define in [
+            "-DQT_VERSION_MAJOR=6",
+            "-DQT_VERSION_MINOR=5",
+            "-DQT_VERSION_PATCH=3",
+            "-DQT_VERSION_STR=\"6.5.3\"",
+        ] {
+            assert!(args.iter().any(|arg| arg == define), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_compare_qt_versions() {
+        let temp = qt_build_temp_dir();
+        let write_source = |name: &str, version: &str, class_name: &str| {
+            let qt_source = temp.path().join(name);
+            let corelib = qt_source.join("qtbase/src/corelib");
+            std::fs::create_dir_all(corelib.join("global")).unwrap();
+            std::fs::create_dir_all(corelib.join("kernel")).unwrap();
+            std::fs::create_dir_all(qt_source.join(configure::qplatformdefs_path("linux-clang")))
+                .unwrap();
+            std::fs::write(
+                corelib.join("global/qglobal.h"),
+                format!("#define QT_VERSION_STR \"{}\"\n", version),
+            )
+            .unwrap();
+            std::fs::write(
+                corelib.join("kernel/qobject.h"),
+                "class Q_CORE_EXPORT QObject { };\n",
+            )
+            .unwrap();
+            std::fs::write(
+                corelib.join("kernel/qtimer.h"),
+                format!("class Q_CORE_EXPORT {} {{ }};\n", class_name),
+            )
+            .unwrap();
+            qt_source
+        };
+        let old_source = write_source("qt-src-old", "6.2.0", "QTimer");
+        let new_source = write_source("qt-src-new", "6.2.1", "QChronoTimer");
+
+        let delta =
+            compare_qt_versions(&old_source, &new_source, temp.path().join("delta")).unwrap();
+        assert_eq!(delta.old_version, Some(version::QtVersion::new(6, 2, 0)));
+        assert_eq!(delta.new_version, Some(version::QtVersion::new(6, 2, 1)));
+        assert_eq!(delta.added_classes, vec!["QChronoTimer".to_string()]);
+        assert_eq!(delta.removed_classes, vec!["QTimer".to_string()]);
+        assert!(delta.changed_features.is_empty());
+        // The source trees differ in location only for QObject
+        assert!(!delta
+            .output
+            .changed
+            .contains(&PathBuf::from("qt_forwarding_headers/QtCore/QObject")));
+
+        let delta =
+            compare_qt_versions(&old_source, &old_source, temp.path().join("same")).unwrap();
+        assert!(delta.is_empty());
+    }
+
+    #[test]
+    fn test_configure_qtcore_missing_source() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        let qt_build = temp.path().join("build");
+        std::fs::create_dir_all(qt_source.join(configure::qplatformdefs_path("linux-clang")))
+            .unwrap();
+        let mut builder = cc::Build::new();
+        match configure_qtcore_for_linux(&mut builder, Some(&qt_build), &qt_source) {
+            Err(QtBuildError::MissingSource { path }) => {
+                assert_eq!(path, qt_source.join("qtbase/src/corelib"))
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_configure_qtcore_for_android() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        std::fs::create_dir_all(qt_source.join("qtbase/src/corelib")).unwrap();
+        let qt_build = temp.path().join("build");
+        let triples = target::BuildTriples::resolve(
+            Some(&target::default_triple()),
+            Some("aarch64-linux-android"),
+        );
+        let mut builder = cc::Build::new();
+        // Not run, cc would otherwise look for the NDK compiler
+        builder.compiler("clang++");
+        let options = QtBuildOptions {
+            build_triples: Some(triples),
+            ..Default::default()
+        };
+        configure_qtcore_for_android_with_options(
+            &mut builder,
+            Some(&qt_build),
+            &qt_source,
+            &options,
+        )
+        .unwrap();
+
+        let args = builder.get_compiler().args().to_vec();
+        assert!(
+            args.iter()
+                .any(|arg| arg == "--target=aarch64-linux-android"),
+            "{:?}",
+            args
+        );
+        assert_eq!(args.iter().filter(|arg| *arg == "-std=c++17").count(), 1);
+        assert!(args.iter().any(|arg| arg == "-DANDROID"));
+        let qplatformdefs =
+            std::fs::read_to_string(qt_build.join("qt_config_headers/QtCore/qplatformdefs.h"))
+                .unwrap();
+        assert!(qplatformdefs.contains("android-clang"), "{}", qplatformdefs);
+    }
+
+    #[test]
+    fn test_configure_qtcore_for_macos() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        std::fs::create_dir_all(qt_source.join("qtbase/src/corelib")).unwrap();
+        let qt_build = temp.path().join("build");
+        let mut builder = cc::Build::new();
+        configure_qtcore_for_macos(&mut builder, Some(&qt_build), &qt_source).unwrap();
+
+        let args = builder.get_compiler().args().to_vec();
+        assert!(args.iter().any(|arg| arg == "-stdlib=libc++"));
+        assert!(!args.iter().any(|arg| arg == "objective-c++"));
+        let qplatformdefs =
+            std::fs::read_to_string(qt_build.join("qt_config_headers/QtCore/qplatformdefs.h"))
+                .unwrap();
+        assert!(qplatformdefs.contains("macx-clang"), "{}", qplatformdefs);
+
+        // cc places the builder flags before the source file on the compile command line
+        let objective_cpp_builder = objective_cpp_builder(&builder);
+        let args = objective_cpp_builder.get_compiler().args().to_vec();
+        let language = args.iter().position(|arg| arg == "-x").unwrap();
+        assert_eq!(args[language + 1], "objective-c++");
+        assert!(args.iter().any(|arg| arg == "-stdlib=libc++"));
+    }
+
+    #[test]
+    fn test_as_include_flags() {
+        let mut options = QtBuildOptions::default();
+        options.prepend_include("/override");
+        let paths = QtBuildPaths {
+            build_path: PathBuf::from("/build"),
+            include_paths: [
+                options.prepended_include_paths.clone(),
+                vec![
+                    PathBuf::from("/build/qt_config_headers"),
+                    PathBuf::from("/build/qt_forwarding_headers"),
+                ],
+            ]
+            .concat(),
+        };
+        assert_eq!(
+            paths.as_include_flags(),
+            [
+                "-I/override",
+                "-I/build/qt_config_headers",
+                "-I/build/qt_forwarding_headers"
+            ]
+        );
+
+        // The flags match the include paths of a builder configured with the paths
+        let (mut builder, _temp) = util::test_builder();
+        for include_path in &paths.include_paths {
+            builder.include(include_path);
+        }
+        let build_flags = flags::describe_build_flags(&builder);
+        assert_eq!(build_flags.include_paths, paths.include_paths);
+    }
+
+    #[test]
+    fn test_forwarding_header_for_class() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-class-header-test").unwrap();
+        let source_path = temp.path().join("corelib/kernel");
+        std::fs::create_dir_all(&source_path).unwrap();
+        std::fs::write(
+            source_path.join("qobject.h"),
+            "class Q_CORE_EXPORT QObject\n{\n};\n",
+        )
+        .unwrap();
+
+        let build_path = temp.path().join("build");
+        let mut builder = cc::Build::new();
+        write_qtcore_forwarding_headers(&mut builder, &build_path, temp.path().join("corelib"))
+            .unwrap();
+        let header_path = forwarding_header_for_class(&build_path, "QObject").unwrap();
+        assert_eq!(
+            header_path,
+            build_path.join("qt_forwarding_headers/QtCore/QObject")
+        );
+        assert!(std::fs::read_to_string(&header_path)
+            .unwrap()
+            .contains("qobject.h"));
+        assert_eq!(forwarding_header_for_class(&build_path, "QString"), None);
+        assert_eq!(forwarding_header_for_class(&build_path, "../QObject"), None);
+    }
+
+    #[test]
+    fn test_write_output_gitignore() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-gitignore-test").unwrap();
+        let gitignore_path = write_output_gitignore(temp.path().join("out")).unwrap();
+        assert_eq!(gitignore_path, temp.path().join("out/.gitignore"));
+        let content = std::fs::read_to_string(&gitignore_path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        for entry in [
+            "/qt_config_headers/",
+            "/qt_forwarding_headers/",
+            "/qt_patched_headers/",
+            "/qt_classes.rs",
+            "/qt_class_headers.cache",
+        ] {
+            assert!(lines.contains(&entry), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_build_path_conflict() {
+        assert_eq!(
+            build_path_conflict(Path::new("/target/out"), Path::new("/target/out/qt")),
+            None
+        );
+        assert_eq!(
+            build_path_conflict(Path::new("/target/out"), Path::new("/target/out")),
+            None
+        );
+        let message =
+            build_path_conflict(Path::new("/target/out"), Path::new("/tmp/qt-build")).unwrap();
+        assert!(
+            message.contains("\"/tmp/qt-build\" is ignored"),
+            "{}",
+            message
+        );
+        assert!(message.contains("OUT_DIR \"/target/out\""), "{}", message);
+        assert!(message.contains(STRICT_BUILD_PATH_ENV_VAR), "{}", message);
+    }
+
+    #[test]
+    fn test_link_arg_directives() {
+        assert_eq!(
+            link_arg_directives(&["-Wl,--start-group", "-lQt6Core", "-Wl,--end-group"]),
+            [
+                "cargo:rustc-link-arg=-Wl,--start-group",
+                "cargo:rustc-link-arg=-lQt6Core",
+                "cargo:rustc-link-arg=-Wl,--end-group",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_compile_defines() {
+        let mut config = configure::QtConfiguration::new();
+        config.set_foreach(false);
+        let (mut builder, _temp) = util::test_builder();
+        apply_compile_defines(&mut builder, &config);
+        let build_flags = flags::describe_build_flags(&builder);
+        assert!(build_flags
+            .defines
+            .contains(&("QT_NO_FOREACH".to_string(), None)));
+    }
+
+    #[test]
+    fn test_compiler_option() {
+        let compiler = util::find_compiler("c++").unwrap();
+        let options = QtBuildOptions {
+            compiler: Some(compiler.clone()),
+            ..Default::default()
+        };
+        let (mut builder, _temp) = util::test_builder();
+        apply_build_options(&mut builder, &options).unwrap();
+        assert_eq!(builder.get_compiler().path(), compiler);
+
+        assert!(util::find_compiler("qt-cargo-nonexistent-g++").is_err());
+        let options = QtBuildOptions {
+            compiler: Some(PathBuf::from("qt-cargo-nonexistent-g++")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply_build_options(&mut builder, &options),
+            Err(QtBuildError::Toolchain(_))
+        ));
+    }
+
+    #[test]
+    fn test_compiler_workarounds_option() {
+        let compiler_args = |options: &QtBuildOptions| {
+            let (mut builder, _temp) = util::test_builder();
+            apply_build_options(&mut builder, options).unwrap();
+            builder
+                .get_compiler()
+                .args()
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let default_args = compiler_args(&QtBuildOptions::default());
+        let (builder, _temp) = util::test_builder();
+        let compiler = toolchain::detect_compiler(&builder).unwrap();
+        for flag in toolchain::workaround_flags(&compiler) {
+            assert!(default_args.iter().any(|arg| arg == flag), "{}", flag);
+        }
+
+        let overridden_args = compiler_args(&QtBuildOptions {
+            compiler_workarounds: Some(vec!["-Wno-unused-parameter".to_string()]),
+            ..Default::default()
+        });
+        assert!(overridden_args.contains(&"-Wno-unused-parameter".to_string()));
+        assert!(!overridden_args.contains(&"-Wno-deprecated-copy".to_string()));
+    }
+
+    #[test]
+    fn test_prepended_include_paths() {
+        let mut options = QtBuildOptions::default();
+        options.prepend_include("/override/first");
+        options.prepend_include("/override/second");
+
+        let (mut builder, _temp) = util::test_builder();
+        apply_build_options(&mut builder, &options).unwrap();
+        builder.include("/generated/qt_config_headers");
+
+        let include_paths: Vec<_> = builder
+            .get_compiler()
+            .args()
+            .windows(2)
+            .filter(|args| args[0] == "-I")
+            .map(|args| PathBuf::from(&args[1]))
+            .collect();
+        assert_eq!(
+            include_paths,
+            vec![
+                PathBuf::from("/override/first"),
+                PathBuf::from("/override/second"),
+                PathBuf::from("/generated/qt_config_headers"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tools_shared_forwarding_headers() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        let tools_shared_path = qt_source.join(sources::TOOLS_SHARED_PATH);
+        std::fs::create_dir_all(&tools_shared_path).unwrap();
+        std::fs::write(tools_shared_path.join("depfile_shared.h"), "").unwrap();
+
+        let mut builder = cc::Build::new();
+        let include_paths =
+            write_tools_shared_forwarding_headers(&mut builder, temp.path(), &qt_source).unwrap();
+
+        let forwarding_headers_path = temp.path().join("qt_forwarding_headers/QtToolsShared");
+        assert_eq!(include_paths, vec![forwarding_headers_path.clone()]);
+        let forwarding_header =
+            std::fs::read_to_string(forwarding_headers_path.join("depfile_shared.h")).unwrap();
+        assert!(forwarding_header.contains("depfile_shared.h"));
+    }
+
+    #[test]
+    fn test_export_bindgen_includes() {
+        let temp = qt_build_temp_dir();
+        let qt_source = temp.path().join("qt-src");
+        let qglobal_path = qt_source.join("qtbase/src/corelib/global");
+        let qplatformdefs = qt_source.join(configure::qplatformdefs_path("linux-clang"));
+        std::fs::create_dir_all(&qglobal_path).unwrap();
+        std::fs::create_dir_all(qplatformdefs.parent().unwrap()).unwrap();
+        std::fs::write(&qplatformdefs, "").unwrap();
+        std::fs::write(
+            qglobal_path.join("qglobal.h"),
+            "#include <QtCore/qconfig.h>\n#include <QtCore/qtcore-config.h>\n",
+        )
+        .unwrap();
+
+        let include_dir = temp.path().join("bindgen");
+        let include_paths = export_bindgen_includes(&qt_source, "QtCore", &include_dir).unwrap();
+        assert!(util::find_unresolved_includes(&include_paths).is_empty());
+        assert!(matches!(
+            export_bindgen_includes(&qt_source, "QtGui", &include_dir),
+            Err(QtBuildError::InvalidArgument { .. })
+        ));
+
+        // Parse <QtCore/qglobal.h> using only the exported include paths
+        let source = temp.path().join("bindings.h");
+        std::fs::write(&source, "#include <QtCore/qglobal.h>\nQT_VERSION_STR\n").unwrap();
+        let (builder, _temp) = util::test_builder();
+        let output = builder
+            .get_compiler()
+            .to_command()
+            .args(
+                include_paths
+                    .iter()
+                    .map(|path| format!("-I{}", path.display())),
+            )
+            .arg("-E")
+            .arg(&source)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("\"6.2.0\""));
+    }
+
+    #[test]
+    fn build_pcre2() {
+        let qt_source = util::qt_src_path();
+
+        let (mut builder, _temp) = util::test_builder();
+        builder.cpp(false);
+        configure_pcre2(&mut builder, &qt_source, PCRE2_DEFAULT_CODE_UNIT_WIDTH).unwrap();
+
+        builder.compile("pcre2"); // No panic -> test pass
+    }
+
+    #[test]
+    fn test_bootstrap_platform_sources() {
+        assert_eq!(
+            bootstrap_platform_sources("x86_64-unknown-linux-gnu").unwrap(),
+            sources::BOOTSTRAP_SOURCES_UNIX
+        );
+        assert_eq!(
+            bootstrap_platform_sources("aarch64-apple-darwin").unwrap(),
+            sources::BOOTSTRAP_SOURCES_UNIX
+        );
+        assert_eq!(
+            bootstrap_platform_sources("x86_64-pc-windows-msvc").unwrap(),
+            sources::BOOTSTRAP_SOURCES_WIN32
+        );
+        assert!(matches!(
+            bootstrap_platform_sources("x86_64-pc-windows-gnu"),
+            Err(QtBuildError::InvalidArgument { .. })
+        ));
+    }
+
+    #[test]
+    fn test_configure_pcre2_code_unit_width() {
+        let (mut builder, _temp) = util::test_builder();
+        assert!(matches!(
+            configure_pcre2(&mut builder, "qt-src", 12),
+            Err(QtBuildError::InvalidArgument { .. })
+        ));
+    }
 }
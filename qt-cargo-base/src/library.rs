@@ -0,0 +1,518 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::QtBuildError;
+use crate::flags::{self, BuildFlags};
+use crate::host_tools;
+use crate::util;
+use crate::version::QtVersion;
+
+// Static and shared library output
+//
+// cc::Build::compile always produces a static archive. For shared output
+// the sources are compiled as position independent object files, which
+// are then linked into a shared library using the configured compiler
+// driver (so that the C++ standard library is linked in as well). Shared
+// libraries are versioned the same way as the Qt build system does:
+// the SONAME (or install name on Apple platforms) contains the major
+// version, with symlinks for the unversioned library name.
+//
+// cc runs one compiler process per source file and adds the object files
+// to the archive in chunks, so the length of a compiler command line is
+// the include paths, defines and flags plus a single source file. Large
+// configurations can still exceed the OS argument limits with the flags
+// alone; compile_with_argument_budget moves the include paths and
+// defines to a response file ("@file", supported by gcc and clang) when
+// the command line would exceed a given budget.
+
+/// Default argument length budget in bytes for compile_with_argument_budget. This is the
+/// per-argument limit on Linux (MAX_ARG_STRLEN); the total limit is usually larger.
+pub const DEFAULT_ARGUMENT_BUDGET: usize = 128 * 1024;
+
+/// The kind of library produced by compile_library
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LibraryKind {
+    /// Static archive, e.g. "libQt6Core.a"
+    #[default]
+    Static,
+    /// Shared library with the given version, e.g. "libQt6Core.so.6.2.0"
+    Shared(QtVersion),
+}
+
+fn is_apple_target(target: &str) -> bool {
+    target.contains("-apple-")
+}
+
+/// Returns the file name of the shared library, and the name of the symlinks which
+/// should point to it (the SONAME first)
+fn shared_library_names(name: &str, version: QtVersion, target: &str) -> (String, Vec<String>) {
+    if is_apple_target(target) {
+        (
+            format!("lib{}.{}.dylib", name, version.major),
+            vec![format!("lib{}.dylib", name)],
+        )
+    } else {
+        (
+            format!("lib{}.so.{}", name, version),
+            vec![
+                format!("lib{}.so.{}", name, version.major),
+                format!("lib{}.so", name),
+            ],
+        )
+    }
+}
+
+/// Returns the file name of the static library cc::Build::compile writes for name with
+/// the builder, "<name>.lib" for MSVC and "lib<name>.a" otherwise
+pub(crate) fn static_library_name(builder: &cc::Build, name: &str) -> String {
+    match builder.get_compiler().is_like_msvc() {
+        true => format!("{}.lib", name),
+        false => format!("lib{}.a", name),
+    }
+}
+
+fn create_symlink(target: &str, link_path: &Path) -> io::Result<()> {
+    let _ = std::fs::remove_file(link_path);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, link_path)?;
+    #[cfg(not(unix))]
+    std::fs::copy(link_path.with_file_name(target), link_path)?;
+    Ok(())
+}
+
+/// Compiles the sources added to the builder into a library named "lib<name>" in out_dir
+/// ("<name>.lib" for static MSVC libraries), and emits cargo link directives for it.
+/// Returns the library path, or HostTool if linking a shared library fails.
+///
+/// Shared libraries are compiled with -fPIC and linked with the builder's compiler.
+/// Libraries QtCore depends on, such as pthread, should be added as link_args.
+pub fn compile_library(
+    builder: &mut cc::Build,
+    name: &str,
+    out_dir: &Path,
+    kind: LibraryKind,
+    link_args: &[&str],
+) -> Result<PathBuf, QtBuildError> {
+    builder.out_dir(out_dir);
+    let version = match kind {
+        LibraryKind::Static => {
+            builder.compile(name);
+            return Ok(out_dir.join(static_library_name(builder, name)));
+        }
+        LibraryKind::Shared(version) => version,
+    };
+
+    builder.pic(true).cargo_metadata(false);
+    let objects = builder.compile_intermediates();
+
+    let target = crate::target::BuildTriples::from_env().target;
+    let (library_name, symlink_names) = shared_library_names(name, version, &target);
+    let library_path = out_dir.join(&library_name);
+
+    let compiler = builder.get_compiler();
+    let mut command = compiler.to_command();
+    command.arg("-shared").args(&objects);
+    if is_apple_target(&target) {
+        command
+            .arg("-dynamiclib")
+            .arg(format!("-Wl,-install_name,@rpath/{}", library_name))
+            .arg(format!(
+                "-Wl,-compatibility_version,{}.{}",
+                version.major, version.minor
+            ))
+            .arg(format!("-Wl,-current_version,{}", version));
+    } else {
+        command.arg(format!("-Wl,-soname,{}", symlink_names[0]));
+    }
+    command.args(link_args).arg("-o").arg(&library_path);
+
+    host_tools::run_host_tool(command, compiler.path(), &library_path)?;
+
+    for symlink_name in &symlink_names {
+        let symlink_path = out_dir.join(symlink_name);
+        create_symlink(&library_name, &symlink_path)
+            .map_err(QtBuildError::config_write(symlink_path))?;
+    }
+
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+    println!("cargo:rustc-link-lib=dylib={}", name);
+
+    Ok(library_path)
+}
+
+/// Returns the argument as written in a gcc/clang response file
+fn response_file_argument(argument: &str) -> String {
+    format!(
+        "\"{}\"",
+        argument.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Compiles the sources added to the builder into a static library named "lib<name>.a" in
+/// out_dir, like compile_library with LibraryKind::Static, and returns the library path.
+///
+/// If the compiler command line for a source file would be longer than argument_budget
+/// bytes (see DEFAULT_ARGUMENT_BUDGET), the include paths and defines are written to
+/// "<name>.rsp" in out_dir, and the sources are compiled with the builder's compiler and
+/// remaining flags plus the response file instead, and archived with the builder's
+/// archiver. Returns InvalidArgument if the command line still exceeds the budget.
+pub fn compile_with_argument_budget(
+    builder: &cc::Build,
+    name: &str,
+    out_dir: &Path,
+    argument_budget: usize,
+) -> Result<PathBuf, QtBuildError> {
+    let build_flags = flags::describe_build_flags(builder);
+    let sources: Vec<PathBuf> = builder.get_files().map(Path::to_path_buf).collect();
+    let arguments_length = |build_flags: &BuildFlags| {
+        let compiler = build_flags.compiler.as_os_str().len();
+        let include_paths = build_flags
+            .include_paths
+            .iter()
+            .map(|path| path.as_os_str().len() + 3);
+        let defines = build_flags
+            .defines
+            .iter()
+            .map(|(key, value)| key.len() + value.as_ref().map_or(0, |value| value.len() + 1) + 3);
+        let flags = build_flags.flags.iter().map(|flag| flag.len() + 1);
+        // "-o <object> -c <source>", where the object path is the source path below out_dir
+        let longest_source = sources
+            .iter()
+            .map(|source| source.as_os_str().len())
+            .max()
+            .unwrap_or_default();
+        compiler
+            + include_paths.chain(defines).chain(flags).sum::<usize>()
+            + out_dir.as_os_str().len()
+            + 2 * longest_source
+            + 16
+    };
+
+    if arguments_length(&build_flags) <= argument_budget {
+        let mut compile_builder = builder.clone();
+        return compile_library(
+            &mut compile_builder,
+            name,
+            out_dir,
+            LibraryKind::Static,
+            &[],
+        );
+    }
+
+    std::fs::create_dir_all(out_dir).map_err(QtBuildError::config_write(out_dir))?;
+    let response_file_path = out_dir.join(format!("{}.rsp", name));
+    let mut response_file = String::new();
+    for include_path in &build_flags.include_paths {
+        let argument = format!("-I{}", include_path.display());
+        response_file.push_str(&response_file_argument(&argument));
+        response_file.push('\n');
+    }
+    for (key, value) in &build_flags.defines {
+        let argument = match value {
+            Some(value) => format!("-D{}={}", key, value),
+            None => format!("-D{}", key),
+        };
+        response_file.push_str(&response_file_argument(&argument));
+        response_file.push('\n');
+    }
+    std::fs::write(&response_file_path, response_file)
+        .map_err(QtBuildError::config_write(&response_file_path))?;
+
+    let mut response_flags = BuildFlags {
+        compiler: build_flags.compiler.clone(),
+        include_paths: Vec::new(),
+        defines: Vec::new(),
+        flags: build_flags.flags.clone(),
+    };
+    response_flags
+        .flags
+        .push(format!("@{}", response_file_path.display()));
+    if arguments_length(&response_flags) > argument_budget {
+        return Err(QtBuildError::InvalidArgument {
+            message: format!(
+                "The compiler flags for {} exceed the argument budget of {} bytes",
+                name, argument_budget
+            ),
+        });
+    }
+
+    // cc::Build can't remove include paths or defines, so the sources are compiled with
+    // the builder's compiler (which is set up for its target) and the remaining flags
+    let compiler = builder.get_compiler();
+    let objects_dir = out_dir.join(format!("{}.objects", name));
+    std::fs::create_dir_all(&objects_dir).map_err(QtBuildError::config_write(&objects_dir))?;
+    let mut objects = Vec::new();
+    for (index, source) in sources.iter().enumerate() {
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+        let object = objects_dir.join(format!("{}-{}.o", index, stem));
+        let mut command = Command::new(compiler.path());
+        command
+            .envs(compiler.get_envs())
+            .args(&response_flags.flags)
+            .arg("-o")
+            .arg(&object)
+            .arg("-c")
+            .arg(source);
+        host_tools::run_host_tool(command, compiler.path(), source)?;
+        objects.push(object);
+    }
+
+    let library_path = out_dir.join(static_library_name(builder, name));
+    let _ = std::fs::remove_file(&library_path);
+    let mut archiver = builder.get_archiver();
+    let archiver_path = PathBuf::from(archiver.get_program());
+    archiver.arg("crs").arg(&library_path).args(&objects);
+    host_tools::run_host_tool(archiver, &archiver_path, &library_path)?;
+
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+    println!("cargo:rustc-link-lib=static={}", name);
+    Ok(library_path)
+}
+
+// Runs ar (or $AR) with the given arguments in dir
+fn run_ar(dir: &Path, args: &[&std::ffi::OsStr]) -> io::Result<String> {
+    let ar = std::env::var_os("AR").unwrap_or_else(|| "ar".into());
+    let output = Command::new(&ar).current_dir(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{:?} failed: {}",
+            ar,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Combines the static libraries in inputs, for example QtCore and the bundled pcre2,
+/// into a single static library at output (replacing any existing file). The inputs may
+/// be regular or thin archives; the output is a regular archive containing copies of the
+/// object files. Object files with the same name in different inputs are all kept.
+pub fn merge_archives(output: &Path, inputs: &[PathBuf]) -> io::Result<()> {
+    let work_dir = output.with_extension("merge");
+    let _ = std::fs::remove_dir_all(&work_dir);
+    let _ = std::fs::remove_file(output);
+    std::fs::create_dir_all(&work_dir)?;
+
+    let mut objects = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let input = util::safe_canonicalize(input)?;
+        let mut magic = [0u8; 8];
+        io::Read::read_exact(&mut std::fs::File::open(&input)?, &mut magic)?;
+        if &magic == b"!<thin>\n" {
+            // Thin archives reference the object files, relative to the archive
+            let archive_dir = input.parent().unwrap();
+            let members = run_ar(&work_dir, &["t".as_ref(), input.as_os_str()])?;
+            objects.extend(members.lines().map(|member| archive_dir.join(member)));
+        } else {
+            // Extract each archive to a separate directory, so that object files
+            // with the same name do not overwrite each other
+            let extract_dir = work_dir.join(index.to_string());
+            std::fs::create_dir_all(&extract_dir)?;
+            let members = run_ar(&extract_dir, &["t".as_ref(), input.as_os_str()])?;
+            run_ar(&extract_dir, &["x".as_ref(), input.as_os_str()])?;
+            objects.extend(members.lines().map(|member| extract_dir.join(member)));
+        }
+    }
+
+    let output = util::safe_canonicalize(output)?;
+    let mut args: Vec<&std::ffi::OsStr> = vec!["qcs".as_ref(), output.as_os_str()];
+    args.extend(objects.iter().map(|object| object.as_os_str()));
+    let result = run_ar(&work_dir, &args);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod qt_cargo_base_library_tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_shared_library_names() {
+        let version = QtVersion::new(6, 2, 0);
+        assert_eq!(
+            shared_library_names("Qt6Core", version, "x86_64-unknown-linux-gnu"),
+            (
+                "libQt6Core.so.6.2.0".to_string(),
+                vec!["libQt6Core.so.6".to_string(), "libQt6Core.so".to_string()]
+            )
+        );
+        assert_eq!(
+            shared_library_names("Qt6Core", version, "aarch64-apple-darwin").0,
+            "libQt6Core.6.dylib"
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_compile_shared_library() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-library-test").unwrap();
+        let source = temp.path().join("shared.cpp");
+        std::fs::write(
+            &source,
+            "extern \"C\" int qt_cargo_shared_test() { return 42; }\n",
+        )
+        .unwrap();
+
+        let (mut builder, _build_temp) = util::test_builder();
+        builder.file(&source);
+        let library = compile_library(
+            &mut builder,
+            "sharedtest",
+            temp.path(),
+            LibraryKind::Shared(QtVersion::new(6, 2, 0)),
+            &["-lpthread"],
+        )
+        .unwrap();
+        assert_eq!(library, temp.path().join("libsharedtest.so.6.2.0"));
+        assert!(temp.path().join("libsharedtest.so").exists());
+
+        let output = std::process::Command::new("nm")
+            .arg("-D")
+            .arg("--defined-only")
+            .arg(&library)
+            .output()
+            .unwrap();
+        let symbols = String::from_utf8_lossy(&output.stdout);
+        assert!(symbols.contains("qt_cargo_shared_test"), "{}", symbols);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_compile_namespaced_library() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-library-test").unwrap();
+        let mut config = crate::configure::QtConfiguration::new();
+        crate::configure::set_default_configuration(&mut config);
+        config.set_namespace("vendored");
+        config.set_library_prefix("vendored_");
+        assert_eq!(config.namespace().as_deref(), Some("vendored"));
+        assert_eq!(config.library_name("Qt6Core"), "vendored_Qt6Core");
+
+        let config_path = temp.path().join("qt_config_headers");
+        let no_path: Option<PathBuf> = None;
+        crate::configure::write_configuration(&config, &config_path, no_path).unwrap();
+        let source = temp.path().join("namespaced.cpp");
+        std::fs::write(
+            &source,
+            "#include <QtCore/qconfig.h>\n\
+             namespace QT_NAMESPACE { int qt_cargo_namespace_test() { return 42; } }\n",
+        )
+        .unwrap();
+        let (mut builder, _build_temp) = util::test_builder();
+        builder
+            .cargo_metadata(false)
+            .include(&config_path)
+            .file(&source);
+        let library = compile_library(
+            &mut builder,
+            &config.library_name("Qt6Core"),
+            temp.path(),
+            LibraryKind::Static,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(library, temp.path().join("libvendored_Qt6Core.a"));
+
+        let output = std::process::Command::new("nm")
+            .arg("--defined-only")
+            .arg("--demangle")
+            .arg(&library)
+            .output()
+            .unwrap();
+        let symbols = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            symbols.contains("vendored::qt_cargo_namespace_test()"),
+            "{}",
+            symbols
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_merge_archives() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-library-test").unwrap();
+        let compile = |name: &str| {
+            let source = temp.path().join(format!("{}.cpp", name));
+            std::fs::write(&source, format!("int {}() {{ return 42; }}\n", name)).unwrap();
+            let (mut builder, _build_temp) = util::test_builder();
+            builder.cargo_metadata(false).file(&source);
+            compile_library(&mut builder, name, temp.path(), LibraryKind::Static, &[]).unwrap()
+        };
+        let core = compile("merge_core");
+        let pcre2 = compile("merge_pcre2");
+
+        // A thin archive, referencing the objects of the core archive
+        let thin_dir = temp.path().join("thin");
+        std::fs::create_dir_all(&thin_dir).unwrap();
+        run_ar(&thin_dir, &["x".as_ref(), core.as_os_str()]).unwrap();
+        let members = run_ar(&thin_dir, &["t".as_ref(), core.as_os_str()]).unwrap();
+        let mut args = vec!["rcT", "libthin.a"];
+        args.extend(members.lines());
+        let args: Vec<&std::ffi::OsStr> = args.iter().map(|arg| arg.as_ref()).collect();
+        run_ar(&thin_dir, &args).unwrap();
+
+        let output = temp.path().join("libmerged.a");
+        merge_archives(&output, &[core, pcre2, thin_dir.join("libthin.a")]).unwrap();
+        let merged_members = run_ar(temp.path(), &["t".as_ref(), output.as_os_str()]).unwrap();
+        assert_eq!(
+            merged_members
+                .lines()
+                .filter(|member| member.contains("merge_core"))
+                .count(),
+            2
+        );
+        assert!(merged_members.contains("merge_pcre2"));
+
+        let symbols = Command::new("nm").arg(&output).output().unwrap();
+        let symbols = String::from_utf8_lossy(&symbols.stdout);
+        assert!(symbols.contains("merge_pcre2"), "{}", symbols);
+
+        assert!(merge_archives(&output, &[temp.path().join("missing.a")]).is_err());
+    }
+
+    #[test]
+    fn test_compile_with_argument_budget() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-library-test").unwrap();
+        let source = temp.path().join("budget.cpp");
+        std::fs::write(
+            &source,
+            "#include \"budget.h\"\nint qt_cargo_budget_test() { return BUDGET_VALUE; }\n",
+        )
+        .unwrap();
+
+        // Many long include paths, with the header in the last one
+        let (mut builder, _build_temp) = util::test_builder();
+        builder.define("BUDGET_VALUE", "42").file(&source);
+        let mut include_path = PathBuf::new();
+        for index in 0..200 {
+            include_path = temp.path().join(format!(
+                "include/{}/{:03}",
+                "long_directory_name".repeat(4),
+                index
+            ));
+            builder.include(&include_path);
+        }
+        std::fs::create_dir_all(&include_path).unwrap();
+        std::fs::write(include_path.join("budget.h"), "#pragma once\n").unwrap();
+
+        let out_dir = temp.path().join("out");
+        let library = compile_with_argument_budget(&builder, "budget", &out_dir, 8 * 1024).unwrap();
+        assert_eq!(library, out_dir.join("libbudget.a"));
+        assert!(library.exists());
+        let response_file = std::fs::read_to_string(out_dir.join("budget.rsp")).unwrap();
+        assert!(response_file.contains("-DBUDGET_VALUE=42"));
+        assert_eq!(response_file.lines().count(), 201);
+
+        // Within the budget the builder is used as is
+        let out_dir = temp.path().join("out_unlimited");
+        compile_with_argument_budget(&builder, "budget", &out_dir, DEFAULT_ARGUMENT_BUDGET)
+            .unwrap();
+        assert!(out_dir.join("libbudget.a").exists());
+        assert!(!out_dir.join("budget.rsp").exists());
+
+        // The flags alone exceed a tiny budget
+        let error = compile_with_argument_budget(&builder, "budget", &out_dir, 64).unwrap_err();
+        assert!(matches!(error, QtBuildError::InvalidArgument { .. }));
+    }
+}
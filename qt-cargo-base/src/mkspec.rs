@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::configure::QtConfiguration;
+
+// qmake mkspecs
+//
+// Each mkspec (qtbase/mkspecs/<name>) has a qplatformdefs.h, which the
+// configuration forwards to, and a qmake.conf which sets the compiler and
+// platform variables and includes shared .conf files from
+// mkspecs/common. Some platforms add defines there which QtCore expects
+// to be set for all code (e.g. UNICODE for win32-g++, or _REENTRANT).
+//
+// Mkspec reads the variables by following the include() statements from
+// qmake.conf. The parser covers the assignments used by the mkspecs
+// ("=", "+=", "*=", "-=", continuation lines and $$VAR expansion);
+// scoped assignments ("unix:VAR = ...") and conditional blocks are
+// skipped, as are load() and other function calls.
+
+/// Variables read from a mkspec, see Mkspec::load
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Mkspec {
+    pub name: String,
+    /// The values of each variable, e.g. "QMAKE_PLATFORM" -> ["linux", "unix", "posix"]
+    pub variables: BTreeMap<String, Vec<String>>,
+}
+
+impl Mkspec {
+    /// Reads qtbase/mkspecs/<name>/qmake.conf in the Qt source, and the files it includes
+    pub fn load<P: AsRef<Path>>(qt_source_path: P, name: &str) -> io::Result<Mkspec> {
+        let mut mkspec = Mkspec {
+            name: name.to_string(),
+            variables: BTreeMap::new(),
+        };
+        let qmake_conf = qt_source_path
+            .as_ref()
+            .join("qtbase/mkspecs")
+            .join(name)
+            .join("qmake.conf");
+        mkspec.read_file(&qmake_conf, 0)?;
+        Ok(mkspec)
+    }
+
+    /// Returns the values of the variable, or an empty slice if not set
+    pub fn values(&self, name: &str) -> &[String] {
+        self.variables
+            .get(name)
+            .map(|values| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the defines the mkspec adds to all compiles ("DEFINES"), as (key, value)
+    /// pairs. Defines without a value get the value "1", as with -D.
+    pub fn defines(&self) -> Vec<(String, String)> {
+        self.values("DEFINES")
+            .iter()
+            .map(|define| match define.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (define.to_string(), "1".to_string()),
+            })
+            .collect()
+    }
+
+    /// Returns the predefined macros of the mkspec compiler ("QMAKE_COMPILER_DEFINES"),
+    /// which identify the compiler and platform to tools like moc. These are defined by
+    /// the compiler itself, and are not written to the config headers.
+    pub fn compiler_defines(&self) -> &[String] {
+        self.values("QMAKE_COMPILER_DEFINES")
+    }
+
+    /// Sets the mkspec of the configuration (for qplatformdefs.h), and adds the mkspec
+    /// defines to qconfig.h.
+    pub fn apply(&self, qt_configuration: &mut QtConfiguration) {
+        qt_configuration.set_mkspec(&self.name);
+        for (key, value) in self.defines() {
+            qt_configuration.set_global_define(&key, &value);
+        }
+    }
+
+    fn read_file(&mut self, path: &Path, depth: usize) -> io::Result<()> {
+        if depth > 16 {
+            return Err(io::Error::other(format!(
+                "include depth exceeded at {:?}",
+                path
+            )));
+        }
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for line in logical_lines(&content) {
+            if let Some(include) = line
+                .strip_prefix("include(")
+                .and_then(|rest| rest.strip_suffix(')'))
+            {
+                let include_path: PathBuf = directory.join(self.expand(include.trim()));
+                self.read_file(&include_path, depth + 1)?;
+                continue;
+            }
+            self.assign(&line);
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, line: &str) {
+        let Some(operator_index) = line.find('=') else {
+            return;
+        };
+        let (name, operator) = match line[..operator_index].trim_end() {
+            name if name.ends_with(['+', '*', '-']) => (
+                name[..name.len() - 1].trim_end(),
+                &line[operator_index - 1..operator_index + 1],
+            ),
+            name => (name, "="),
+        };
+        // Scoped and conditional assignments are not evaluated
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return;
+        }
+        let values: Vec<String> = self
+            .expand(&line[operator_index + 1..])
+            .split_whitespace()
+            .map(|value| value.to_string())
+            .collect();
+        let variable = self.variables.entry(name.to_string()).or_default();
+        match operator {
+            "+=" => variable.extend(values),
+            "*=" => {
+                for value in values {
+                    if !variable.contains(&value) {
+                        variable.push(value);
+                    }
+                }
+            }
+            "-=" => variable.retain(|value| !values.contains(value)),
+            _ => *variable = values,
+        }
+    }
+
+    // Expands $$VAR and $${VAR}
+    fn expand(&self, text: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = text;
+        while let Some(index) = rest.find("$$") {
+            expanded.push_str(&rest[..index]);
+            rest = &rest[index + 2..];
+            let (name, remainder) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => (braced, ""),
+                },
+                None => {
+                    let end = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            expanded.push_str(&self.values(name).join(" "));
+            rest = remainder;
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+// Returns the lines of a .conf file with comments removed and continuation lines joined,
+// skipping empty lines and conditional blocks.
+fn logical_lines(content: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut block_depth = 0usize;
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim_end();
+        let (line, continues) = match line.strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        current.push_str(line);
+        current.push(' ');
+        if continues {
+            continue;
+        }
+        let logical_line = std::mem::take(&mut current).trim().to_string();
+        // Braces of block scopes, not of $${VAR} expansions
+        let mut structure = logical_line.clone();
+        while let Some(start) = structure.find("$${") {
+            let end = structure[start..]
+                .find('}')
+                .map(|end| start + end + 1)
+                .unwrap_or(structure.len());
+            structure.replace_range(start..end, "");
+        }
+        let opens = structure.matches('{').count();
+        let closes = structure.matches('}').count();
+        let in_block = block_depth > 0 || opens > 0;
+        block_depth = (block_depth + opens).saturating_sub(closes);
+        if !logical_line.is_empty() && !in_block {
+            lines.push(logical_line);
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod qt_cargo_base_mkspec_tests {
+    use super::*;
+    use crate::configure;
+    use crate::util;
+
+    #[test]
+    fn test_mkspec_defines() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-mkspec-test").unwrap();
+        let mkspecs = temp.path().join("qt/qtbase/mkspecs");
+        std::fs::create_dir_all(mkspecs.join("common")).unwrap();
+        std::fs::create_dir_all(mkspecs.join("linux-test")).unwrap();
+        std::fs::write(
+            mkspecs.join("common/unix.conf"),
+            "QMAKE_PLATFORM         += unix posix\n\
+             QMAKE_COMPILER_DEFINES += __unix__\n\
+             DEFINES                += _REENTRANT\n",
+        )
+        .unwrap();
+        std::fs::write(
+            mkspecs.join("linux-test/qmake.conf"),
+            "# Test mkspec\n\
+             MAKEFILE_GENERATOR = UNIX\n\
+             QMAKE_PLATFORM = linux\n\
+             include(../common/unix.conf)\n\
+             QMAKE_COMPILER = gcc\n\
+             DEFINES += QT_TEST_PLATFORM=2 \\\n    QT_TEST_$${QMAKE_COMPILER}\n\
+             DEFINES *= _REENTRANT\n\
+             android: DEFINES += ANDROID\n\
+             contains(QMAKE_HOST.arch, x86_64) {\n    DEFINES += QT_TEST_X86_64\n}\n\
+             load(qt_config)\n",
+        )
+        .unwrap();
+
+        let mkspec = Mkspec::load(temp.path().join("qt"), "linux-test").unwrap();
+        assert_eq!(mkspec.values("QMAKE_PLATFORM"), ["linux", "unix", "posix"]);
+        assert_eq!(mkspec.compiler_defines(), ["__unix__"]);
+        assert_eq!(
+            mkspec.defines(),
+            [
+                ("_REENTRANT".to_string(), "1".to_string()),
+                ("QT_TEST_PLATFORM".to_string(), "2".to_string()),
+                ("QT_TEST_gcc".to_string(), "1".to_string()),
+            ]
+        );
+
+        let mut config = QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        mkspec.apply(&mut config);
+        assert_eq!(config.mkspec().as_deref(), Some("linux-test"));
+        let qconfig = &configure::generate_config_headers(&config)[Path::new("QtCore/qconfig.h")];
+        assert!(qconfig.contains("#define _REENTRANT 1\n"));
+        assert!(qconfig.contains("#define QT_TEST_PLATFORM 2\n"));
+        assert!(!qconfig.contains("__unix__"));
+
+        assert!(Mkspec::load(temp.path().join("qt"), "no-such-mkspec").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+// Qt modules and link order
+//
+// Static archives must appear on the linker command line before the
+// libraries they depend on. QtModule records the module dependencies and
+// the system libraries each module needs, and link_order computes an
+// order which satisfies this for a set of modules.
+
+/// A Qt library which can be built by this crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QtModule {
+    /// QtCore
+    Core,
+    /// The host tools library built by qtcore-host-tools: moc
+    HostTools,
+    /// The bootstrap library (a reduced QtCore) which the host tools link against, see
+    /// build_bootstrap_archive
+    Bootstrap,
+}
+
+impl QtModule {
+    /// The archive name, as used with "cargo:rustc-link-lib"
+    pub fn library_name(&self) -> &'static str {
+        match self {
+            QtModule::Core => "Qt6Core",
+            QtModule::HostTools => "qtcore_host_tools",
+            QtModule::Bootstrap => "qt_bootstrap",
+        }
+    }
+
+    /// The Qt modules this module depends on
+    pub fn dependencies(&self) -> &'static [QtModule] {
+        match self {
+            QtModule::Core | QtModule::Bootstrap => &[],
+            QtModule::HostTools => &[QtModule::Bootstrap],
+        }
+    }
+
+    /// The system libraries this module depends on with the default configuration,
+    /// in link order. See also QtConfiguration::link_libraries for the libraries
+    /// which depend on the configured features.
+    pub fn system_libraries(&self) -> &'static [&'static str] {
+        match self {
+            QtModule::Core => &["pcre2-16", "z", "pthread"],
+            QtModule::HostTools => &[],
+            QtModule::Bootstrap => &["pcre2-16"],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LinkNode {
+    Module(QtModule),
+    SystemLibrary(&'static str),
+}
+
+/// Returns the libraries to link for the given modules, in link order: each module archive
+/// comes before the modules and system libraries it depends on. The names can be used with
+/// "cargo:rustc-link-lib" (module archives are static libraries).
+pub fn link_order(modules: &[QtModule]) -> Vec<String> {
+    fn visit(node: LinkNode, visited: &mut BTreeSet<LinkNode>, order: &mut Vec<LinkNode>) {
+        if !visited.insert(node) {
+            return;
+        }
+        if let LinkNode::Module(module) = node {
+            // Dependencies are visited in reverse, so that they keep their
+            // order after the final reversal.
+            let dependencies = module
+                .dependencies()
+                .iter()
+                .map(|dependency| LinkNode::Module(*dependency))
+                .chain(
+                    module
+                        .system_libraries()
+                        .iter()
+                        .map(|library| LinkNode::SystemLibrary(library)),
+                )
+                .collect::<Vec<_>>();
+            for dependency in dependencies.into_iter().rev() {
+                visit(dependency, visited, order);
+            }
+        }
+        order.push(node);
+    }
+
+    // Post-order traversal places dependencies before their users; reversing
+    // it gives the link order.
+    let mut visited = BTreeSet::new();
+    let mut order = Vec::new();
+    for module in modules.iter().rev() {
+        visit(LinkNode::Module(*module), &mut visited, &mut order);
+    }
+    order
+        .into_iter()
+        .rev()
+        .map(|node| match node {
+            LinkNode::Module(module) => module.library_name().to_string(),
+            LinkNode::SystemLibrary(library) => library.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod qt_cargo_base_modules_tests {
+    use super::*;
+
+    #[test]
+    fn test_link_order() {
+        assert_eq!(
+            link_order(&[QtModule::Core]),
+            ["Qt6Core", "pcre2-16", "z", "pthread"]
+        );
+        assert_eq!(
+            link_order(&[QtModule::HostTools, QtModule::Core]),
+            [
+                "qtcore_host_tools",
+                "qt_bootstrap",
+                "Qt6Core",
+                "pcre2-16",
+                "z",
+                "pthread"
+            ]
+        );
+        assert_eq!(
+            link_order(&[QtModule::HostTools]),
+            ["qtcore_host_tools", "qt_bootstrap", "pcre2-16"]
+        );
+    }
+}
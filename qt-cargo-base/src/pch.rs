@@ -0,0 +1,159 @@
+use std::path::{Path, PathBuf};
+
+// Precompiled headers
+//
+// The Qt headers are large, and are parsed again for each source file.
+// add_precompiled_header writes a header with the given includes,
+// precompiles it with the builder's compiler and flags, and configures
+// the builder to use it:
+//
+//  - GCC: "<name>.h.gch" next to the header, used with -include <name>.h.
+//  - Clang: "<name>.h.pch" next to the header, used with -include-pch.
+//  - MSVC: "<name>.pch", created with /Yc from a stub source file and used
+//    with /Yu and /FI. The stub object must be linked as well, and is
+//    added to the builder as an object file.
+//
+// The precompiled header is only valid for the flags it was created
+// with, so it must be created after the builder is fully configured.
+
+/// Heavy QtCore headers which most QtCore sources include, for use with
+/// add_precompiled_header. The headers are resolved using the forwarding headers.
+pub const QTCORE_PCH_INCLUDES: &[&str] = &[
+    "QtCore/qglobal.h",
+    "QtCore/qbytearray.h",
+    "QtCore/qstring.h",
+    "QtCore/qlist.h",
+    "QtCore/qhash.h",
+    "QtCore/qmap.h",
+    "QtCore/qmetatype.h",
+    "QtCore/qobject.h",
+    "QtCore/qvariant.h",
+];
+
+/// Writes "<name>.h" with the given includes to out_dir, precompiles it with the
+/// builder's compiler and flags, and configures the builder to use the precompiled header
+/// for all sources. Returns the path of the precompiled header.
+pub fn add_precompiled_header(
+    builder: &mut cc::Build,
+    includes: &[&str],
+    out_dir: &Path,
+    name: &str,
+) -> PathBuf {
+    std::fs::create_dir_all(out_dir).expect("Unable to create directory");
+    let header_path = out_dir.join(format!("{}.h", name));
+    let content: String = includes
+        .iter()
+        .map(|include| format!("#include <{}>\n", include))
+        .collect();
+    std::fs::write(&header_path, content).expect("Unable to write file");
+
+    let compiler = builder.get_compiler();
+    let mut command = compiler.to_command();
+    let pch_path = if compiler.is_like_msvc() {
+        let pch_path = out_dir.join(format!("{}.pch", name));
+        let stub_path = out_dir.join(format!("{}_pch.cpp", name));
+        let object_path = out_dir.join(format!("{}_pch.obj", name));
+        std::fs::write(
+            &stub_path,
+            format!("#include \"{}\"\n", header_path.display()),
+        )
+        .expect("Unable to write file");
+        command
+            .arg("/c")
+            .arg(format!("/Yc{}", header_path.display()))
+            .arg(format!("/Fp{}", pch_path.display()))
+            .arg(format!("/Fo{}", object_path.display()))
+            .arg(&stub_path);
+        builder
+            .flag(format!("/Yu{}", header_path.display()))
+            .flag(format!("/FI{}", header_path.display()))
+            .flag(format!("/Fp{}", pch_path.display()))
+            .object(object_path);
+        pch_path
+    } else {
+        let extension = if compiler.is_like_clang() {
+            "pch"
+        } else {
+            "gch"
+        };
+        let pch_path = out_dir.join(format!("{}.h.{}", name, extension));
+        command
+            .arg("-x")
+            .arg("c++-header")
+            .arg(&header_path)
+            .arg("-o")
+            .arg(&pch_path);
+        if compiler.is_like_clang() {
+            builder
+                .flag("-include-pch")
+                .flag(pch_path.to_str().unwrap());
+        } else {
+            // GCC uses <name>.h.gch in place of the header, and warns if it can't
+            builder
+                .flag("-include")
+                .flag(header_path.to_str().unwrap())
+                .flag("-Winvalid-pch");
+        }
+        pch_path
+    };
+
+    let output = command
+        .output()
+        .unwrap_or_else(|e| panic!("Unable to run compiler {:?}: {}", compiler.path(), e));
+    if !output.status.success() {
+        panic!(
+            "Failed to create precompiled header {:?}: {}",
+            pch_path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    pch_path
+}
+
+#[cfg(test)]
+mod qt_cargo_base_pch_tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_add_precompiled_header() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-pch-test").unwrap();
+        let (mut builder, _build_temp) = util::test_builder();
+        let pch_path = add_precompiled_header(
+            &mut builder,
+            &["map", "string", "vector"],
+            &temp.path().join("pch"),
+            "stl_pch",
+        );
+        assert!(pch_path.exists());
+
+        // The source compiles without including the headers, and the compiler reports
+        // the precompiled header as used ("! <path>" with -H)
+        let source = temp.path().join("pch_test.cpp");
+        std::fs::write(
+            &source,
+            "std::map<std::string, std::vector<int>> pch_test_map;\n",
+        )
+        .unwrap();
+        let output = builder
+            .get_compiler()
+            .to_command()
+            .arg("-H")
+            .arg("-c")
+            .arg(&source)
+            .arg("-o")
+            .arg(temp.path().join("pch_test.o"))
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+        if builder.get_compiler().is_like_gnu() && !builder.get_compiler().is_like_clang() {
+            assert!(
+                stderr.contains(&format!("! {}", pch_path.display())),
+                "{}",
+                stderr
+            );
+        }
+    }
+}
@@ -0,0 +1,651 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::configure::QtConfiguration;
+use crate::flags::BuildFlags;
+
+// Build plan
+//
+// A BuildPlan collects everything which was decided for a build: Qt
+// version, target, mkspec, effective features and defines, include
+// paths, source files and external libraries. It is written as a JSON
+// document, which tooling can read and CI can diff across commits to
+// catch configuration drift.
+//
+// The plan can also be exported as a compile_commands.json compilation
+// database for clang tooling, with one entry per source file. An entry
+// can be parsed back into a plan (include paths, defines, C++ standard
+// and source file), which lets tools work with the configuration of an
+// existing database.
+//
+// The JSON support is minimal and covers what the plan needs (objects,
+// arrays, strings and booleans), to avoid a serde dependency. It is also
+// used for the SBOM, see sbom.rs.
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BuildPlan {
+    pub qt_version: String,
+    pub target: String,
+    pub mkspec: String,
+    pub features: BTreeMap<String, bool>,
+    pub defines: BTreeMap<String, String>,
+    /// The C++ standard, as for the -std flag (e.g. "c++17")
+    pub cxx_standard: String,
+    /// Include paths, in search order
+    pub include_paths: Vec<PathBuf>,
+    pub sources: Vec<PathBuf>,
+    /// External libraries, as names for "cargo:rustc-link-lib"
+    pub link_libraries: Vec<String>,
+}
+
+impl BuildPlan {
+    /// Creates a build plan for the given configuration, include paths (see
+    /// QtBuildPaths) and source files.
+    pub fn new(
+        qt_configuration: &QtConfiguration,
+        include_paths: &[PathBuf],
+        sources: &[PathBuf],
+    ) -> BuildPlan {
+        let defines = qt_configuration.defines();
+        BuildPlan {
+            qt_version: defines
+                .get("QT_VERSION_STR")
+                .map(|version| version.trim_matches('"').to_string())
+                .unwrap_or_default(),
+            // Standalone builds (no TARGET) use the configure_for_qt_build default target
+            target: crate::target::BuildTriples::from_env().target,
+            mkspec: qt_configuration.mkspec().unwrap_or_default(),
+            features: qt_configuration.features(),
+            defines,
+            // See configure_for_qt_build
+            cxx_standard: "c++17".to_string(),
+            include_paths: include_paths.to_vec(),
+            sources: sources.to_vec(),
+            link_libraries: qt_configuration
+                .link_libraries()
+                .iter()
+                .map(|library| library.to_string())
+                .collect(),
+        }
+    }
+
+    /// Returns the plan as a JSON document
+    pub fn to_json(&self) -> String {
+        let strings = |values: &mut dyn Iterator<Item = String>| {
+            Json::Array(values.map(Json::String).collect())
+        };
+        let paths = |paths: &[PathBuf]| {
+            strings(&mut paths.iter().map(|path| path.to_string_lossy().into_owned()))
+        };
+        let plan = Json::Object(vec![
+            ("qt_version".into(), Json::String(self.qt_version.clone())),
+            ("target".into(), Json::String(self.target.clone())),
+            ("mkspec".into(), Json::String(self.mkspec.clone())),
+            (
+                "features".into(),
+                Json::Object(
+                    self.features
+                        .iter()
+                        .map(|(name, enabled)| (name.clone(), Json::Bool(*enabled)))
+                        .collect(),
+                ),
+            ),
+            (
+                "defines".into(),
+                Json::Object(
+                    self.defines
+                        .iter()
+                        .map(|(key, value)| (key.clone(), Json::String(value.clone())))
+                        .collect(),
+                ),
+            ),
+            (
+                "cxx_standard".into(),
+                Json::String(self.cxx_standard.clone()),
+            ),
+            ("include_paths".into(), paths(&self.include_paths)),
+            ("sources".into(), paths(&self.sources)),
+            (
+                "link_libraries".into(),
+                strings(&mut self.link_libraries.iter().cloned()),
+            ),
+        ]);
+        let mut json = String::new();
+        plan.write(&mut json, 0);
+        json.push('\n');
+        json
+    }
+
+    /// Parses a plan written by to_json
+    pub fn from_json(json: &str) -> Result<BuildPlan, String> {
+        let mut parser = JsonParser {
+            chars: json.chars().collect(),
+            position: 0,
+        };
+        let plan = parser.parse_document()?;
+        let field = |name: &str| match &plan {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| format!("missing field {}", name)),
+            _ => Err("expected an object".to_string()),
+        };
+        let string = |value: &Json| match value {
+            Json::String(string) => Ok(string.clone()),
+            _ => Err("expected a string".to_string()),
+        };
+        let strings = |name: &str| match field(name)? {
+            Json::Array(values) => values.iter().map(string).collect::<Result<Vec<_>, _>>(),
+            _ => Err(format!("expected an array for {}", name)),
+        };
+        let object = |name: &str| match field(name)? {
+            Json::Object(fields) => Ok(fields.clone()),
+            _ => Err(format!("expected an object for {}", name)),
+        };
+
+        Ok(BuildPlan {
+            qt_version: string(field("qt_version")?)?,
+            target: string(field("target")?)?,
+            mkspec: string(field("mkspec")?)?,
+            features: object("features")?
+                .into_iter()
+                .map(|(name, value)| match value {
+                    Json::Bool(enabled) => Ok((name, enabled)),
+                    _ => Err(format!("expected a boolean for feature {}", name)),
+                })
+                .collect::<Result<_, _>>()?,
+            defines: object("defines")?
+                .into_iter()
+                .map(|(key, value)| Ok((key, string(&value)?)))
+                .collect::<Result<_, String>>()?,
+            cxx_standard: string(field("cxx_standard")?)?,
+            include_paths: strings("include_paths")?
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            sources: strings("sources")?.into_iter().map(PathBuf::from).collect(),
+            link_libraries: strings("link_libraries")?,
+        })
+    }
+
+    /// Returns the compiler arguments for compiling the given source file, starting with
+    /// the compiler ("c++"). Defines with an empty value are written as "-D<key>".
+    pub fn compile_arguments(&self, source: &Path) -> Vec<String> {
+        let mut arguments = vec!["c++".to_string()];
+        if !self.cxx_standard.is_empty() {
+            arguments.push(format!("-std={}", self.cxx_standard));
+        }
+        for include_path in &self.include_paths {
+            arguments.push(format!("-I{}", include_path.display()));
+        }
+        for (key, value) in &self.defines {
+            match value.is_empty() {
+                true => arguments.push(format!("-D{}", key)),
+                false => arguments.push(format!("-D{}={}", key, value)),
+            }
+        }
+        arguments.push("-c".to_string());
+        arguments.push(source.to_string_lossy().into_owned());
+        arguments
+    }
+
+    /// Returns the plan as a compile_commands.json document, with one entry per source
+    /// file. Relative source paths are relative to directory.
+    pub fn to_compile_commands(&self, directory: &Path) -> String {
+        let entries = self
+            .sources
+            .iter()
+            .map(|source| {
+                Json::Object(vec![
+                    (
+                        "directory".into(),
+                        Json::String(directory.to_string_lossy().into_owned()),
+                    ),
+                    (
+                        "file".into(),
+                        Json::String(source.to_string_lossy().into_owned()),
+                    ),
+                    (
+                        "arguments".into(),
+                        Json::Array(
+                            self.compile_arguments(source)
+                                .into_iter()
+                                .map(Json::String)
+                                .collect(),
+                        ),
+                    ),
+                ])
+            })
+            .collect();
+        let mut json = String::new();
+        Json::Array(entries).write(&mut json, 0);
+        json.push('\n');
+        json
+    }
+
+    /// Parses a single compile_commands.json entry (a JSON object with "file" and either
+    /// "arguments" or "command") into a plan with the include paths, defines, C++ standard
+    /// and source file of the entry. Other fields are left empty, and other compiler flags
+    /// are ignored.
+    pub fn from_compile_command(entry: &str) -> Result<BuildPlan, String> {
+        let mut parser = JsonParser {
+            chars: entry.chars().collect(),
+            position: 0,
+        };
+        let fields = match parser.parse_document()? {
+            Json::Object(fields) => fields,
+            _ => return Err("expected an object".to_string()),
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+        };
+        let arguments = match (field("arguments"), field("command")) {
+            (Some(Json::Array(values)), _) => values
+                .iter()
+                .map(|value| match value {
+                    Json::String(argument) => Ok(argument.clone()),
+                    _ => Err("expected a string argument".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            (None, Some(Json::String(command))) => split_command(command)?,
+            _ => return Err("missing arguments or command".to_string()),
+        };
+        let file = match field("file") {
+            Some(Json::String(file)) => PathBuf::from(file),
+            _ => return Err("missing field file".to_string()),
+        };
+
+        // The first argument is the compiler
+        let build_flags = BuildFlags::from_args(arguments.into_iter().skip(1).map(Into::into));
+        Ok(BuildPlan {
+            defines: build_flags
+                .defines
+                .into_iter()
+                .map(|(key, value)| (key, value.unwrap_or_default()))
+                .collect(),
+            cxx_standard: build_flags
+                .flags
+                .iter()
+                .rev()
+                .find_map(|flag| flag.strip_prefix("-std="))
+                .unwrap_or_default()
+                .to_string(),
+            include_paths: build_flags.include_paths,
+            sources: vec![file],
+            ..Default::default()
+        })
+    }
+}
+
+/// Splits a compile_commands.json "command" into arguments, following the shell quoting
+/// rules the compilation database format uses (single and double quotes, backslash escapes)
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    let mut argument: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => arguments.extend(argument.take()),
+            '\'' => {
+                let argument = argument.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or("unterminated quote")? {
+                        '\'' => break,
+                        c => argument.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let argument = argument.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or("unterminated quote")? {
+                        '"' => break,
+                        '\\' => argument.push(chars.next().ok_or("unterminated quote")?),
+                        c => argument.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                argument.get_or_insert_with(String::new).push(escaped);
+            }
+            c => argument.get_or_insert_with(String::new).push(c),
+        }
+    }
+    arguments.extend(argument);
+    Ok(arguments)
+}
+
+/// Writes the build plan as JSON to path
+pub fn write_build_plan(path: &Path, plan: &BuildPlan) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).expect("Unable to create directory");
+    }
+    std::fs::write(path, plan.to_json()).expect("Unable to write file");
+}
+
+/// Writes the build plan as a compile_commands.json compilation database to path, with
+/// source paths relative to directory. See BuildPlan::to_compile_commands.
+pub fn write_compile_commands(path: &Path, plan: &BuildPlan, directory: &Path) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).expect("Unable to create directory");
+    }
+    std::fs::write(path, plan.to_compile_commands(directory)).expect("Unable to write file");
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a JSON document
+    pub(crate) fn parse(json: &str) -> Result<Json, String> {
+        let mut parser = JsonParser {
+            chars: json.chars().collect(),
+            position: 0,
+        };
+        parser.parse_document()
+    }
+
+    pub(crate) fn write(&self, out: &mut String, indent: usize) {
+        let write_items = |out: &mut String, items: Vec<(Option<&str>, &Json)>| {
+            if items.is_empty() {
+                return;
+            }
+            out.push('\n');
+            for (index, (key, value)) in items.iter().enumerate() {
+                out.push_str(&"  ".repeat(indent + 1));
+                if let Some(key) = key {
+                    write_json_string(out, key);
+                    out.push_str(": ");
+                }
+                value.write(out, indent + 1);
+                out.push_str(if index + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(indent));
+        };
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::String(value) => write_json_string(out, value),
+            Json::Array(values) => {
+                out.push('[');
+                write_items(out, values.iter().map(|value| (None, value)).collect());
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                write_items(
+                    out,
+                    fields
+                        .iter()
+                        .map(|(key, value)| (Some(key.as_str()), value))
+                        .collect(),
+                );
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl JsonParser {
+    fn parse_document(&mut self) -> Result<Json, String> {
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+        match self.position == self.chars.len() {
+            true => Ok(value),
+            false => Err(format!("unexpected content at {}", self.position)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            c => Err(format!(
+                "expected {:?} at {}, found {:?}",
+                expected, self.position, c
+            )),
+        }
+    }
+
+    fn parse_keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        let end = self.position + keyword.len();
+        if end <= self.chars.len()
+            && self.chars[self.position..end]
+                .iter()
+                .copied()
+                .eq(keyword.chars())
+        {
+            self.position = end;
+            Ok(value)
+        } else {
+            Err(format!("unexpected token at {}", self.position))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_object(),
+            Some('[') => self.parse_array(),
+            Some('"') => Ok(Json::String(self.parse_string()?)),
+            Some('t') => self.parse_keyword("true", Json::Bool(true)),
+            Some('f') => self.parse_keyword("false", Json::Bool(false)),
+            Some('n') => self.parse_keyword("null", Json::Null),
+            c => Err(format!("unsupported value at {}: {:?}", self.position, c)),
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                _ => break,
+            }
+        }
+        self.expect(']')?;
+        Ok(Json::Array(values))
+    }
+
+    fn parse_object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(':')?;
+            fields.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.position += 1,
+                _ => break,
+            }
+        }
+        self.expect('}')?;
+        Ok(Json::Object(fields))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let c = self.peek().ok_or("unterminated string")?;
+            self.position += 1;
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = self.peek().ok_or("unterminated string")?;
+                    self.position += 1;
+                    match escaped {
+                        '"' | '\\' | '/' => string.push(escaped),
+                        'n' => string.push('\n'),
+                        'r' => string.push('\r'),
+                        't' => string.push('\t'),
+                        'b' => string.push('\u{8}'),
+                        'f' => string.push('\u{c}'),
+                        'u' => {
+                            let end = self.position + 4;
+                            let hex: String = self
+                                .chars
+                                .get(self.position..end)
+                                .ok_or("truncated escape")?
+                                .iter()
+                                .collect();
+                            let code = u32::from_str_radix(&hex, 16)
+                                .map_err(|_| format!("invalid escape {}", hex))?;
+                            string.push(char::from_u32(code).ok_or("invalid escape")?);
+                            self.position = end;
+                        }
+                        c => return Err(format!("invalid escape \\{}", c)),
+                    }
+                }
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_plan_tests {
+    use super::*;
+    use crate::{configure, util};
+
+    #[test]
+    fn test_build_plan_round_trip() {
+        let mut config = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        config.set_feature("thread", false);
+        config.set_global_define("QT_CARGO_TEST", "\"quoted\\\\path\"\n");
+
+        let plan = BuildPlan::new(
+            &config,
+            &[PathBuf::from("/build/qt_config_headers")],
+            &[PathBuf::from("qtbase/src/corelib/global/qglobal.cpp")],
+        );
+        assert_eq!(plan.qt_version, "6.2.0");
+        assert_eq!(plan.mkspec, "linux-clang");
+        assert_eq!(plan.features.get("thread"), Some(&false));
+        assert_eq!(plan.link_libraries, ["rt", "dl"]);
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-plan-test").unwrap();
+        let plan_path = temp.path().join("plan/build_plan.json");
+        write_build_plan(&plan_path, &plan);
+        let json = std::fs::read_to_string(&plan_path).unwrap();
+        assert!(json.contains("\"mkspec\": \"linux-clang\""));
+        assert_eq!(BuildPlan::from_json(&json), Ok(plan));
+        assert!(BuildPlan::from_json("{\"qt_version\": 6}").is_err());
+    }
+
+    #[test]
+    fn test_compile_command_round_trip() {
+        let mut config = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        config.set_global_define("QT_CARGO_EMPTY", "");
+        let plan = BuildPlan::new(
+            &config,
+            &[
+                PathBuf::from("/build/qt_config_headers"),
+                PathBuf::from("/build/qt_forwarding_headers"),
+            ],
+            &[
+                PathBuf::from("qtbase/src/corelib/global/qglobal.cpp"),
+                PathBuf::from("qtbase/src/corelib/text/qstring.cpp"),
+            ],
+        );
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-plan-test").unwrap();
+        let database_path = temp.path().join("compile_commands.json");
+        write_compile_commands(&database_path, &plan, Path::new("/qt"));
+        let database = std::fs::read_to_string(&database_path).unwrap();
+        let mut parser = JsonParser {
+            chars: database.chars().collect(),
+            position: 0,
+        };
+        let entries = match parser.parse_document().unwrap() {
+            Json::Array(entries) => entries,
+            _ => panic!("expected an array"),
+        };
+        assert_eq!(entries.len(), 2);
+
+        let mut entry = String::new();
+        entries[1].write(&mut entry, 0);
+        let parsed = BuildPlan::from_compile_command(&entry).unwrap();
+        assert_eq!(parsed.include_paths, plan.include_paths);
+        assert_eq!(parsed.defines, plan.defines);
+        assert_eq!(parsed.defines.get("QT_CARGO_EMPTY"), Some(&String::new()));
+        assert_eq!(parsed.cxx_standard, "c++17");
+        assert_eq!(parsed.sources, [plan.sources[1].clone()]);
+
+        // "command" entries are split using shell quoting
+        let parsed = BuildPlan::from_compile_command(
+            r#"{"directory": "/qt", "file": "a.cpp",
+                "command": "c++ -std=c++20 -I '/my include' -DQT_VERSION_STR=\\\"6.2.0\\\" -O2 -c a.cpp"}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.include_paths, [PathBuf::from("/my include")]);
+        assert_eq!(parsed.defines.get("QT_VERSION_STR").unwrap(), "\"6.2.0\"");
+        assert_eq!(parsed.cxx_standard, "c++20");
+        assert!(BuildPlan::from_compile_command("{\"file\": \"a.cpp\"}").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use crate::configure::{self, QtLicense};
+use crate::plan::Json;
+
+// Software bill of materials
+//
+// write_sbom lists the Qt source files compiled into an archive and the
+// 3rdparty components bundled with qtbase (qtbase/src/3rdparty), with
+// their licenses, as an SPDX 2.3 style JSON document. Component licenses
+// are read from the "LicenseId" entries of each component's
+// qt_attribution.json, the Qt source license from the license edition
+// (see configure::detect_license). Licenses which can't be determined are
+// given as "NOASSERTION".
+//
+// The 3rdparty components are listed whether or not any of their files
+// were compiled, since several of them (e.g. tinycbor) are included as
+// headers from the Qt sources.
+
+/// The 3rdparty source directory in the Qt source, relative to the Qt source path
+pub const THIRD_PARTY_PATH: &str = "qtbase/src/3rdparty";
+
+const NO_ASSERTION: &str = "NOASSERTION";
+
+/// Returns the SPDX license expression for the Qt source files
+pub fn qt_license_expression(license: QtLicense) -> &'static str {
+    match license {
+        QtLicense::OpenSource => "LGPL-3.0-only OR GPL-2.0-only OR GPL-3.0-only",
+        QtLicense::Commercial => "LicenseRef-Qt-Commercial",
+    }
+}
+
+/// Returns the bundled 3rdparty components in the Qt source as (name, SPDX license
+/// expression) pairs, sorted by name.
+pub fn third_party_components(qt_source: &Path) -> Vec<(String, String)> {
+    let third_party_path = qt_source.join(THIRD_PARTY_PATH);
+    let mut components: Vec<(String, String)> = std::fs::read_dir(third_party_path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| {
+                    let license = attribution_license(&entry.path());
+                    (entry.file_name().to_string_lossy().into_owned(), license)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    components.sort();
+    components
+}
+
+// Returns the license of the 3rdparty component at path, from its qt_attribution.json.
+// The file contains one entry or an array of entries (for components with several
+// parts); the licenses of all entries are combined.
+fn attribution_license(path: &Path) -> String {
+    let attribution = std::fs::read_to_string(path.join("qt_attribution.json"))
+        .ok()
+        .and_then(|content| Json::parse(&content).ok());
+    let entries = match attribution {
+        Some(Json::Array(entries)) => entries,
+        Some(entry) => vec![entry],
+        None => return NO_ASSERTION.to_string(),
+    };
+    let mut licenses: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Json::Object(fields) => fields.iter().find_map(|(key, value)| match value {
+                Json::String(license) if key == "LicenseId" => Some(license.clone()),
+                _ => None,
+            }),
+            _ => None,
+        })
+        .collect();
+    licenses.sort();
+    licenses.dedup();
+    match licenses.len() {
+        0 => NO_ASSERTION.to_string(),
+        1 => licenses.remove(0),
+        _ => licenses
+            .iter()
+            .map(|license| format!("({})", license))
+            .collect::<Vec<_>>()
+            .join(" AND "),
+    }
+}
+
+// SPDX identifiers may contain letters, digits, '.' and '-' only
+fn spdx_id(kind: &str, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{}-{}", kind, name)
+}
+
+/// Writes an SPDX style JSON bill of materials for the compiled sources to out: a package
+/// for qtbase and each bundled 3rdparty component, and a file entry for each source with
+/// the license of the package it belongs to. Sources are listed relative to qt_source
+/// where possible.
+pub fn write_sbom(out: &Path, sources: &[PathBuf], qt_source: &Path) {
+    let string = |value: &str| Json::String(value.to_string());
+    let qt_license = qt_license_expression(configure::detect_license(qt_source));
+    let components = third_party_components(qt_source);
+
+    let package = |name: &str, license: &str| {
+        Json::Object(vec![
+            ("SPDXID".to_string(), string(&spdx_id("Package", name))),
+            ("name".to_string(), string(name)),
+            ("downloadLocation".to_string(), string(NO_ASSERTION)),
+            ("licenseConcluded".to_string(), string(license)),
+            ("licenseDeclared".to_string(), string(license)),
+        ])
+    };
+    let mut packages = vec![package("qtbase", qt_license)];
+    for (name, license) in &components {
+        packages.push(package(name, license));
+    }
+
+    let mut relative_sources: Vec<PathBuf> = sources
+        .iter()
+        .map(|source| {
+            source
+                .strip_prefix(qt_source)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| source.clone())
+        })
+        .collect();
+    relative_sources.sort();
+    relative_sources.dedup();
+    let files = relative_sources
+        .iter()
+        .map(|source| {
+            let file_name = source.to_string_lossy().replace('\\', "/");
+            let license = components
+                .iter()
+                .find(|(name, _)| source.starts_with(Path::new(THIRD_PARTY_PATH).join(name)))
+                .map(|(_, license)| license.as_str())
+                .unwrap_or(qt_license);
+            Json::Object(vec![
+                ("SPDXID".to_string(), string(&spdx_id("File", &file_name))),
+                ("fileName".to_string(), string(&file_name)),
+                ("licenseConcluded".to_string(), string(license)),
+            ])
+        })
+        .collect();
+
+    let document = Json::Object(vec![
+        ("spdxVersion".to_string(), string("SPDX-2.3")),
+        ("dataLicense".to_string(), string("CC0-1.0")),
+        ("SPDXID".to_string(), string("SPDXRef-DOCUMENT")),
+        ("name".to_string(), string("qt-cargo-base")),
+        ("packages".to_string(), Json::Array(packages)),
+        ("files".to_string(), Json::Array(files)),
+    ]);
+    let mut json = String::new();
+    document.write(&mut json, 0);
+    json.push('\n');
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent).expect("Unable to create directory");
+    }
+    std::fs::write(out, json).expect("Unable to write file");
+}
+
+#[cfg(test)]
+mod qt_cargo_base_sbom_tests {
+    use super::*;
+    use crate::sources;
+    use crate::util;
+
+    #[test]
+    fn test_write_sbom() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-sbom-test").unwrap();
+        let qt_source = temp.path().join("qt");
+        let tinycbor = qt_source.join(THIRD_PARTY_PATH).join("tinycbor");
+        std::fs::create_dir_all(&tinycbor).unwrap();
+        std::fs::write(
+            tinycbor.join("qt_attribution.json"),
+            "{ \"Id\": \"tinycbor\", \"Name\": \"TinyCBOR\", \"LicenseId\": \"MIT\" }\n",
+        )
+        .unwrap();
+        let pcre2 = qt_source.join(sources::PCRE2_PATH);
+        std::fs::create_dir_all(&pcre2).unwrap();
+
+        let corelib = qt_source.join("qtbase/src/corelib");
+        let mut compiled: Vec<PathBuf> = sources::BOOTSTRAP_SOURCES
+            .iter()
+            .map(|source| corelib.join(source))
+            .collect();
+        compiled.push(pcre2.join("pcre2_compile.c"));
+        let out = temp.path().join("out/sbom.spdx.json");
+        write_sbom(&out, &compiled, &qt_source);
+
+        let sbom = Json::parse(&std::fs::read_to_string(&out).unwrap()).unwrap();
+        let field = |json: &Json, name: &str| match json {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+                .unwrap(),
+            _ => panic!("expected an object"),
+        };
+        let entries = |name: &str| match field(&sbom, name) {
+            Json::Array(entries) => entries,
+            _ => panic!("expected an array"),
+        };
+        let as_pairs = |entries: Vec<Json>, name_key: &str| {
+            entries
+                .iter()
+                .map(|entry| (field(entry, name_key), field(entry, "licenseConcluded")))
+                .collect::<Vec<_>>()
+        };
+        let string = |value: &str| Json::String(value.to_string());
+
+        let packages = as_pairs(entries("packages"), "name");
+        assert!(packages.contains(&(string("tinycbor"), string("MIT"))));
+        assert!(packages.contains(&(string("pcre2"), string(NO_ASSERTION))));
+        assert!(packages.contains(&(
+            string("qtbase"),
+            string(qt_license_expression(QtLicense::OpenSource))
+        )));
+
+        let files = as_pairs(entries("files"), "fileName");
+        assert_eq!(files.len(), sources::BOOTSTRAP_SOURCES.len() + 1);
+        for source in sources::BOOTSTRAP_SOURCES {
+            let file_name = format!("qtbase/src/corelib/{}", source);
+            assert!(
+                files.contains(&(
+                    string(&file_name),
+                    string(qt_license_expression(QtLicense::OpenSource))
+                )),
+                "{}",
+                file_name
+            );
+        }
+        assert!(files.contains(&(
+            string("qtbase/src/3rdparty/pcre2/src/pcre2_compile.c"),
+            string(NO_ASSERTION)
+        )));
+    }
+}
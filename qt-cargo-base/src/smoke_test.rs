@@ -0,0 +1,264 @@
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::QtBuildPaths;
+
+// Smoke test for a compiled QtCore archive
+//
+// Compiles a small program which uses QString, links it against the
+// archive, and runs it. This validates that the archive is complete and
+// linkable, beyond the compilation succeeding. Running the test is opt-in:
+// it requires that binaries for the target can be run on the host.
+//
+// run_qtcore_validation goes further, and checks the results of string,
+// JSON, date and byte order operations. This catches archives which link
+// but behave wrong, for example due to wrong endianness defines or an
+// inconsistent feature combination. Each failed check is printed by the
+// validation program as "FAIL <check>: <details>".
+
+const SMOKE_TEST_SOURCE: &str = r#"
+#include <QtCore/qstring.h>
+
+int main()
+{
+    QString string = QString::fromLatin1("Hello");
+    string.append(QLatin1String(", Qt"));
+    return string == QLatin1String("Hello, Qt") ? 0 : 1;
+}
+"#;
+
+const VALIDATION_SOURCE: &str = r#"
+#include <QtCore/qbytearray.h>
+#include <QtCore/qdatetime.h>
+#include <QtCore/qendian.h>
+#include <QtCore/qjsonarray.h>
+#include <QtCore/qjsondocument.h>
+#include <QtCore/qjsonobject.h>
+#include <QtCore/qstring.h>
+#include <QtCore/qstringlist.h>
+#include <cstdio>
+#include <cstring>
+
+static int failures = 0;
+
+static void check(bool ok, const char *name, const QString &details)
+{
+    if (!ok) {
+        ++failures;
+        std::printf("FAIL %s: %s
+", name, details.toUtf8().constData());
+    }
+}
+
+int main()
+{
+    QString string = QStringLiteral("Hello, Qt 6");
+    check(string.toUpper() == QLatin1String("HELLO, QT 6"), "qstring_upper", string.toUpper());
+    check(string.split(QLatin1Char(' ')).size() == 3, "qstring_split", string);
+    check(QString::number(3.25) == QLatin1String("3.25"), "qstring_number",
+          QString::number(3.25));
+    check(QString::fromUtf8("Ã¦Ã¸Ã¥").size() == 3, "qstring_utf8",
+          QString::fromUtf8("Ã¦Ã¸Ã¥"));
+
+    QJsonParseError error;
+    QJsonDocument document = QJsonDocument::fromJson(
+        "{"name": "qt", "values": [1, 2.5, true], "nested": {"key": null}}", &error);
+    check(error.error == QJsonParseError::NoError, "json_parse", error.errorString());
+    QJsonObject object = document.object();
+    check(object.value(QLatin1String("name")).toString() == QLatin1String("qt"), "json_string",
+          QString::fromUtf8(document.toJson(QJsonDocument::Compact)));
+    check(object.value(QLatin1String("values")).toArray().at(1).toDouble() == 2.5,
+          "json_double", QString::fromUtf8(document.toJson(QJsonDocument::Compact)));
+    check(QJsonDocument(object).toJson(QJsonDocument::Compact).size() > 0, "json_write",
+          QString());
+
+    QDateTime start(QDate(2024, 2, 28), QTime(23, 30), Qt::UTC);
+    QDateTime end = start.addDays(1).addSecs(3600);
+    check(end.date() == QDate(2024, 3, 1) && end.time() == QTime(0, 30), "datetime_add",
+          end.toString(Qt::ISODate));
+    check(start.secsTo(end) == 25 * 3600, "datetime_secs_to",
+          QString::number(start.secsTo(end)));
+    check(start.toString(Qt::ISODate) == QLatin1String("2024-02-28T23:30:00Z"),
+          "datetime_iso", start.toString(Qt::ISODate));
+
+    const unsigned int value = 0x01020304;
+    unsigned char bytes[4];
+    std::memcpy(bytes, &value, sizeof(value));
+    const bool little_endian = bytes[0] == 0x04;
+    check(little_endian == (Q_BYTE_ORDER == Q_LITTLE_ENDIAN), "byte_order",
+          QString::number(bytes[0]));
+    check(qFromBigEndian<quint32>(bytes) == (little_endian ? 0x04030201u : 0x01020304u),
+          "endian_conversion", QString::number(qFromBigEndian<quint32>(bytes), 16));
+    check(QByteArray("qt").toHex() == "7174", "qbytearray_hex", QString());
+
+    return failures == 0 ? 0 : 1;
+}
+"#;
+
+/// Environment variable which enables the runtime validation in build scripts which
+/// support it, see validation_requested
+pub const VALIDATION_ENV_VAR: &str = "QT_CARGO_RUNTIME_VALIDATION";
+
+#[derive(Debug)]
+pub enum TestError {
+    /// The test program could not be compiled or linked
+    Compile(String),
+    /// The test program could not be run
+    Run(String),
+    /// The test program ran, but reported failure
+    Failed(Option<i32>),
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestError::Compile(message) => write!(f, "smoke test compile failed: {}", message),
+            TestError::Run(message) => write!(f, "smoke test could not be run: {}", message),
+            TestError::Failed(code) => write!(f, "smoke test failed with exit code {:?}", code),
+        }
+    }
+}
+
+impl std::error::Error for TestError {}
+
+/// A runtime validation failure, see run_qtcore_validation
+#[derive(Debug)]
+pub enum ValidationFailure {
+    /// The validation program could not be built or run, or failed without reporting
+    /// which check failed
+    Test(TestError),
+    /// The checks which failed, as (check name, details)
+    Checks(Vec<(String, String)>),
+}
+
+impl fmt::Display for ValidationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationFailure::Test(error) => write!(f, "{}", error),
+            ValidationFailure::Checks(checks) => {
+                write!(f, "QtCore validation failed:")?;
+                for (name, details) in checks {
+                    write!(f, "\n  {}: {}", name, details)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationFailure {}
+
+/// Returns true if runtime validation is requested with the QT_CARGO_RUNTIME_VALIDATION
+/// environment variable. Build scripts should call run_qtcore_validation only if so,
+/// since it runs a binary for the target on the host.
+pub fn validation_requested() -> bool {
+    std::env::var_os(VALIDATION_ENV_VAR).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Compiles a small program using QString against the Qt build at paths, links it with
+/// the QtCore archive, and runs it.
+pub fn build_and_run_smoke_test(paths: &QtBuildPaths, archive: &Path) -> Result<(), TestError> {
+    build_and_run(paths, archive, "smoke_test", SMOKE_TEST_SOURCE)
+        .map(|_| ())
+        .map_err(|(error, _)| error)
+}
+
+/// Compiles a program which checks the results of QString, QJsonDocument, QDateTime and
+/// byte order operations against the Qt build at paths, links it with the QtCore archive,
+/// and runs it. Returns the failed checks as the error.
+pub fn run_qtcore_validation(
+    paths: &QtBuildPaths,
+    archive: &Path,
+) -> Result<(), ValidationFailure> {
+    let stdout = match build_and_run(paths, archive, "validation", VALIDATION_SOURCE) {
+        Ok(_) => return Ok(()),
+        Err((TestError::Failed(_), stdout)) => stdout,
+        Err((error, _)) => return Err(ValidationFailure::Test(error)),
+    };
+    let checks = parse_failed_checks(&stdout);
+    match checks.is_empty() {
+        true => Err(ValidationFailure::Test(TestError::Run(stdout))),
+        false => Err(ValidationFailure::Checks(checks)),
+    }
+}
+
+// Returns the "FAIL <check>: <details>" lines of the validation output, as (check, details)
+fn parse_failed_checks(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("FAIL "))
+        .map(|failure| match failure.split_once(": ") {
+            Some((name, details)) => (name.to_string(), details.to_string()),
+            None => (failure.trim_end_matches(':').to_string(), String::new()),
+        })
+        .collect()
+}
+
+// Builds and runs the test program, and returns its output. The output is returned with
+// the error as well, for TestError::Failed.
+fn build_and_run(
+    paths: &QtBuildPaths,
+    archive: &Path,
+    name: &str,
+    source: &str,
+) -> Result<String, (TestError, String)> {
+    let compile_error = |e: std::io::Error| (TestError::Compile(e.to_string()), String::new());
+    let test_path = paths.build_path.join("qt_smoke_test");
+    std::fs::create_dir_all(&test_path).map_err(compile_error)?;
+    let source_path = test_path.join(format!("{}.cpp", name));
+    let executable_path = test_path.join(name);
+    std::fs::write(&source_path, source).map_err(compile_error)?;
+
+    let mut builder = cc::Build::new();
+    crate::configure_for_qt_build(&mut builder, Some(&test_path))
+        .map_err(|e| (TestError::Compile(e.to_string()), String::new()))?;
+    for include_path in &paths.include_paths {
+        builder.include(include_path);
+    }
+    let compiler = builder
+        .try_get_compiler()
+        .map_err(|e| (TestError::Compile(e.to_string()), String::new()))?;
+
+    let output = compiler
+        .to_command()
+        .arg(&source_path)
+        .arg(archive)
+        .arg("-lpthread")
+        .arg("-o")
+        .arg(&executable_path)
+        .output()
+        .map_err(compile_error)?;
+    if !output.status.success() {
+        return Err((
+            TestError::Compile(String::from_utf8_lossy(&output.stderr).into_owned()),
+            String::new(),
+        ));
+    }
+
+    let output = Command::new(&executable_path)
+        .output()
+        .map_err(|e| (TestError::Run(e.to_string()), String::new()))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    match output.status.success() {
+        true => Ok(stdout),
+        false => Err((TestError::Failed(output.status.code()), stdout)),
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_smoke_test_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_failed_checks() {
+        let output = "FAIL byte_order: 1\nunrelated\nFAIL json_write: \n";
+        assert_eq!(
+            parse_failed_checks(output),
+            [
+                ("byte_order".to_string(), "1".to_string()),
+                ("json_write".to_string(), String::new()),
+            ]
+        );
+    }
+}
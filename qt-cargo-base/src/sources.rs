@@ -2,6 +2,12 @@
 // in a central location they can also be used by the auto-tests in
 // lib.rs, in addition to by build.rs scripts.
 
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::configure::{LocaleBackend, QtConfiguration};
+use crate::error::QtBuildError;
+
 pub const BOOTSTRAP_PATH: &'static str = "qtbase/src/corelib";
 pub const BOOTSTRAP_SOURCES: &'static [&'static str] = &[
     "global/qendian.cpp",
@@ -104,6 +110,29 @@ pub const BOOTSTRAP_SOURCES_UNIX: &'static [&'static str] = &[
     "kernel/qsystemsemaphore_unix.cpp",
 ];
 
+// Windows specific bootstrap sources, used instead of BOOTSTRAP_SOURCES_UNIX
+// with configure_qtcore_for_windows_msvc.
+pub const BOOTSTRAP_SOURCES_WIN32: &[&str] = &[
+    "global/qoperatingsystemversion_win.cpp",
+    "io/qfilesystemengine_win.cpp",
+    "io/qfilesystemiterator_win.cpp",
+    "io/qfsfileengine_win.cpp",
+    "kernel/qcoreapplication_win.cpp",
+    "kernel/qwinregistry.cpp",
+    "plugin/qsystemlibrary.cpp",
+];
+
+// macOS specific bootstrap sources, used in addition to BOOTSTRAP_SOURCES_UNIX
+// with configure_qtcore_for_macos. These are Objective-C++, compile them with
+// a builder from objective_cpp_builder.
+pub const BOOTSTRAP_SOURCES_MACOS: &[&str] = &[
+    "global/qoperatingsystemversion_darwin.mm",
+    "io/qfilesystemengine_mac.mm",
+    "io/qstandardpaths_mac.mm",
+    "kernel/qcore_foundation.mm",
+    "kernel/qcore_mac.mm",
+];
+
 pub const MOC_PATH: &'static str = "qtbase/src/tools/moc";
 pub const MOC_SOURCES: &'static [&'static str] = &[
     "collectjson.cpp",
@@ -114,3 +143,523 @@ pub const MOC_SOURCES: &'static [&'static str] = &[
     "preprocessor.cpp",
     "token.cpp",
 ];
+
+// QtTest, for building tests against the vendored QtCore, see
+// QtConfiguration::set_testlib. The sources which need QtGui (event
+// simulation, QAbstractItemModelTester needs QtCore only) and the platform
+// specific loggers are not listed. The benchmark backends are compiled in
+// qbenchmark*.cpp, except for valgrind which is disabled.
+pub const TESTLIB_PATH: &str = "qtbase/src/testlib";
+pub const TESTLIB_SOURCES: &[&str] = &[
+    "qabstractitemmodeltester.cpp",
+    "qabstracttestlogger.cpp",
+    "qasciikey.cpp",
+    "qbenchmark.cpp",
+    "qbenchmarkevent.cpp",
+    "qbenchmarkmeasurement.cpp",
+    "qbenchmarkmetric.cpp",
+    "qbenchmarkperfevents.cpp",
+    "qcsvbenchmarklogger.cpp",
+    "qjunittestlogger.cpp",
+    "qplaintestlogger.cpp",
+    "qsignaldumper.cpp",
+    "qtaptestlogger.cpp",
+    "qteamcitylogger.cpp",
+    "qtestblacklist.cpp",
+    "qtestcase.cpp",
+    "qtestdata.cpp",
+    "qtestelement.cpp",
+    "qtestelementattribute.cpp",
+    "qtestjunitstreamer.cpp",
+    "qtestlog.cpp",
+    "qtestresult.cpp",
+    "qtesttable.cpp",
+    "qxmltestlogger.cpp",
+];
+
+// Bundled PCRE2, used by QRegularExpression. The same sources are
+// compiled for each code unit width; the width is selected with the
+// PCRE2_CODE_UNIT_WIDTH define, see configure_pcre2() in lib.rs. Not
+// needed if "regularexpression" is disabled, see
+// QtConfiguration::requires_pcre2.
+pub const PCRE2_PATH: &str = "qtbase/src/3rdparty/pcre2/src";
+pub const PCRE2_SOURCES: &[&str] = &[
+    "pcre2_auto_possess.c",
+    "pcre2_chartables.c",
+    "pcre2_compile.c",
+    "pcre2_config.c",
+    "pcre2_context.c",
+    "pcre2_dfa_match.c",
+    "pcre2_error.c",
+    "pcre2_extuni.c",
+    "pcre2_find_bracket.c",
+    "pcre2_jit_compile.c",
+    "pcre2_maketables.c",
+    "pcre2_match.c",
+    "pcre2_match_data.c",
+    "pcre2_newline.c",
+    "pcre2_ord2utf.c",
+    "pcre2_pattern_info.c",
+    "pcre2_script_run.c",
+    "pcre2_serialize.c",
+    "pcre2_string_utils.c",
+    "pcre2_study.c",
+    "pcre2_substitute.c",
+    "pcre2_substring.c",
+    "pcre2_tables.c",
+    "pcre2_ucd.c",
+    "pcre2_valid_utf.c",
+    "pcre2_xclass.c",
+];
+
+// QtCore sources which are compiled only when the corresponding feature
+// is enabled, relative to qtbase/src/corelib. Use filter_sources() to
+// remove the sources for disabled features from a source list.
+//
+// thread: QThread is always available, but runs without a separate thread
+// of execution when the feature is disabled. QMutex, QReadWriteLock,
+// QSemaphore, QWaitCondition and QThreadPool are not compiled, and no
+// threading library is required. See QtConfiguration::set_thread.
+//
+// future: See QtConfiguration::set_concurrent. "future" and "concurrent"
+// depend on "thread" and must be disabled with it, but can be disabled on
+// their own to leave out QFuture and QtConcurrent while keeping QThread
+// and QThreadPool. "concurrent" has no QtCore sources.
+//
+// timezone, icu: See QtConfiguration::set_timezone_backend and
+// QtConfiguration::set_locale_backend. The ICU backend sources are
+// compiled only if "icu" is enabled. The built-in locale backend has
+// sources of its own, which are selected by locale_sources.
+//
+// cborstreamreader, cborstreamwriter: See QtConfiguration::set_cbor_streams.
+// The stream classes use tinycbor; add TINYCBOR_INCLUDE_PATH to the include
+// paths if QtConfiguration::requires_tinycbor() returns true.
+//
+// glib: See QtConfiguration::set_glib. The generic unix event dispatcher
+// (kernel/qeventdispatcher_unix.cpp) is always compiled.
+//
+// topleveldomain: See QtConfiguration::set_network_features. This is the only
+// network related QtCore feature with sources; QUrl and IDNA support
+// (io/qurl*.cpp) are always compiled and do not access the network.
+//
+// sharedmemory, systemsemaphore: See QtConfiguration::set_ipc. The shared
+// implementation files guard their content with QT_CONFIG checks as well.
+//
+// process, processenvironment: See QtConfiguration::set_process. qprocess.cpp
+// also contains QProcessEnvironment; QProcess itself is guarded with
+// QT_CONFIG(process). forkfd_qt.cpp compiles the bundled forkfd, which
+// needs FORKFD_INCLUDE_PATH.
+//
+// animation: See QtConfiguration::set_animation. Requires "properties"
+// and "easingcurve" (tools/qeasingcurve.cpp, which is gated separately).
+//
+// library: See QtConfiguration::set_plugins. QFactoryLoader
+// (plugin/qfactoryloader.cpp) is always compiled, since it also handles
+// statically linked plugins. The dynamic loading code requires libdl on
+// linux (added to QtConfiguration::link_libraries).
+//
+// regularexpression: See QtConfiguration::set_regular_expression. Compile
+// the bundled PCRE2 (PCRE2_SOURCES) only if
+// QtConfiguration::requires_pcre2() returns true.
+//
+// xmlstream: See QtConfiguration::set_xml_stream. QXmlStreamReader and
+// QXmlStreamWriter share qxmlstream.cpp, which guards each class with its
+// own QT_CONFIG check, so the sources are left out only if both are
+// disabled. This is independent of the QtXml module ("xml", QDomDocument
+// and the SAX classes), which is not part of QtCore.
+//
+// permissions: The application permission API (QPermission,
+// kernel/qpermissions.cpp) is new in Qt 6.5. The vendored Qt 6.2 has no
+// "permissions" feature and no sources to gate; add the feature here when
+// the Qt source is updated.
+pub const QTCORE_FEATURE_SOURCES: &[(&str, &[&str])] = &[
+    (
+        "thread",
+        &[
+            "thread/qatomic.cpp",
+            "thread/qmutex.cpp",
+            "thread/qreadwritelock.cpp",
+            "thread/qsemaphore.cpp",
+            "thread/qthreadpool.cpp",
+            "thread/qthreadstorage.cpp",
+            "thread/qthread_unix.cpp",
+            "thread/qwaitcondition_unix.cpp",
+        ],
+    ),
+    (
+        "future",
+        &[
+            "thread/qexception.cpp",
+            "thread/qfutureinterface.cpp",
+            "thread/qfuturewatcher.cpp",
+            "thread/qresultstore.cpp",
+        ],
+    ),
+    (
+        "timezone",
+        &[
+            "time/qtimezone.cpp",
+            "time/qtimezoneprivate.cpp",
+            "time/qtimezoneprivate_tz.cpp",
+            "time/qtimezoneprivate_icu.cpp",
+        ],
+    ),
+    (
+        "icu",
+        &[
+            "time/qtimezoneprivate_icu.cpp",
+            "text/qlocale_icu.cpp",
+            "text/qcollator_icu.cpp",
+        ],
+    ),
+    ("cborstreamreader", &["serialization/qcborstreamreader.cpp"]),
+    ("cborstreamwriter", &["serialization/qcborstreamwriter.cpp"]),
+    ("glib", &["kernel/qeventdispatcher_glib.cpp"]),
+    ("topleveldomain", &["io/qtldurl.cpp"]),
+    (
+        "animation",
+        &[
+            "animation/qabstractanimation.cpp",
+            "animation/qanimationgroup.cpp",
+            "animation/qparallelanimationgroup.cpp",
+            "animation/qpauseanimation.cpp",
+            "animation/qpropertyanimation.cpp",
+            "animation/qsequentialanimationgroup.cpp",
+            "animation/qvariantanimation.cpp",
+        ],
+    ),
+    ("easingcurve", &["tools/qeasingcurve.cpp"]),
+    (
+        "library",
+        &[
+            "plugin/qlibrary.cpp",
+            "plugin/qlibrary_unix.cpp",
+            "plugin/qpluginloader.cpp",
+        ],
+    ),
+    ("regularexpression", &["text/qregularexpression.cpp"]),
+    (
+        "xmlstream",
+        &[
+            "serialization/qxmlstream.cpp",
+            "serialization/qxmlstreamgrammar.cpp",
+            "serialization/qxmlutils.cpp",
+        ],
+    ),
+    (
+        "processenvironment",
+        &["io/qprocess.cpp", "io/qprocess_unix.cpp"],
+    ),
+    ("process", &["io/forkfd_qt.cpp"]),
+    (
+        "sharedmemory",
+        &[
+            "kernel/qsharedmemory.cpp",
+            "kernel/qsharedmemory_posix.cpp",
+            "kernel/qsharedmemory_systemv.cpp",
+            "kernel/qsharedmemory_unix.cpp",
+        ],
+    ),
+    (
+        "systemsemaphore",
+        &[
+            "kernel/qsystemsemaphore.cpp",
+            "kernel/qsystemsemaphore_posix.cpp",
+            "kernel/qsystemsemaphore_systemv.cpp",
+            "kernel/qsystemsemaphore_unix.cpp",
+        ],
+    ),
+];
+
+// Categorized logging (qDebug and friends, QLoggingCategory and the
+// QT_LOGGING_RULES / qtlogging.ini rules registry). Not feature gated in
+// Qt 6; these are part of BOOTSTRAP_SOURCES and must always be compiled.
+// See QtConfiguration::set_logging_backend.
+pub const QTCORE_LOGGING_SOURCES: &[&str] = &[
+    "global/qlogging.cpp",
+    "io/qloggingcategory.cpp",
+    "io/qloggingregistry.cpp",
+];
+
+// The file engines and the resource system (":/" paths, QResource). Not
+// feature gated in Qt 6, and there is no feature which leaves out the
+// resource system: QFile and QDir dispatch file names to the resource
+// engine or the native file system engine in qfilesystemengine.cpp and
+// qabstractfileengine.cpp, so real file I/O needs these as well. These
+// are part of BOOTSTRAP_SOURCES and must always be compiled. Resources
+// compiled with rcc (see host_tools::add_qrc) register themselves with
+// the resource system when linked in; a build which does not use
+// resources pays only for the (small) resource engine.
+pub const QTCORE_FILE_ENGINE_SOURCES: &[&str] = &[
+    "io/qabstractfileengine.cpp",
+    "io/qfilesystemengine.cpp",
+    "io/qfsfileengine.cpp",
+    "io/qfsfileengine_iterator.cpp",
+    "io/qresource.cpp",
+];
+
+// Unicode character tables: Qt 6 has no reduced tables build, and no
+// feature or define which selects one. The generated tables
+// (text/qunicodetables.cpp) are included by text/qchar.cpp, which is in
+// turn included by text/qstring.cpp, so they cannot be left out without
+// patching Qt. QChar, QString case conversion and normalization all use
+// the full property tables, so there is no Unicode size option for
+// size-constrained targets.
+
+// State machine: QStateMachine moved from QtCore to the Qt State Machine
+// module (qtscxml) in Qt 6. qtbase has no state machine sources and no
+// "statemachine" feature, so there is nothing to gate here; the state
+// machine is never part of the QtCore build.
+
+// Include path for the bundled tinycbor, relative to the Qt source
+pub const TINYCBOR_INCLUDE_PATH: &str = "qtbase/src/3rdparty/tinycbor/src";
+
+// Include path for the bundled forkfd, relative to the Qt source
+pub const FORKFD_INCLUDE_PATH: &str = "qtbase/src/3rdparty/forkfd";
+
+// Helper headers shared between the Qt tools (moc, rcc, ...), relative to the Qt
+// source. See write_tools_shared_forwarding_headers.
+pub const TOOLS_SHARED_PATH: &str = "qtbase/src/tools/shared";
+
+/// Returns the sources which should be compiled for the given configuration: sources
+/// gated on a feature which is disabled in the configuration are removed.
+pub fn filter_sources<'a>(sources: &[&'a str], qt_configuration: &QtConfiguration) -> Vec<&'a str> {
+    let is_disabled = |source: &str| {
+        QTCORE_FEATURE_SOURCES
+            .iter()
+            .filter(|(feature, _)| qt_configuration.feature(feature) == Some(false))
+            .any(|(_, feature_sources)| feature_sources.contains(&source))
+    };
+    sources
+        .iter()
+        .filter(|source| !is_disabled(source))
+        .copied()
+        .collect()
+}
+
+/// Returns the QtCore locale sources for the configured locale backend (see
+/// QtConfiguration::set_locale_backend), relative to qtbase/src/corelib. QLocale itself
+/// (text/qlocale.cpp) is part of BOOTSTRAP_SOURCES.
+pub fn locale_sources(qt_configuration: &QtConfiguration) -> Vec<&'static str> {
+    let mut sources = vec!["text/qcollator.cpp", "text/qlocale_unix.cpp"];
+    match qt_configuration.locale_backend() {
+        LocaleBackend::Icu => sources.extend(["text/qcollator_icu.cpp", "text/qlocale_icu.cpp"]),
+        LocaleBackend::Builtin => sources.push("text/qcollator_posix.cpp"),
+    }
+    sources
+}
+
+// Source manifests
+//
+// As an alternative to the constants above, source lists can be read from a
+// TOML manifest, for example generated from Qt's CMake files. This makes
+// updating the source lists for a new Qt version a data change. Each module
+// is a table with a path (relative to the Qt source) and a list of sources
+// (relative to that path):
+//
+//   [bootstrap]
+//   path = "qtbase/src/corelib"
+//   sources = ["global/qendian.cpp", "global/qfloat16.cpp"]
+
+/// The source files for one module, e.g. "bootstrap" or "moc"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleSources {
+    /// Path relative to the Qt source path
+    pub path: String,
+    /// Source files, relative to path
+    pub sources: Vec<String>,
+}
+
+impl ModuleSources {
+    fn from_constants(path: &str, sources: &[&str]) -> ModuleSources {
+        ModuleSources {
+            path: path.to_string(),
+            sources: sources.iter().map(|source| source.to_string()).collect(),
+        }
+    }
+}
+
+/// Source lists by module, see load_source_manifest
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceManifest {
+    modules: BTreeMap<String, ModuleSources>,
+}
+
+impl SourceManifest {
+    /// Returns a manifest with the built-in source lists: "bootstrap", "bootstrap_unix",
+    /// "bootstrap_win32", "bootstrap_macos", "moc", and "pcre2".
+    pub fn builtin() -> SourceManifest {
+        let modules = [
+            ("bootstrap", BOOTSTRAP_PATH, BOOTSTRAP_SOURCES),
+            ("bootstrap_unix", BOOTSTRAP_PATH, BOOTSTRAP_SOURCES_UNIX),
+            ("bootstrap_win32", BOOTSTRAP_PATH, BOOTSTRAP_SOURCES_WIN32),
+            ("bootstrap_macos", BOOTSTRAP_PATH, BOOTSTRAP_SOURCES_MACOS),
+            ("moc", MOC_PATH, MOC_SOURCES),
+            ("pcre2", PCRE2_PATH, PCRE2_SOURCES),
+        ];
+        SourceManifest {
+            modules: modules
+                .iter()
+                .map(|(name, path, sources)| {
+                    (
+                        name.to_string(),
+                        ModuleSources::from_constants(path, sources),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the sources for the given module. Falls back to the built-in source
+    /// lists for modules which are not in the manifest.
+    pub fn module(&self, name: &str) -> Option<ModuleSources> {
+        self.modules
+            .get(name)
+            .cloned()
+            .or_else(|| SourceManifest::builtin().modules.remove(name))
+    }
+
+    /// Returns the names of the modules in the manifest (excluding built-in fallbacks)
+    pub fn module_names(&self) -> impl Iterator<Item = &str> {
+        self.modules.keys().map(|name| name.as_str())
+    }
+}
+
+/// Loads a TOML source manifest from the given path. Returns QtBuildError::MissingSource
+/// if the manifest does not exist, and QtBuildError::InvalidArgument if it is malformed.
+pub fn load_source_manifest<P: AsRef<Path>>(path: P) -> Result<SourceManifest, QtBuildError> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(QtBuildError::missing_source(path))?;
+    parse_source_manifest(&content).map_err(|message| QtBuildError::InvalidArgument {
+        message: format!("invalid source manifest {}: {}", path.display(), message),
+    })
+}
+
+fn parse_source_manifest(content: &str) -> Result<SourceManifest, String> {
+    let manifest: toml::Value = content.parse().map_err(|e| format!("{}", e))?;
+    let table = manifest.as_table().ok_or("expected a table")?;
+    let mut modules = BTreeMap::new();
+    for (name, module) in table {
+        let path = module
+            .get("path")
+            .and_then(|path| path.as_str())
+            .ok_or(format!("module {} has no path", name))?;
+        let sources = module
+            .get("sources")
+            .and_then(|sources| sources.as_array())
+            .ok_or(format!("module {} has no sources", name))?
+            .iter()
+            .map(|source| source.as_str().map(|source| source.to_string()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(format!("module {} has non-string sources", name))?;
+        modules.insert(
+            name.clone(),
+            ModuleSources {
+                path: path.to_string(),
+                sources,
+            },
+        );
+    }
+    Ok(SourceManifest { modules })
+}
+
+#[cfg(test)]
+mod qt_cargo_base_sources_tests {
+    use super::*;
+    use crate::configure;
+    use crate::util;
+
+    #[test]
+    fn test_filter_sources() {
+        let sources = [
+            "text/qstring.cpp",
+            "text/qbytearray.cpp",
+            "thread/qmutex.cpp",
+        ];
+        let mut config = QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        assert_eq!(filter_sources(&sources, &config), sources);
+
+        config.set_feature("thread", false);
+        assert_eq!(
+            filter_sources(&sources, &config),
+            ["text/qstring.cpp", "text/qbytearray.cpp"]
+        );
+    }
+
+    #[test]
+    fn test_locale_sources() {
+        let mut config = QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        assert_eq!(config.locale_backend(), LocaleBackend::Builtin);
+        let sources = locale_sources(&config);
+        assert!(sources.contains(&"text/qcollator_posix.cpp"));
+        assert_eq!(
+            filter_sources(&sources, &config),
+            [
+                "text/qcollator.cpp",
+                "text/qlocale_unix.cpp",
+                "text/qcollator_posix.cpp"
+            ]
+        );
+        assert!(!config.link_libraries().contains(&"icuuc"));
+
+        config.set_locale_backend(LocaleBackend::Icu);
+        let sources = locale_sources(&config);
+        assert!(!sources.contains(&"text/qcollator_posix.cpp"));
+        assert_eq!(filter_sources(&sources, &config), sources);
+        assert!(config.link_libraries().contains(&"icuuc"));
+    }
+
+    #[test]
+    fn test_logging_sources() {
+        for source in QTCORE_LOGGING_SOURCES {
+            assert!(BOOTSTRAP_SOURCES.contains(source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_file_engine_sources() {
+        let mut config = QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        for source in QTCORE_FILE_ENGINE_SOURCES {
+            assert!(BOOTSTRAP_SOURCES.contains(source), "{}", source);
+        }
+        assert_eq!(
+            filter_sources(QTCORE_FILE_ENGINE_SOURCES, &config),
+            QTCORE_FILE_ENGINE_SOURCES
+        );
+    }
+
+    #[test]
+    fn test_load_source_manifest() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-sources-test").unwrap();
+        let manifest_path = temp.path().join("sources.toml");
+        std::fs::write(
+            &manifest_path,
+            "[moc]\npath = \"qtbase/src/tools/moc\"\nsources = [\"main.cpp\", \"moc.cpp\"]\n",
+        )
+        .unwrap();
+
+        let manifest = load_source_manifest(&manifest_path).unwrap();
+        assert_eq!(manifest.module_names().collect::<Vec<_>>(), ["moc"]);
+        let moc = manifest.module("moc").unwrap();
+        assert_eq!(moc.path, MOC_PATH);
+        assert_eq!(moc.sources, ["main.cpp", "moc.cpp"]);
+
+        // Modules not in the manifest fall back to the built-in lists
+        let bootstrap = manifest.module("bootstrap").unwrap();
+        assert_eq!(bootstrap.sources.len(), BOOTSTRAP_SOURCES.len());
+        assert!(manifest.module("qtgui").is_none());
+
+        assert!(matches!(
+            load_source_manifest(temp.path().join("missing.toml")),
+            Err(QtBuildError::MissingSource { .. })
+        ));
+        std::fs::write(&manifest_path, "[moc]\npath = \"qtbase/src/tools/moc\"\n").unwrap();
+        assert!(matches!(
+            load_source_manifest(&manifest_path),
+            Err(QtBuildError::InvalidArgument { .. })
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+
+// Content-addressed header store
+//
+// Builds which configure Qt in several places (one OUT_DIR per crate and
+// profile, CI jobs sharing a cache) write the same generated headers many
+// times. ContentStore keeps one copy ("blob") of each distinct header
+// content, named after util::content_hash of the content, and the output
+// tree refers to the blobs: with symlinks on unix, and copies elsewhere.
+//
+// A symlinked forwarding header still resolves its relative "#include"
+// against the directory of the symlink, since the compilers use the path
+// the header was opened with, not the resolved path.
+//
+// Blobs are written to a temporary file and renamed into place, so
+// several builds can share a store concurrently. Blobs are never removed;
+// delete the store directory to clean it.
+
+/// A content-addressed store for generated headers, see configure::write_config_header_to_store
+/// and configure::write_forwarding_header_to_store.
+#[derive(Clone, Debug)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+impl ContentStore {
+    /// Opens the store at root, creating the directory if needed
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<ContentStore> {
+        std::fs::create_dir_all(root.as_ref())?;
+        Ok(ContentStore {
+            root: util::safe_canonicalize(root)?,
+        })
+    }
+
+    /// The store directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path of the blob for content. The blob exists if the content has been
+    /// written to the store.
+    pub fn blob_path(&self, content: &[u8]) -> PathBuf {
+        let hash = format!("{:016x}", util::content_hash(content));
+        self.root.join(&hash[..2]).join(&hash)
+    }
+
+    /// Stores content, and makes path refer to the stored blob. An existing file at path
+    /// is replaced. Returns the path of the blob.
+    pub fn write<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> io::Result<PathBuf> {
+        let blob_path = self.store_blob(content)?;
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if path.symlink_metadata().is_ok() {
+            std::fs::remove_file(path)?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&blob_path, path)?;
+        #[cfg(not(unix))]
+        std::fs::copy(&blob_path, path).map(|_| ())?;
+        Ok(blob_path)
+    }
+
+    fn store_blob(&self, content: &[u8]) -> io::Result<PathBuf> {
+        let blob_path = self.blob_path(content);
+        match std::fs::read(&blob_path) {
+            Ok(stored) if stored == content => return Ok(blob_path),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("content hash collision at {:?}", blob_path),
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let blob_dir = blob_path.parent().unwrap();
+        std::fs::create_dir_all(blob_dir)?;
+        let temp_path = blob_dir.join(format!(
+            "{}.{}.tmp",
+            blob_path.file_name().unwrap().to_string_lossy(),
+            std::process::id()
+        ));
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &blob_path)?;
+        Ok(blob_path)
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_store_tests {
+    use super::*;
+
+    #[test]
+    fn test_content_store() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-store-test").unwrap();
+        let store = ContentStore::new(temp.path().join("store")).unwrap();
+        let content = b"#include \"../qfoo.h\"\n";
+        let first = temp.path().join("build1/QtCore/qfoo.h");
+        let second = temp.path().join("build2/QtCore/qfoo.h");
+        let first_blob = store.write(&first, content).unwrap();
+        let second_blob = store.write(&second, content).unwrap();
+        assert_eq!(first_blob, second_blob);
+        assert_eq!(first_blob, store.blob_path(content));
+
+        let blobs: Vec<PathBuf> = walkdir::WalkDir::new(store.root())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .collect();
+        assert_eq!(blobs, vec![first_blob.clone()]);
+        assert_eq!(std::fs::read(&first).unwrap(), content);
+        assert_eq!(std::fs::read(&second).unwrap(), content);
+        #[cfg(unix)]
+        {
+            assert_eq!(std::fs::read_link(&first).unwrap(), first_blob);
+            assert_eq!(std::fs::read_link(&second).unwrap(), first_blob);
+        }
+
+        // Rewriting with new content replaces the reference, and keeps the old blob
+        store.write(&second, b"#include \"../qbar.h\"\n").unwrap();
+        assert_eq!(std::fs::read(&first).unwrap(), content);
+        assert_eq!(std::fs::read(&second).unwrap(), b"#include \"../qbar.h\"\n");
+    }
+}
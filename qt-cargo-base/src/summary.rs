@@ -0,0 +1,129 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::util;
+
+// Build summary
+//
+// Collects a short summary of what was configured and built: Qt version,
+// target, feature counts, generated header count and archive path. The
+// summary is read back from the generated output, and thus reflects what
+// was actually written to disk.
+
+/// Environment variable which enables printing the build summary
+pub const SUMMARY_ENV_VAR: &str = "QT_CARGO_SUMMARY";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BuildSummary {
+    pub qt_version: String,
+    pub target: String,
+    pub features_enabled: usize,
+    pub features_disabled: usize,
+    pub headers_generated: usize,
+    pub archive_path: PathBuf,
+}
+
+impl BuildSummary {
+    /// Creates a summary for the configure output at destination_path (as returned
+    /// by configure_for_qt_build) and the compiled archive at archive_path.
+    pub fn from_output<P, Q>(destination_path: P, archive_path: Q) -> BuildSummary
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let config_headers_path = destination_path.as_ref().join("qt_config_headers");
+        let forwarding_headers_path = destination_path.as_ref().join("qt_forwarding_headers");
+
+        let mut summary = BuildSummary {
+            // Standalone builds (no TARGET) use the configure_for_qt_build default target
+            target: crate::target::BuildTriples::from_env().target,
+            archive_path: archive_path.as_ref().to_path_buf(),
+            ..Default::default()
+        };
+
+        for header in util::glob_files(&config_headers_path, OsStr::new("h")) {
+            let content = std::fs::read_to_string(&header).unwrap_or_default();
+            for line in content.lines() {
+                let mut tokens = line.split_whitespace().skip(1);
+                match (tokens.next(), tokens.next()) {
+                    (Some("QT_VERSION_STR"), Some(value)) => {
+                        summary.qt_version = value.trim_matches('"').to_string()
+                    }
+                    (Some(name), Some("1")) if name.starts_with("QT_FEATURE_") => {
+                        summary.features_enabled += 1
+                    }
+                    (Some(name), Some("-1")) if name.starts_with("QT_FEATURE_") => {
+                        summary.features_disabled += 1
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        summary.headers_generated = [config_headers_path, forwarding_headers_path]
+            .iter()
+            .flat_map(|path| WalkDir::new(path).into_iter().filter_map(|e| e.ok()))
+            .filter(|e| e.file_type().is_file())
+            .count();
+
+        summary
+    }
+}
+
+impl fmt::Display for BuildSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Qt version: {}", self.qt_version)?;
+        writeln!(f, "Target: {}", self.target)?;
+        writeln!(
+            f,
+            "Features: {} enabled, {} disabled",
+            self.features_enabled, self.features_disabled
+        )?;
+        writeln!(f, "Generated headers: {}", self.headers_generated)?;
+        write!(f, "Archive: {}", self.archive_path.display())
+    }
+}
+
+/// Prints a build summary as "cargo:warning" lines if the QT_CARGO_SUMMARY environment
+/// variable is set. Call at the end of the build script, after compiling.
+pub fn print_build_summary<P, Q>(destination_path: P, archive_path: Q)
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    if std::env::var_os(SUMMARY_ENV_VAR).is_none() {
+        return;
+    }
+    let summary = BuildSummary::from_output(destination_path, archive_path);
+    for line in summary.to_string().lines() {
+        println!("cargo:warning={}", line);
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_summary_tests {
+    use super::*;
+    use crate::configure;
+
+    #[test]
+    fn test_build_summary() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-summary-test").unwrap();
+        let qt_path: Option<&str> = None;
+
+        let mut config = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        config.set_bindable_properties(false);
+        configure::write_configuration(&config, temp.path().join("qt_config_headers"), qt_path)
+            .unwrap();
+
+        let summary = BuildSummary::from_output(temp.path(), "libqtcore.a");
+        assert_eq!(summary.qt_version, "6.2.0");
+        assert_eq!(summary.headers_generated, 5);
+        assert!(summary.features_enabled > 0);
+        assert!(summary.features_disabled > 0);
+        assert!(summary.to_string().contains("Archive: libqtcore.a"));
+    }
+}
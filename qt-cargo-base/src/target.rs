@@ -0,0 +1,199 @@
+// Target properties
+//
+// Qt detects the pointer size and byte order at compile time, but qconfig.h
+// may set them explicitly (QT_POINTER_SIZE and Q_BYTE_ORDER). Writing them
+// from the cargo target makes sure that the Qt build agrees with the Rust
+// code it is linked into, also when cross-compiling.
+//
+// These are the only endian dependent parts of the configuration. The
+// generated data in the Qt sources (the Unicode tables, the CLDR locale
+// data) is endian independent, and QDataStream, CBOR and the other
+// serialization code convert at run time based on Q_BYTE_ORDER. Since an
+// explicit Q_BYTE_ORDER overrides Qt's compile time detection, it must
+// never be taken from the host: see QtBuildOptions::target_data_layout
+// for builders which target a different architecture than the cargo
+// target.
+
+/// Returns the target triple of the machine this code runs on, for builds which are not
+/// run by cargo (tests and tools). The triple is derived from the architecture and OS this
+/// crate was compiled for, e.g. "aarch64-unknown-linux-gnu".
+pub fn default_triple() -> String {
+    let arch = std::env::consts::ARCH;
+    match std::env::consts::OS {
+        "linux" => format!("{}-unknown-linux-gnu", arch),
+        "macos" => format!("{}-apple-darwin", arch),
+        "windows" => format!("{}-pc-windows-msvc", arch),
+        os => format!("{}-unknown-{}", arch, os),
+    }
+}
+
+/// The host and target triples for a build
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildTriples {
+    pub host: String,
+    pub target: String,
+}
+
+impl BuildTriples {
+    /// Returns the triples from the HOST and TARGET environment variables (set by cargo
+    /// for build.rs), or default_triple() if not set.
+    pub fn from_env() -> BuildTriples {
+        BuildTriples::resolve(None, None)
+    }
+
+    /// Returns the given triples, using the HOST and TARGET environment variables or
+    /// default_triple() for triples which are not given.
+    pub fn resolve(host: Option<&str>, target: Option<&str>) -> BuildTriples {
+        BuildTriples {
+            host: resolve_triple(host, std::env::var("HOST").ok()),
+            target: resolve_triple(target, std::env::var("TARGET").ok()),
+        }
+    }
+}
+
+fn resolve_triple(explicit: Option<&str>, from_env: Option<String>) -> String {
+    explicit
+        .map(str::to_string)
+        .or(from_env)
+        .unwrap_or_else(default_triple)
+}
+
+/// Byte order of the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Pointer size and byte order of the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetDataLayout {
+    /// Pointer size in bytes
+    pub pointer_size: u32,
+    pub endianness: Endianness,
+}
+
+impl TargetDataLayout {
+    /// Returns the data layout for the cargo build target. Uses CARGO_CFG_TARGET_POINTER_WIDTH
+    /// and CARGO_CFG_TARGET_ENDIAN when called from build.rs, and the target triple from
+    /// BuildTriples::from_env otherwise.
+    pub fn from_cargo_env() -> TargetDataLayout {
+        let target = BuildTriples::from_env().target;
+        let mut layout = TargetDataLayout::from_target_triple(&target);
+        if let Some(pointer_width) = std::env::var("CARGO_CFG_TARGET_POINTER_WIDTH")
+            .ok()
+            .and_then(|width| width.parse::<u32>().ok())
+        {
+            layout.pointer_size = pointer_width / 8;
+        }
+        match std::env::var("CARGO_CFG_TARGET_ENDIAN").as_deref() {
+            Ok("little") => layout.endianness = Endianness::Little,
+            Ok("big") => layout.endianness = Endianness::Big,
+            _ => {}
+        }
+        layout
+    }
+
+    /// Returns the data layout for the given target triple, based on the architecture.
+    /// Unknown architectures are assumed to be 64-bit little endian.
+    pub fn from_target_triple(target: &str) -> TargetDataLayout {
+        let arch = target.split('-').next().unwrap_or_default();
+        let is_32_bit = target.ends_with("gnux32")
+            || target.ends_with("gnu_ilp32")
+            || ["i386", "i586", "i686", "arm", "thumb", "riscv32", "wasm32"]
+                .iter()
+                .any(|prefix| arch.starts_with(prefix))
+            || [
+                "mips",
+                "mipsel",
+                "mipsisa32r6",
+                "mipsisa32r6el",
+                "powerpc",
+                "sparc",
+                "m68k",
+                "hexagon",
+            ]
+            .contains(&arch);
+        let is_big_endian = arch.starts_with("armeb")
+            || arch.ends_with("_be")
+            || [
+                "mips",
+                "mips64",
+                "mipsisa32r6",
+                "mipsisa64r6",
+                "powerpc",
+                "powerpc64",
+                "sparc",
+                "sparc64",
+                "sparcv9",
+                "s390x",
+                "m68k",
+            ]
+            .contains(&arch);
+        TargetDataLayout {
+            pointer_size: if is_32_bit { 4 } else { 8 },
+            endianness: if is_big_endian {
+                Endianness::Big
+            } else {
+                Endianness::Little
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_target_tests {
+    use super::*;
+
+    #[test]
+    fn test_build_triples() {
+        assert!(default_triple().starts_with(std::env::consts::ARCH));
+        assert_eq!(
+            resolve_triple(
+                Some("aarch64-unknown-linux-gnu"),
+                Some("x86_64-pc-windows-msvc".into())
+            ),
+            "aarch64-unknown-linux-gnu"
+        );
+        assert_eq!(
+            resolve_triple(None, Some("x86_64-pc-windows-msvc".into())),
+            "x86_64-pc-windows-msvc"
+        );
+        assert_eq!(resolve_triple(None, None), default_triple());
+
+        let triples = BuildTriples::resolve(
+            Some("x86_64-unknown-linux-gnu"),
+            Some("powerpc-unknown-linux-gnu"),
+        );
+        assert_eq!(triples.host, "x86_64-unknown-linux-gnu");
+        assert_eq!(triples.target, "powerpc-unknown-linux-gnu");
+    }
+
+    #[test]
+    fn test_target_data_layout() {
+        let layout = |pointer_size, endianness| TargetDataLayout {
+            pointer_size,
+            endianness,
+        };
+        assert_eq!(
+            TargetDataLayout::from_target_triple("x86_64-unknown-linux-gnu"),
+            layout(8, Endianness::Little)
+        );
+        assert_eq!(
+            TargetDataLayout::from_target_triple("armv7-unknown-linux-gnueabihf"),
+            layout(4, Endianness::Little)
+        );
+        assert_eq!(
+            TargetDataLayout::from_target_triple("powerpc-unknown-linux-gnu"),
+            layout(4, Endianness::Big)
+        );
+        assert_eq!(
+            TargetDataLayout::from_target_triple("s390x-unknown-linux-gnu"),
+            layout(8, Endianness::Big)
+        );
+        assert_eq!(
+            TargetDataLayout::from_target_triple("mipsel-unknown-linux-gnu"),
+            layout(4, Endianness::Little)
+        );
+    }
+}
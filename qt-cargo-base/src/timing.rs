@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::configure::QtConfiguration;
+
+// Build time estimates
+//
+// BuildTimings stores the measured build times per configuration (see
+// QtConfiguration::cache_key) in a small text file, which is kept
+// across builds, for example in the cargo target directory. A build
+// frontend can use the stored history to show an estimate of how long
+// a build with the same configuration will take.
+//
+// HeaderScanProfile records how long the class forwarding header scan
+// takes per header, for finding the headers which dominate the forwarding
+// header generation (candidates for exclusion, see util::DEFAULT_EXCLUDED_DIRS).
+// The forwarding header writers record to a profile passed with
+// configure::ForwardingHeaderOptions::profile. The configure_qtcore_for_*
+// functions pass one if the QT_CARGO_HEADER_SCAN_PROFILE environment
+// variable is set, like the header scan log (util::HEADER_SCAN_LOG_ENV_VAR).
+
+/// The number of recent builds per configuration used for estimates
+pub const TIMING_HISTORY_LENGTH: usize = 5;
+
+/// Build times per configuration, see estimate_build_time
+#[derive(Debug, Default)]
+pub struct BuildTimings {
+    timings_path: PathBuf,
+    entries: BTreeMap<u64, Vec<Duration>>,
+}
+
+impl BuildTimings {
+    /// Loads the build timings from timings_path. Returns empty timings if the file does
+    /// not exist or can't be parsed.
+    pub fn load<P: AsRef<Path>>(timings_path: P) -> BuildTimings {
+        let timings_path = timings_path.as_ref().to_path_buf();
+        let content = std::fs::read_to_string(&timings_path).unwrap_or_default();
+        // Line format: <cache key> <tab> <comma-separated build times in milliseconds>
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let (key, timings) = line.split_once('\t')?;
+                let key = u64::from_str_radix(key, 16).ok()?;
+                let timings = timings
+                    .split(',')
+                    .map(|millis| millis.parse().ok().map(Duration::from_millis))
+                    .collect::<Option<Vec<_>>>()?;
+                Some((key, timings))
+            })
+            .collect();
+        BuildTimings {
+            timings_path,
+            entries,
+        }
+    }
+
+    /// Writes the timings to the path they were loaded from
+    pub fn save(&self) {
+        let content: String = self
+            .entries
+            .iter()
+            .map(|(key, timings)| {
+                let timings: Vec<String> = timings
+                    .iter()
+                    .map(|timing| timing.as_millis().to_string())
+                    .collect();
+                format!("{:016x}\t{}\n", key, timings.join(","))
+            })
+            .collect();
+        if let Some(parent) = self.timings_path.parent() {
+            std::fs::create_dir_all(parent).expect("Unable to create directory");
+        }
+        std::fs::write(&self.timings_path, content).expect("Unable to write file");
+    }
+
+    /// Records the build time of a build with the given configuration. Only the last
+    /// TIMING_HISTORY_LENGTH builds are kept per configuration.
+    pub fn record_build_time(&mut self, qt_configuration: &QtConfiguration, duration: Duration) {
+        let timings = self
+            .entries
+            .entry(qt_configuration.cache_key())
+            .or_default();
+        timings.push(duration);
+        if timings.len() > TIMING_HISTORY_LENGTH {
+            timings.remove(0);
+        }
+    }
+
+    /// Returns the estimated build time for the configuration: the average of the recorded
+    /// build times, or None if no builds with the configuration have been recorded.
+    pub fn estimate_build_time(&self, qt_configuration: &QtConfiguration) -> Option<Duration> {
+        let timings = self.entries.get(&qt_configuration.cache_key())?;
+        match timings.len() {
+            0 => None,
+            count => Some(timings.iter().sum::<Duration>() / count as u32),
+        }
+    }
+}
+
+/// Environment variable which enables the header scan profile: if set, the
+/// configure_qtcore_for_* functions print the slowest headers to scan for classes as build
+/// warnings. The
+/// value is the number of headers to report; DEFAULT_HEADER_SCAN_REPORT_LENGTH if empty.
+pub const HEADER_SCAN_PROFILE_ENV_VAR: &str = "QT_CARGO_HEADER_SCAN_PROFILE";
+
+/// The number of headers reported by HeaderScanProfile::from_env by default
+pub const DEFAULT_HEADER_SCAN_REPORT_LENGTH: usize = 10;
+
+/// Scan times per header, see configure::ForwardingHeaderOptions::profile
+#[derive(Clone, Debug, Default)]
+pub struct HeaderScanProfile {
+    report_length: usize,
+    entries: Vec<(PathBuf, Duration)>,
+}
+
+impl HeaderScanProfile {
+    /// Returns an empty profile which reports the report_length slowest headers
+    pub fn new(report_length: usize) -> HeaderScanProfile {
+        HeaderScanProfile {
+            report_length,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns an empty profile if HEADER_SCAN_PROFILE_ENV_VAR is set, or None
+    pub fn from_env() -> Option<HeaderScanProfile> {
+        let report_length = std::env::var(HEADER_SCAN_PROFILE_ENV_VAR).ok()?;
+        Some(HeaderScanProfile::new(
+            report_length
+                .parse()
+                .unwrap_or(DEFAULT_HEADER_SCAN_REPORT_LENGTH),
+        ))
+    }
+
+    /// Records the scan time of the header at header_path
+    pub fn record<P: AsRef<Path>>(&mut self, header_path: P, duration: Duration) {
+        self.entries
+            .push((header_path.as_ref().to_path_buf(), duration));
+    }
+
+    /// Returns the total scan time of the recorded headers
+    pub fn total(&self) -> Duration {
+        self.entries.iter().map(|(_, duration)| *duration).sum()
+    }
+
+    /// Returns the slowest headers, slowest first, at most report_length of them
+    pub fn slowest(&self) -> Vec<(&Path, Duration)> {
+        let mut entries: Vec<(&Path, Duration)> = self
+            .entries
+            .iter()
+            .map(|(header_path, duration)| (header_path.as_path(), *duration))
+            .collect();
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        entries.truncate(self.report_length);
+        entries
+    }
+
+    /// Returns a report of the slowest headers, one "<milliseconds> ms <path>" line each,
+    /// after a summary line
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "Header scan: {} headers in {:.1} ms, slowest:\n",
+            self.entries.len(),
+            self.total().as_secs_f64() * 1000.0
+        );
+        for (header_path, duration) in self.slowest() {
+            report.push_str(&format!(
+                "{:8.1} ms {}\n",
+                duration.as_secs_f64() * 1000.0,
+                header_path.display()
+            ));
+        }
+        report
+    }
+
+    /// Prints the report as "cargo:warning" lines, which cargo shows for build scripts
+    pub fn print_report(&self) {
+        for line in self.report().lines() {
+            println!("cargo:warning={}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod qt_cargo_base_timing_tests {
+    use super::*;
+    use crate::{configure, util};
+
+    #[test]
+    fn test_header_scan_profile() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-timing-test").unwrap();
+        let source = temp.path().join("corelib");
+        std::fs::create_dir_all(source.join("kernel")).unwrap();
+        for name in ["qsmall.h", "qtiny.h", "qshort.h"] {
+            std::fs::write(
+                source.join("kernel").join(name),
+                "class Q_CORE_EXPORT QSmall;\n",
+            )
+            .unwrap();
+        }
+        let mut large_header = String::new();
+        for i in 0..20000 {
+            large_header.push_str(&format!(
+                "class Q_CORE_EXPORT QLarge{} {{ int value; }};\n",
+                i
+            ));
+        }
+        std::fs::write(source.join("kernel/qlarge.h"), large_header).unwrap();
+
+        let mut profile = HeaderScanProfile::new(2);
+        configure::write_all_forwarding_headers_with_options(
+            &source,
+            temp.path().join("QtCore"),
+            configure::ForwardingHeaderOptions {
+                profile: Some(&mut profile),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let slowest = profile.slowest();
+        assert_eq!(slowest.len(), 2);
+        assert_eq!(slowest[0].0, source.join("kernel/qlarge.h"));
+        assert!(profile.total() >= slowest[0].1);
+        assert!(profile.report().starts_with("Header scan: 4 headers in "));
+    }
+
+    #[test]
+    fn test_estimate_build_time() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-timing-test").unwrap();
+        let timings_path = temp.path().join("timings/build_timings.txt");
+        let mut config = configure::QtConfiguration::new();
+        configure::set_default_configuration(&mut config);
+        let mut other_config = config.clone();
+        other_config.set_feature("thread", false);
+
+        let mut timings = BuildTimings::load(&timings_path);
+        assert_eq!(timings.estimate_build_time(&config), None);
+        for seconds in [100, 60, 10, 20, 30, 40, 50] {
+            timings.record_build_time(&config, Duration::from_secs(seconds));
+        }
+        timings.record_build_time(&other_config, Duration::from_millis(1500));
+        timings.save();
+
+        // The estimate is the average of the last five builds, per configuration
+        let timings = BuildTimings::load(&timings_path);
+        assert_eq!(
+            timings.estimate_build_time(&config),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            timings.estimate_build_time(&other_config),
+            Some(Duration::from_millis(1500))
+        );
+    }
+}
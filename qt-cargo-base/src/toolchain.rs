@@ -0,0 +1,190 @@
+use std::path::PathBuf;
+
+use crate::util::{self, ToolchainError};
+
+// Compiler workarounds
+//
+// Qt builds cleanly with the compilers it is tested with, but newer
+// compilers add warnings which trigger on Qt code, and some compilers
+// need flags to accept it at all. detect_compiler finds the family and
+// version of the builder's compiler (from the predefined macros, so it
+// works with wrapper scripts and cross compilers), and workaround_flags
+// returns the known-needed flags for it from COMPILER_WORKAROUNDS.
+//
+// The workarounds are applied by apply_build_options, see
+// QtBuildOptions::compiler_workarounds for overriding them.
+
+/// Compiler family, as detected by detect_compiler
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompilerFamily {
+    Gcc,
+    Clang,
+    /// MSVC, and clang-cl
+    Msvc,
+    Other,
+}
+
+/// The family and (major, minor) version of a compiler. The MSVC version is the
+/// compiler version (19.x), not the Visual Studio version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompilerInfo {
+    pub family: CompilerFamily,
+    pub version: (u32, u32),
+}
+
+/// Known-needed workaround flags, as (family, minimum version, flag)
+pub const COMPILER_WORKAROUNDS: &[(CompilerFamily, (u32, u32), &str)] = &[
+    // Qt classes with user-declared copy constructors and defaulted assignment
+    (CompilerFamily::Gcc, (9, 0), "-Wno-deprecated-copy"),
+    // False positives for functions returning references, e.g. QStringView
+    (CompilerFamily::Gcc, (13, 0), "-Wno-dangling-reference"),
+    (CompilerFamily::Clang, (10, 0), "-Wno-deprecated-copy"),
+    // Qt 6 requires standard conformance mode and a correct __cplusplus
+    (CompilerFamily::Msvc, (19, 0), "/permissive-"),
+    (CompilerFamily::Msvc, (19, 14), "/Zc:__cplusplus"),
+    // Qt sources are UTF-8 without a BOM
+    (CompilerFamily::Msvc, (19, 0), "/utf-8"),
+];
+
+const COMPILER_VERSION_SOURCE: &str = r#"
+#if defined(_MSC_VER)
+qt_cargo_compiler_version msvc _MSC_VER
+#elif defined(__clang__)
+qt_cargo_compiler_version clang __clang_major__ __clang_minor__
+#elif defined(__GNUC__)
+qt_cargo_compiler_version gcc __GNUC__ __GNUC_MINOR__
+#else
+qt_cargo_compiler_version other
+#endif
+"#;
+
+/// Detects the family and version of the builder's compiler, by preprocessing a source
+/// file with the builder's compiler and flags.
+pub fn detect_compiler(builder: &cc::Build) -> Result<CompilerInfo, ToolchainError> {
+    let compiler = builder
+        .try_get_compiler()
+        .map_err(|e| ToolchainError::CompilerNotFound {
+            compiler: PathBuf::new(),
+            message: e.to_string(),
+        })?;
+    let compiler_path = compiler.path().to_path_buf();
+    let io_error = |e: std::io::Error| ToolchainError::CompilerNotFound {
+        compiler: compiler_path.clone(),
+        message: e.to_string(),
+    };
+
+    let temp = util::scratch_temp_dir("qt-cargo-base-detect-compiler").map_err(io_error)?;
+    let source_path = temp.path().join("compiler_version.cpp");
+    std::fs::write(&source_path, COMPILER_VERSION_SOURCE).map_err(io_error)?;
+    let mut command = compiler.to_command();
+    match compiler.is_like_msvc() {
+        true => command.arg("/EP").arg(&source_path),
+        false => command.arg("-E").arg(&source_path),
+    };
+    let output = command.output().map_err(io_error)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("qt_cargo_compiler_version"))
+        .filter(|_| output.status.success())
+        .ok_or_else(|| ToolchainError::CompileFailed {
+            compiler: compiler_path.clone(),
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })?
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let number = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|field| field.parse::<u32>().ok())
+            .unwrap_or(0)
+    };
+    Ok(match fields.first().copied() {
+        Some("msvc") => CompilerInfo {
+            family: CompilerFamily::Msvc,
+            version: (number(1) / 100, number(1) % 100),
+        },
+        Some("clang") => CompilerInfo {
+            family: CompilerFamily::Clang,
+            version: (number(1), number(2)),
+        },
+        Some("gcc") => CompilerInfo {
+            family: CompilerFamily::Gcc,
+            version: (number(1), number(2)),
+        },
+        _ => CompilerInfo {
+            family: CompilerFamily::Other,
+            version: (0, 0),
+        },
+    })
+}
+
+/// Returns the workaround flags for the compiler, see COMPILER_WORKAROUNDS
+pub fn workaround_flags(compiler: &CompilerInfo) -> Vec<&'static str> {
+    COMPILER_WORKAROUNDS
+        .iter()
+        .filter(|(family, minimum_version, _)| {
+            *family == compiler.family && compiler.version >= *minimum_version
+        })
+        .map(|(_, _, flag)| *flag)
+        .collect()
+}
+
+/// Adds the workaround flags for the builder's compiler to the builder. Nothing is added
+/// if the compiler can't be detected. Returns the added flags.
+pub fn apply_compiler_workarounds(builder: &mut cc::Build) -> Vec<&'static str> {
+    let flags = detect_compiler(builder)
+        .map(|compiler| workaround_flags(&compiler))
+        .unwrap_or_default();
+    for flag in &flags {
+        builder.flag(flag);
+    }
+    flags
+}
+
+#[cfg(test)]
+mod qt_cargo_base_toolchain_tests {
+    use super::*;
+
+    #[test]
+    fn test_workaround_flags() {
+        let gcc_8 = CompilerInfo {
+            family: CompilerFamily::Gcc,
+            version: (8, 5),
+        };
+        let gcc_13 = CompilerInfo {
+            family: CompilerFamily::Gcc,
+            version: (13, 1),
+        };
+        let msvc_2022 = CompilerInfo {
+            family: CompilerFamily::Msvc,
+            version: (19, 36),
+        };
+        assert!(workaround_flags(&gcc_8).is_empty());
+        assert_eq!(
+            workaround_flags(&gcc_13),
+            ["-Wno-deprecated-copy", "-Wno-dangling-reference"]
+        );
+        assert_eq!(
+            workaround_flags(&msvc_2022),
+            ["/permissive-", "/Zc:__cplusplus", "/utf-8"]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_apply_compiler_workarounds() {
+        let (mut builder, _build_temp) = util::test_builder();
+        let compiler = detect_compiler(&builder).unwrap();
+        assert_ne!(compiler.family, CompilerFamily::Other);
+        assert!(compiler.version.0 > 0);
+
+        let flags = apply_compiler_workarounds(&mut builder);
+        assert_eq!(flags, workaround_flags(&compiler));
+        let args = builder.get_compiler().args().to_vec();
+        for flag in flags {
+            assert!(args.iter().any(|arg| arg == flag), "{}", flag);
+        }
+        util::try_compile(&builder, "int main() { return 0; }\n", &[]).unwrap();
+    }
+}
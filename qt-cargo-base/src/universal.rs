@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Universal (multi-architecture) archives for Apple platforms
+//
+// A universal archive is created by compiling the sources once per
+// architecture, each with its own build directory, and then combining
+// the per-architecture archives using "lipo". The Qt configuration is
+// written per architecture as well, since some config defines (for
+// example the QT_COMPILER_SUPPORTS_* SIMD defines) differ between them.
+
+/// Returns the Apple target triple for the given architecture name, as accepted by
+/// e.g. "clang -arch" ("arm64", "x86_64").
+pub fn apple_target_triple(architecture: &str) -> String {
+    match architecture {
+        "arm64" | "aarch64" => "aarch64-apple-darwin".to_string(),
+        arch => format!("{}-apple-darwin", arch),
+    }
+}
+
+/// Compiles a universal static archive "lib<name>.a" in out_dir, containing code for each
+/// of the given architectures. configure_arch is called once per architecture with a new
+/// cc::Build targeting that architecture, the target triple, and a build directory for the
+/// architecture. It should configure the builder and write the Qt configuration for the
+/// architecture to the build directory (see write_default_qt_configuration), and add
+/// source files.
+///
+/// Emits cargo link directives for the universal archive. Returns the archive path.
+pub fn compile_universal_archive<F>(
+    name: &str,
+    out_dir: &Path,
+    architectures: &[&str],
+    mut configure_arch: F,
+) -> PathBuf
+where
+    F: FnMut(&mut cc::Build, &str, &Path),
+{
+    let host =
+        std::env::var("HOST").unwrap_or_else(|_| apple_target_triple(std::env::consts::ARCH));
+
+    let mut arch_archives = Vec::new();
+    for architecture in architectures {
+        let target = apple_target_triple(architecture);
+        let arch_build_path = out_dir.join(architecture);
+        std::fs::create_dir_all(&arch_build_path).expect("Unable to create directory");
+
+        let mut builder = cc::Build::new();
+        builder.host(&host).target(&target);
+        configure_arch(&mut builder, &target, &arch_build_path);
+
+        // Always compile to the per-architecture directory, also when OUT_DIR is set.
+        let arch_archive_name = format!("{}_{}", name, architecture);
+        builder
+            .out_dir(&arch_build_path)
+            .cargo_metadata(false)
+            .compile(&arch_archive_name);
+        arch_archives.push(arch_build_path.join(format!("lib{}.a", arch_archive_name)));
+    }
+
+    let universal_archive = out_dir.join(format!("lib{}.a", name));
+    let status = Command::new("lipo")
+        .arg("-create")
+        .args(&arch_archives)
+        .arg("-output")
+        .arg(&universal_archive)
+        .status()
+        .expect("Unable to run lipo");
+    if !status.success() {
+        panic!("lipo failed to create {:?}", universal_archive);
+    }
+
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+    println!("cargo:rustc-link-lib=static={}", name);
+
+    universal_archive
+}
+
+#[cfg(test)]
+mod qt_cargo_base_universal_tests {
+    use super::*;
+
+    #[test]
+    fn test_apple_target_triple() {
+        assert_eq!(apple_target_triple("arm64"), "aarch64-apple-darwin");
+        assert_eq!(apple_target_triple("x86_64"), "x86_64-apple-darwin");
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_compile_universal_archive() {
+        let temp = crate::util::scratch_temp_dir("qt-cargo-base-universal-test").unwrap();
+        let source = temp.path().join("universal.cpp");
+        std::fs::write(&source, "int universal() { return 42; }\n").unwrap();
+
+        let archive = compile_universal_archive(
+            "universal",
+            temp.path(),
+            &["x86_64", "arm64"],
+            |builder, _, _| {
+                builder.cpp(true).opt_level(0).file(&source);
+            },
+        );
+
+        let output = Command::new("lipo")
+            .arg("-info")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let info = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            info.contains("x86_64") && info.contains("arm64"),
+            "{}",
+            info
+        );
+    }
+}
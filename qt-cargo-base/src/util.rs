@@ -1,9 +1,13 @@
 use std::{
+    collections::BTreeSet,
     ffi::OsStr,
-    path::{Path, PathBuf},
+    fmt, io,
+    path::{Component, Path, PathBuf},
 };
 use walkdir::WalkDir;
 
+use crate::error::QtBuildError;
+
 // Returns an iterator to all files with a certain extention under the
 // given path
 pub fn glob_files<P: AsRef<Path>>(
@@ -17,6 +21,180 @@ pub fn glob_files<P: AsRef<Path>>(
         .map(|e| e.path().to_owned())
 }
 
+/// Directory names which are skipped when scanning source trees for headers: in-source
+/// build directories, version control metadata and tests.
+pub const DEFAULT_EXCLUDED_DIRS: &[&str] = &["build", ".git", "tests"];
+
+/// Returns the files with the given extension under path, like glob_files, skipping
+/// directories (below path) whose name is in excluded_dirs.
+pub fn glob_files_excluding<'a, P: AsRef<Path>>(
+    path: P,
+    file_ext: &'a OsStr,
+    excluded_dirs: &'a [&'a str],
+) -> impl Iterator<Item = PathBuf> + 'a {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_entry(move |e| {
+            e.depth() == 0
+                || !e.file_type().is_dir()
+                || !excluded_dirs
+                    .iter()
+                    .any(|excluded| e.file_name() == OsStr::new(excluded))
+        })
+        .filter_map(|e| e.ok())
+        .filter(move |e| e.path().extension() == Some(file_ext))
+        .map(|e| e.path().to_owned())
+}
+
+/// Environment variable which enables the header scan log: if set to a file path, the
+/// configure_qtcore_for_* functions append a trace of the paths visited by the forwarding
+/// header scan to the file, see configure::ForwardingHeaderOptions::scan_log.
+pub const HEADER_SCAN_LOG_ENV_VAR: &str = "QT_CARGO_HEADER_SCAN_LOG";
+
+/// Returns the header scan log path given by HEADER_SCAN_LOG_ENV_VAR, or None if not set
+pub fn header_scan_log_from_env() -> Option<PathBuf> {
+    std::env::var_os(HEADER_SCAN_LOG_ENV_VAR)
+        .filter(|log_path| !log_path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns a trace of the scan done by glob_files_excluding, for debugging missing headers.
+/// Each line contains a visited path (relative to path), its extension ("-" if none) and
+/// the result: "match", "skipped: extension", "skipped: excluded directory" (the content
+/// of which is not visited) or "directory". The lines are sorted, so that traces can be
+/// compared with diff.
+pub fn trace_glob_files_excluding<P: AsRef<Path>>(
+    path: P,
+    file_ext: &OsStr,
+    excluded_dirs: &[&str],
+) -> Vec<String> {
+    let path = path.as_ref();
+    let is_excluded = |e: &walkdir::DirEntry| {
+        e.file_type().is_dir()
+            && excluded_dirs
+                .iter()
+                .any(|excluded| e.file_name() == OsStr::new(excluded))
+    };
+    let mut lines = Vec::new();
+    let mut entries = WalkDir::new(path).min_depth(1).into_iter();
+    while let Some(entry) = entries.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let result = if is_excluded(&entry) {
+            entries.skip_current_dir();
+            "skipped: excluded directory"
+        } else if entry.file_type().is_dir() {
+            "directory"
+        } else if entry.path().extension() == Some(file_ext) {
+            "match"
+        } else {
+            "skipped: extension"
+        };
+        let extension = entry.path().extension().unwrap_or(OsStr::new("-"));
+        lines.push(format!(
+            "{}\t{}\t{}",
+            entry.path().strip_prefix(path).unwrap().display(),
+            extension.to_string_lossy(),
+            result
+        ));
+    }
+    lines.sort();
+    lines
+}
+
+/// Appends a trace of the scan of path (see trace_glob_files_excluding) to the file at
+/// log_path
+pub fn log_header_scan<P, Q>(
+    path: P,
+    file_ext: &OsStr,
+    excluded_dirs: &[&str],
+    log_path: Q,
+) -> Result<(), QtBuildError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let log_path = log_path.as_ref();
+    let mut log = format!("# Header scan of {}\n", path.as_ref().display());
+    for line in trace_glob_files_excluding(path, file_ext, excluded_dirs) {
+        log.push_str(&line);
+        log.push('\n');
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .map_err(QtBuildError::config_write(log_path))?;
+    io::Write::write_all(&mut file, log.as_bytes()).map_err(QtBuildError::config_write(log_path))
+}
+
+// Resolves "." and ".." components without accessing the file system
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn canonicalize_retrying(path: &Path) -> io::Result<PathBuf> {
+    let mut attempts = 0;
+    loop {
+        match path.canonicalize() {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted && attempts < 3 => attempts += 1,
+            result => return result,
+        }
+    }
+}
+
+/// Returns an absolute, normalized form of path. Relative paths are resolved against
+/// std::env::current_dir. Unlike Path::canonicalize this also works for paths which
+/// do not (yet) exist: the longest existing ancestor is canonicalized, and the remaining
+/// components are normalized lexically. Interrupted file system calls are retried.
+pub fn safe_canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    let absolute_path = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => std::env::current_dir()?.join(path),
+    };
+    let normalized_path = normalize_lexically(&absolute_path);
+
+    let mut existing_path = normalized_path.as_path();
+    let mut missing_components = Vec::new();
+    loop {
+        match canonicalize_retrying(existing_path) {
+            Ok(canonical_path) => {
+                return Ok(missing_components
+                    .iter()
+                    .rev()
+                    .fold(canonical_path, |path, component| path.join(component)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                match (existing_path.parent(), existing_path.file_name()) {
+                    (Some(parent), Some(file_name)) => {
+                        missing_components.push(file_name.to_os_string());
+                        existing_path = parent;
+                    }
+                    _ => return Ok(normalized_path),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub fn qt_src_path() -> PathBuf {
     // Test and build scripts expects to find the Qt sources in the main vendored-qt workspace,
     // which this crate should be a member of. The path would normally be "../qt-src".
@@ -39,3 +217,585 @@ pub fn qt_src_path() -> PathBuf {
     }
     src_path
 }
+
+/// Returns a hash of the content (64-bit FNV-1a). The hash is stable across builds
+/// and Rust versions, and can be stored in cache files.
+pub fn content_hash(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Environment variable which selects the scratch directory for temporary build output
+pub const TMPDIR_ENV_VAR: &str = "QT_CARGO_TMPDIR";
+
+fn scratch_dir_from(tmpdir: Option<std::ffi::OsString>) -> PathBuf {
+    match tmpdir {
+        Some(tmpdir) if !tmpdir.is_empty() => {
+            safe_canonicalize(PathBuf::from(tmpdir)).expect("Unable to resolve scratch directory")
+        }
+        _ => std::env::temp_dir(),
+    }
+}
+
+/// Prints message as a "cargo:warning" line when called from a build script (OUT_DIR is
+/// set), where cargo shows it to the user. Does nothing in other contexts such as tests.
+pub(crate) fn build_script_warning(message: &str) {
+    if std::env::var_os("OUT_DIR").is_some() {
+        println!("cargo:warning={}", message);
+    }
+}
+
+/// Returns the scratch directory for temporary build output: the QT_CARGO_TMPDIR
+/// environment variable if set, or the system temp directory otherwise. Set
+/// QT_CARGO_TMPDIR if the system temp directory is small or slow, for example a tmpfs.
+pub fn scratch_dir() -> PathBuf {
+    scratch_dir_from(std::env::var_os(TMPDIR_ENV_VAR))
+}
+
+/// Creates a new temporary directory in scratch_dir(), which is removed when the
+/// returned TempDir is dropped. The scratch directory is created if needed.
+pub fn scratch_temp_dir(prefix: &str) -> io::Result<tempdir::TempDir> {
+    create_temp_dir_in(&scratch_dir(), prefix)
+}
+
+/// Returns a C++ builder for the host without optimization, which writes its build
+/// output to the returned temporary directory. For tests.
+#[cfg(test)]
+pub(crate) fn test_builder() -> (cc::Build, tempdir::TempDir) {
+    let temp = scratch_temp_dir("qt-cargo-base-test").expect("unable to create temp dir");
+    let mut builder = cc::Build::new();
+    builder
+        .cpp(true)
+        .host(&crate::target::default_triple())
+        .target(&crate::target::default_triple())
+        .opt_level(0)
+        .out_dir(temp.path());
+    (builder, temp)
+}
+
+fn create_temp_dir_in(scratch_dir: &Path, prefix: &str) -> io::Result<tempdir::TempDir> {
+    std::fs::create_dir_all(scratch_dir)?;
+    tempdir::TempDir::new_in(scratch_dir, prefix)
+}
+
+#[derive(Debug)]
+pub enum ToolchainError {
+    /// The compiler could not be found or run
+    CompilerNotFound { compiler: PathBuf, message: String },
+    /// The compiler ran, but failed to compile the test source
+    CompileFailed { compiler: PathBuf, message: String },
+}
+
+impl fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainError::CompilerNotFound { compiler, message } => {
+                write!(f, "unable to run compiler {:?}: {}", compiler, message)
+            }
+            ToolchainError::CompileFailed { compiler, message } => {
+                write!(f, "compiler {:?} failed: {}", compiler, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ToolchainError {}
+
+/// Returns the path of the given compiler. Names without a directory (e.g. "g++-12") are
+/// looked up in PATH; paths are returned if the file exists.
+pub fn find_compiler<P: AsRef<Path>>(compiler: P) -> Result<PathBuf, ToolchainError> {
+    let compiler = compiler.as_ref();
+    let not_found = |message: &str| ToolchainError::CompilerNotFound {
+        compiler: compiler.to_path_buf(),
+        message: message.to_string(),
+    };
+    if compiler.components().count() > 1 {
+        return match compiler.is_file() {
+            true => Ok(compiler.to_path_buf()),
+            false => Err(not_found("no such file")),
+        };
+    }
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .flat_map(|dir| {
+            let candidate = dir.join(compiler);
+            [candidate.with_extension("exe"), candidate]
+        })
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| not_found("not found in PATH"))
+}
+
+/// Compiles (without linking) the given C++ source using the builder's compiler and flags,
+/// with extra_args added to the compiler command line.
+pub fn try_compile(
+    builder: &cc::Build,
+    source: &str,
+    extra_args: &[&str],
+) -> Result<(), ToolchainError> {
+    let compiler = builder
+        .try_get_compiler()
+        .map_err(|e| ToolchainError::CompilerNotFound {
+            compiler: PathBuf::new(),
+            message: e.to_string(),
+        })?;
+    let compiler_path = compiler.path().to_path_buf();
+    let io_error = |e: io::Error| ToolchainError::CompilerNotFound {
+        compiler: compiler_path.clone(),
+        message: e.to_string(),
+    };
+
+    let temp = scratch_temp_dir("qt-cargo-base-try-compile").map_err(io_error)?;
+    let source_path = temp.path().join("try_compile.cpp");
+    let object_path = temp.path().join("try_compile.o");
+    std::fs::write(&source_path, source).map_err(io_error)?;
+
+    let mut command = compiler.to_command();
+    command.args(extra_args);
+    if compiler.is_like_msvc() {
+        command
+            .arg("/c")
+            .arg(&source_path)
+            .arg(format!("/Fo{}", object_path.display()));
+    } else {
+        command
+            .arg("-c")
+            .arg(&source_path)
+            .arg("-o")
+            .arg(&object_path);
+    }
+    let output = command.output().map_err(io_error)?;
+    if !output.status.success() {
+        return Err(ToolchainError::CompileFailed {
+            compiler: compiler_path,
+            message: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+const CXX17_TEST_SOURCE: &str = r#"
+#if __cplusplus < 201703L && (!defined(_MSVC_LANG) || _MSVC_LANG < 201703L)
+#error "C++17 is required"
+#endif
+#include <optional>
+#include <string_view>
+
+template <typename T>
+constexpr int qt_cargo_cxx17_test(T value)
+{
+    if constexpr (sizeof(T) > 1)
+        return std::optional<T>(value).value_or(0);
+    else
+        return std::string_view("qt").size();
+}
+"#;
+
+/// Checks that the builder's compiler can compile C++17, which Qt 6 requires. Uses the
+/// builder's flags, and adds the C++17 standard flag if no standard is selected.
+pub fn check_cxx17_support(builder: &cc::Build) -> Result<(), ToolchainError> {
+    let (selects_standard, is_like_msvc) = match builder.try_get_compiler() {
+        Ok(compiler) => (
+            compiler.args().iter().any(|arg| {
+                let arg = arg.to_string_lossy();
+                arg.starts_with("-std=") || arg.starts_with("/std:")
+            }),
+            compiler.is_like_msvc(),
+        ),
+        Err(_) => (false, false),
+    };
+    let standard_args: &[&str] = match (selects_standard, is_like_msvc) {
+        (true, _) => &[],
+        (false, true) => &["/std:c++17"],
+        (false, false) => &["-std=c++17"],
+    };
+    try_compile(builder, CXX17_TEST_SOURCE, standard_args)
+}
+
+/// Checks that <QtCore/qconfig.h> resolves to a qconfig.h written by this crate with the
+/// builder's include paths, and not to another qconfig.h (for example from a vendored or
+/// installed Qt) found earlier in the include path order. Compiles a probe which checks
+/// for configure::QCONFIG_MARKER.
+pub fn check_generated_qconfig(builder: &cc::Build) -> Result<(), ToolchainError> {
+    let probe = format!(
+        "#include <QtCore/qconfig.h>\n\
+         #ifndef {}\n\
+         #error \"QtCore/qconfig.h is not the generated qconfig.h, check the include path order\"\n\
+         #endif\n",
+        crate::configure::QCONFIG_MARKER
+    );
+    try_compile(builder, &probe, &[])
+}
+
+/// The difference between a generated output tree and a golden (reference) tree.
+/// All paths are relative to the tree roots, and sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OutputDiff {
+    /// Files present in the generated tree but not in the golden tree
+    pub added: Vec<PathBuf>,
+    /// Files present in the golden tree but not in the generated tree
+    pub removed: Vec<PathBuf>,
+    /// Files present in both trees, with differing content
+    pub changed: Vec<PathBuf>,
+}
+
+impl OutputDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Returns the set of (relative) paths for all files under root
+fn relative_file_paths(root: &Path) -> BTreeSet<PathBuf> {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.path().strip_prefix(root).ok().map(|p| p.to_path_buf()))
+        .collect()
+}
+
+/// Compares the generated output tree against a golden tree, for example a committed
+/// copy of the forwarding and config headers. Files are compared by content only;
+/// file metadata such as mtime is ignored.
+pub fn diff_against_golden(generated: &Path, golden: &Path) -> OutputDiff {
+    let generated_files = relative_file_paths(generated);
+    let golden_files = relative_file_paths(golden);
+
+    let changed = generated_files
+        .intersection(&golden_files)
+        .filter(|path| {
+            let generated_content = std::fs::read(generated.join(path)).ok();
+            let golden_content = std::fs::read(golden.join(path)).ok();
+            generated_content != golden_content
+        })
+        .cloned()
+        .collect();
+
+    OutputDiff {
+        added: generated_files.difference(&golden_files).cloned().collect(),
+        removed: golden_files.difference(&generated_files).cloned().collect(),
+        changed,
+    }
+}
+
+// Returns the included header name for an "#include" line, or None for other lines.
+fn parse_include_line(line: &str) -> Option<&str> {
+    let directive = line.trim_start().strip_prefix('#')?.trim_start();
+    let target = directive.strip_prefix("include")?.trim();
+    let (open, close) = match target.chars().next()? {
+        '"' => ('"', '"'),
+        '<' => ('<', '>'),
+        _ => return None,
+    };
+    let target = target.strip_prefix(open)?;
+    target.find(close).map(|end| &target[..end])
+}
+
+// Returns true for includes which look like Qt includes, for example "qstring.h",
+// <QString>, or <QtCore/qglobal.h>. System and standard library includes are
+// not expected to be found in the generated include directories.
+fn is_qt_include(include: &str) -> bool {
+    let file_name = include.rsplit('/').next().unwrap_or(include);
+    include.starts_with("Qt")
+        || file_name.starts_with('Q')
+        || (file_name.starts_with('q') && file_name.ends_with(".h"))
+}
+
+/// Scans the headers in the given (generated) include directories, and the headers they
+/// include, and returns (header, include) pairs for Qt includes which can't be resolved
+/// either relative to the including header or within the include directories.
+///
+/// This is a static check over the generated output and does not run the preprocessor:
+/// includes inside conditional blocks are checked as well, which may report includes
+/// which would not be seen by the compiler.
+pub fn find_unresolved_includes(include_dirs: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    let resolve = |including_header: &Path, include: &str| -> Option<PathBuf> {
+        let local = including_header.parent().map(|dir| dir.join(include));
+        local
+            .into_iter()
+            .chain(include_dirs.iter().map(|dir| dir.join(include)))
+            .find(|path| path.is_file())
+            .and_then(|path| path.canonicalize().ok())
+    };
+
+    let mut pending: Vec<PathBuf> = include_dirs
+        .iter()
+        .flat_map(|dir| WalkDir::new(dir).into_iter().filter_map(|e| e.ok()))
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.path().canonicalize().ok())
+        .collect();
+    let mut visited = BTreeSet::new();
+    let mut unresolved = BTreeSet::new();
+
+    while let Some(header) = pending.pop() {
+        if !visited.insert(header.clone()) {
+            continue;
+        }
+        let content = match std::fs::read(&header) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(_) => continue,
+        };
+        for include in content.lines().filter_map(parse_include_line) {
+            match resolve(&header, include) {
+                Some(resolved) => pending.push(resolved),
+                None if is_qt_include(include) => {
+                    unresolved.insert((header.clone(), include.to_string()));
+                }
+                None => {}
+            }
+        }
+    }
+
+    unresolved.into_iter().collect()
+}
+
+/// Sets the Unix permission mode bits on all files under path, for example 0o664 for
+/// group-writable files. Directories (including path itself) get the same mode plus the
+/// execute bit wherever the read bit is set, e.g. 0o775. Does nothing on non-Unix platforms.
+pub fn set_permissions_recursive<P: AsRef<Path>>(path: P, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let directory_mode = mode | ((mode & 0o444) >> 2);
+        for entry in WalkDir::new(path) {
+            let entry = entry?;
+            let entry_mode = match entry.file_type().is_dir() {
+                true => directory_mode,
+                false => mode,
+            };
+            std::fs::set_permissions(entry.path(), std::fs::Permissions::from_mode(entry_mode))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+// Escapes a path for use in a Make-style depfile
+fn escape_depfile_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('$', "$$")
+        .replace('#', "\\#")
+        .replace(' ', "\\ ")
+}
+
+/// Writes a Make-style depfile to output, listing deps as the dependencies. The target
+/// is the output path without the ".d" extension; for example "qt_headers.d" describes
+/// "qt_headers". Build systems like Ninja and Bazel can use the depfile to re-run header
+/// generation when any of the source headers change (see forwarding_header_dependencies).
+pub fn write_depfile(output: &Path, deps: &[PathBuf]) -> std::io::Result<()> {
+    let target = match output.extension() {
+        Some(extension) if extension == "d" => output.with_extension(""),
+        _ => output.to_path_buf(),
+    };
+    let mut content = format!("{}:", escape_depfile_path(&target));
+    for dep in deps {
+        content.push_str(" \\\n  ");
+        content.push_str(&escape_depfile_path(dep));
+    }
+    content.push('\n');
+    std::fs::write(output, content)
+}
+
+/// Returns the (sorted) headers read when generating forwarding headers from source_path.
+pub fn forwarding_header_dependencies<P: AsRef<Path>>(source_path: P) -> Vec<PathBuf> {
+    let mut headers: Vec<_> = glob_files(source_path, OsStr::new("h")).collect();
+    headers.sort();
+    headers
+}
+
+#[cfg(test)]
+mod qt_cargo_base_util_tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_against_golden() {
+        let generated = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let golden = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let write = |root: &Path, name: &str, content: &str| {
+            std::fs::write(root.join(name), content).unwrap();
+        };
+        write(generated.path(), "QString", "#include \"qstring.h\"\n");
+        write(golden.path(), "QString", "#include \"qstring.h\"\n");
+        write(
+            generated.path(),
+            "QObject",
+            "#include \"kernel/qobject.h\"\n",
+        );
+        write(golden.path(), "QObject", "#include \"qobject.h\"\n");
+        write(generated.path(), "QNew", "");
+        write(golden.path(), "QOld", "");
+
+        let diff = diff_against_golden(generated.path(), golden.path());
+        assert_eq!(diff.added, vec![PathBuf::from("QNew")]);
+        assert_eq!(diff.removed, vec![PathBuf::from("QOld")]);
+        assert_eq!(diff.changed, vec![PathBuf::from("QObject")]);
+        assert!(diff_against_golden(golden.path(), golden.path()).is_empty());
+    }
+
+    #[test]
+    fn test_find_unresolved_includes() {
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let generated = temp.path().join("generated");
+        let source = temp.path().join("source");
+        std::fs::create_dir_all(&generated).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+
+        std::fs::write(generated.join("QFoo"), "#include \"../source/qfoo.h\"\n").unwrap();
+        std::fs::write(generated.join("qbar.h"), "#include \"../source/qbar.h\"\n").unwrap();
+        std::fs::write(
+            source.join("qfoo.h"),
+            "#include <vector>\n#include \"qbar.h\"\n#  include <QtCore/qmissing.h>\n",
+        )
+        .unwrap();
+        std::fs::write(source.join("qbar.h"), "#include <qbar.h>\n").unwrap();
+
+        let unresolved = find_unresolved_includes(&[generated]);
+        let expected_header = source.join("qfoo.h").canonicalize().unwrap();
+        assert_eq!(
+            unresolved,
+            vec![(expected_header, "QtCore/qmissing.h".to_string())]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_set_permissions_recursive() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let directory = temp.path().join("QtCore");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("qconfig.h"), "").unwrap();
+
+        set_permissions_recursive(temp.path(), 0o664).unwrap();
+
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&directory.join("qconfig.h")), 0o664);
+        assert_eq!(mode(&directory), 0o775);
+    }
+
+    #[test]
+    fn test_write_depfile() {
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let depfile = temp.path().join("qt_headers.d");
+        let deps = vec![
+            PathBuf::from("/qt/corelib/qstring.h"),
+            PathBuf::from("/my qt/corelib/qobject.h"),
+        ];
+        write_depfile(&depfile, &deps).unwrap();
+
+        let expected = format!(
+            "{}: \\\n  /qt/corelib/qstring.h \\\n  /my\\ qt/corelib/qobject.h\n",
+            temp.path().join("qt_headers").display()
+        );
+        assert_eq!(std::fs::read_to_string(&depfile).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_check_cxx17_support() {
+        let (mut builder, _build_temp) = test_builder();
+        assert!(check_cxx17_support(&builder).is_ok());
+
+        builder.compiler("/nonexistent/qt-cargo-c++");
+        match check_cxx17_support(&builder) {
+            Err(error @ ToolchainError::CompilerNotFound { .. }) => {
+                assert!(error.to_string().contains("/nonexistent/qt-cargo-c++"))
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_check_generated_qconfig() {
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let generated = temp.path().join("generated");
+        let mut config = crate::configure::QtConfiguration::new();
+        crate::configure::set_default_configuration(&mut config);
+        let no_qt_path: Option<&str> = None;
+        crate::configure::write_configuration(&config, &generated, no_qt_path).unwrap();
+        let decoy = temp.path().join("decoy");
+        std::fs::create_dir_all(decoy.join("QtCore")).unwrap();
+        std::fs::write(decoy.join("QtCore/qconfig.h"), "#define QT_DECOY 1\n").unwrap();
+
+        let builder = |include_paths: &[&Path]| {
+            let (mut builder, build_temp) = test_builder();
+            builder.includes(include_paths);
+            (builder, build_temp)
+        };
+        assert!(check_generated_qconfig(&builder(&[&generated, &decoy]).0).is_ok());
+        match check_generated_qconfig(&builder(&[&decoy, &generated]).0) {
+            Err(ToolchainError::CompileFailed { message, .. }) => {
+                assert!(
+                    message.contains("check the include path order"),
+                    "{}",
+                    message
+                )
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_scratch_dir() {
+        assert_eq!(scratch_dir_from(None), std::env::temp_dir());
+
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let scratch = temp.path().join("scratch");
+        assert_eq!(
+            scratch_dir_from(Some(scratch.clone().into_os_string())),
+            safe_canonicalize(&scratch).unwrap()
+        );
+        let scratch_temp = create_temp_dir_in(&scratch, "qt-cargo-base-util-test").unwrap();
+        std::fs::write(scratch_temp.path().join("qconfig.h"), "").unwrap();
+        assert!(scratch_temp.path().starts_with(&scratch));
+        assert!(scratch.read_dir().unwrap().next().is_some());
+    }
+
+    #[test]
+    fn test_trace_glob_files_excluding() {
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        std::fs::create_dir_all(temp.path().join("io")).unwrap();
+        std::fs::create_dir_all(temp.path().join("tests/auto")).unwrap();
+        std::fs::write(temp.path().join("io/qfile.h"), "").unwrap();
+        std::fs::write(temp.path().join("io/qfile.cpp"), "").unwrap();
+        std::fs::write(temp.path().join("io/README"), "").unwrap();
+        std::fs::write(temp.path().join("tests/auto/qtest.h"), "").unwrap();
+
+        assert_eq!(
+            trace_glob_files_excluding(temp.path(), OsStr::new("h"), DEFAULT_EXCLUDED_DIRS),
+            [
+                "io\t-\tdirectory",
+                "io/README\t-\tskipped: extension",
+                "io/qfile.cpp\tcpp\tskipped: extension",
+                "io/qfile.h\th\tmatch",
+                "tests\t-\tskipped: excluded directory",
+            ]
+        );
+
+        let log_path = temp.path().join("scan.log");
+        log_header_scan(temp.path().join("io"), OsStr::new("h"), &[], &log_path).unwrap();
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        assert!(log.starts_with("# Header scan of "));
+        assert!(log.ends_with("qfile.h\th\tmatch\n"));
+    }
+
+    #[test]
+    fn test_safe_canonicalize() {
+        let temp = scratch_temp_dir("qt-cargo-base-util-test").unwrap();
+        let canonical_temp = temp.path().canonicalize().unwrap();
+
+        let missing_path = temp.path().join("a/../b/./qfoo.h");
+        assert_eq!(
+            safe_canonicalize(&missing_path).unwrap(),
+            canonical_temp.join("b/qfoo.h")
+        );
+
+        std::fs::write(temp.path().join("qbar.h"), "").unwrap();
+        assert_eq!(
+            safe_canonicalize(temp.path().join("qbar.h")).unwrap(),
+            canonical_temp.join("qbar.h")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+// Qt version handling
+
+/// The Qt version the built-in source lists and feature tables are written for, used when
+/// the version of the Qt source can't be detected
+pub const DEFAULT_QT_VERSION: QtVersion = QtVersion {
+    major: 6,
+    minor: 2,
+    patch: 0,
+};
+
+/// A Qt version, e.g. 6.2.0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QtVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl QtVersion {
+    pub fn new(major: u32, minor: u32, patch: u32) -> QtVersion {
+        QtVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Returns the version as a hex string in QT_VERSION_CHECK format, e.g. "0x060200"
+    pub fn to_hex_string(&self) -> String {
+        format!("0x{:02X}{:02X}{:02X}", self.major, self.minor, self.patch)
+    }
+
+    /// Returns the QT_VERSION_MAJOR, QT_VERSION_MINOR, QT_VERSION_PATCH and QT_VERSION_STR
+    /// defines for the version, as (define, value) pairs
+    pub fn defines(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("QT_VERSION_MAJOR", self.major.to_string()),
+            ("QT_VERSION_MINOR", self.minor.to_string()),
+            ("QT_VERSION_PATCH", self.patch.to_string()),
+            ("QT_VERSION_STR", format!("\"{}\"", self)),
+        ]
+    }
+}
+
+impl fmt::Display for QtVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for QtVersion {
+    type Err = String;
+
+    /// Parses a "major.minor.patch" version string, e.g. "6.2.0"
+    fn from_str(version: &str) -> Result<QtVersion, String> {
+        let parts = version
+            .trim()
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid Qt version {:?}: {}", version, e))?;
+        match parts[..] {
+            [major, minor, patch] => Ok(QtVersion::new(major, minor, patch)),
+            _ => Err(format!("invalid Qt version {:?}", version)),
+        }
+    }
+}
+
+/// Detects the Qt version of the Qt source, from QT_REPO_MODULE_VERSION in
+/// qtbase/.cmake.conf, or from QT_VERSION_STR in qtbase/src/corelib/global/qglobal.h for
+/// sources without .cmake.conf. Returns None if the version could not be found; see
+/// DEFAULT_QT_VERSION.
+pub fn detect_qt_version<P: AsRef<Path>>(qt_source_path: P) -> Option<QtVersion> {
+    let read = |path: &str| std::fs::read_to_string(qt_source_path.as_ref().join(path)).ok();
+    let from_cmake_conf = || {
+        read("qtbase/.cmake.conf")?.lines().find_map(|line| {
+            line.trim()
+                .strip_prefix("set(QT_REPO_MODULE_VERSION")?
+                .trim()
+                .strip_suffix(')')?
+                .trim()
+                .trim_matches('"')
+                .parse()
+                .ok()
+        })
+    };
+    let from_qglobal = || {
+        read("qtbase/src/corelib/global/qglobal.h")?
+            .lines()
+            .find_map(|line| {
+                line.trim()
+                    .strip_prefix("#define QT_VERSION_STR")?
+                    .trim()
+                    .trim_matches('"')
+                    .parse()
+                    .ok()
+            })
+    };
+    from_cmake_conf().or_else(from_qglobal)
+}
+
+#[cfg(test)]
+mod qt_cargo_base_version_tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn test_detect_qt_version() {
+        assert_eq!("6.2.0".parse(), Ok(QtVersion::new(6, 2, 0)));
+        assert!("6.2".parse::<QtVersion>().is_err());
+
+        let temp = util::scratch_temp_dir("qt-cargo-base-version-test").unwrap();
+        assert_eq!(detect_qt_version(temp.path()), None);
+        std::fs::create_dir_all(temp.path().join("qtbase")).unwrap();
+        std::fs::write(
+            temp.path().join("qtbase/.cmake.conf"),
+            "set(QT_REPO_MODULE_VERSION \"6.3.1\")\nset(QT_REPO_MODULE_PRERELEASE_VERSION_SEGMENT \"alpha1\")\n",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_qt_version(temp.path()),
+            Some(QtVersion::new(6, 3, 1))
+        );
+    }
+
+    #[test]
+    fn test_detect_qt_version_from_qglobal() {
+        let temp = util::scratch_temp_dir("qt-cargo-base-version-test").unwrap();
+        let global_path = temp.path().join("qtbase/src/corelib/global");
+        std::fs::create_dir_all(&global_path).unwrap();
+        std::fs::write(
+            global_path.join("qglobal.h"),
+            "#define QT_VERSION_STR   \"5.15.2\"\n#define QT_VERSION 0x050F02\n",
+        )
+        .unwrap();
+        let version = detect_qt_version(temp.path()).unwrap();
+        assert_eq!(version, QtVersion::new(5, 15, 2));
+        assert!(version
+            .defines()
+            .contains(&("QT_VERSION_STR", "\"5.15.2\"".to_string())));
+    }
+}
@@ -4,10 +4,16 @@ extern crate cc;
 
 fn main() {
     let qt_source = qt_cargo_base::util::qt_src_path();
+    let qt_version = qt_cargo_base::version::detect_qt_version(&qt_source)
+        .unwrap_or(qt_cargo_base::version::DEFAULT_QT_VERSION);
 
     let mut builder = cc::Build::new();
     let no_path: Option<PathBuf> = None;
-    qt_cargo_base::configure_qtcore_for_linux(&mut builder, no_path, &qt_source);
+    let qt_build_paths =
+        qt_cargo_base::configure_qtcore_for_linux(&mut builder, no_path, &qt_source)
+            .unwrap_or_else(|e| panic!("{}", e));
+    // The configured builder, for the bootstrap library below
+    let bootstrap_builder = builder.clone();
 
     // Add moc files
     qt_cargo_base::add_path_prefixed_files(
@@ -15,37 +21,32 @@ fn main() {
         qt_source.join(qt_cargo_base::sources::MOC_PATH),
         qt_cargo_base::sources::MOC_SOURCES,
     );
-    builder.include(qt_source.join("qtbase/src/3rdparty/tinycbor/src/"));
-    builder.include(qt_source.join("qtbase/src/tools/shared"));
+    builder.include(qt_source.join(qt_cargo_base::sources::TINYCBOR_INCLUDE_PATH));
+    qt_cargo_base::write_tools_shared_forwarding_headers(
+        &mut builder,
+        &qt_build_paths.build_path,
+        &qt_source,
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
     builder.define("main", "hiddenmocmain"); // build.rs provides main(), hide the one in moc.cpp
 
-    // Add bootstrap library files
-    qt_cargo_base::add_path_prefixed_files(
-        &mut builder,
-        qt_source.join(qt_cargo_base::sources::BOOTSTRAP_PATH),
-        qt_cargo_base::sources::BOOTSTRAP_SOURCES,
-    );
-    qt_cargo_base::add_path_prefixed_files(
-        &mut builder,
-        qt_source.join(qt_cargo_base::sources::BOOTSTRAP_PATH),
-        qt_cargo_base::sources::BOOTSTRAP_SOURCES_UNIX,
-    );
-    builder.define("HAVE_CONFIG_H", None);
-    builder.define("QT_VERSION_MAJOR", "6");
-    builder.define("QT_VERSION_MINOR", "2");
-    builder.define("QT_VERSION_PATCH", "0");
-    builder.define("QT_VERSION_STR", "\"6.2.0\"");
-    builder.define("QT_USE_QSTRINGBUILDER", None);
-    builder.define("QT_BOOTSTRAPPED", None);
-    builder.define("QT_NO_CAST_FROM_ASCII", None);
-    builder.define("QT_NO_CAST_TO_ASCII", None);
-    builder.define("QT_NO_FOREACH", None);
+    // The bootstrap library is compiled separately, see build_bootstrap_archive
+    qt_cargo_base::configure_bootstrap(&mut builder, qt_version);
 
     builder.compile("qtcore_host_tools");
 
+    // After the host tools, which link against it
+    qt_cargo_base::build_bootstrap_archive_with_builder(
+        bootstrap_builder,
+        &qt_source,
+        &qt_build_paths,
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
+
     // Note: This goes last! We are providing the dependencies for
-    // qtcore_host_tools (built above), and the "-l pcre2-16" must
-    // appear after the "-l static=qtcore_host_tools" on the rustc
-    // compiler line.
+    // qtcore_host_tools and qt_bootstrap (built above), and the
+    // "-l pcre2-16" must appear after the "-l static=qt_bootstrap" on the
+    // rustc compiler line. See qt_cargo_base::modules::link_order for the
+    // complete order.
     system_deps::Config::new().probe().unwrap();
 }